@@ -0,0 +1,13 @@
+CREATE TABLE play_history (
+    id INTEGER PRIMARY KEY NOT NULL,
+    track_id INTEGER NOT NULL,
+    -- Seconds since the unix epoch
+    started_at INTEGER NOT NULL,
+    listened_ms INTEGER NOT NULL,
+    duration_ms INTEGER,
+    completed INTEGER NOT NULL,
+    skipped INTEGER NOT NULL
+);
+
+CREATE INDEX play_history_track_id ON play_history (track_id);
+CREATE INDEX play_history_started_at ON play_history (started_at);
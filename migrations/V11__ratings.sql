@@ -0,0 +1,3 @@
+-- 0 means the track isn't rated, otherwise 1 to 5 stars
+ALTER TABLE tracks ADD COLUMN rating INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tracks ADD COLUMN loved INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,15 @@
+-- Plays waiting to be submitted to a scrobbling service. The song details are copied in, so the
+-- log stays intact when the library changes.
+CREATE TABLE scrobbles (
+    id INTEGER PRIMARY KEY NOT NULL,
+    artist TEXT NOT NULL,
+    title TEXT NOT NULL,
+    album TEXT,
+    track_num INTEGER,
+    duration_ms INTEGER,
+    -- Seconds since the unix epoch
+    listened_at INTEGER NOT NULL,
+    submitted INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX scrobbles_submitted ON scrobbles (submitted);
@@ -0,0 +1,13 @@
+CREATE TABLE eq_presets (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT UNIQUE NOT NULL,
+    gains TEXT NOT NULL
+);
+
+CREATE TABLE eq_auto_presets (
+    id INTEGER PRIMARY KEY NOT NULL,
+    kind TEXT NOT NULL,
+    key TEXT NOT NULL,
+    preset TEXT NOT NULL,
+    UNIQUE (kind, key)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE playback_speeds (
+    id INTEGER PRIMARY KEY NOT NULL,
+    kind TEXT NOT NULL,
+    key TEXT NOT NULL,
+    rate REAL NOT NULL,
+    UNIQUE (kind, key)
+);
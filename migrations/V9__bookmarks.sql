@@ -0,0 +1,8 @@
+CREATE TABLE bookmarks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    track_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    position_ms INTEGER NOT NULL
+);
+
+CREATE INDEX bookmarks_track_id ON bookmarks (track_id);
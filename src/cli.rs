@@ -0,0 +1,811 @@
+//! The subcommands of musicom, for managing the library and the player from a shell or a script.
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::control::{self, Command};
+use crate::library::db::{check_integrity, get_library_db, init_library_db};
+use crate::library::loudness::{analyse_tracks, AnalysisMode};
+use crate::library::scrobble::{write_scrobbler_log, Scrobble};
+use crate::library::{self, Track, TrackField, TrackedPath};
+use crate::player::{expand_paths, PlayerHdl, QueueItem};
+use crate::scrobbler::{self, ScrobblerConfig};
+use crate::{config, daemon, instance, mpd, ui};
+
+const USAGE: &str = "\
+Usage: musicom [--config FILE] [--new-instance] [--enqueue] [PATH...]
+       musicom [--config FILE] COMMAND
+
+Without a command musicom starts its UI, and plays the files, directories and playlists in the
+paths. If musicom is already running, the paths are played there instead.
+    --config FILE   read the config from FILE instead of config.toml in the config directory
+    --new-instance  start another musicom even if one is already running
+    --enqueue       add the paths to the end of the queue of the running musicom
+
+Commands:
+    scan            look for new songs in the tracked paths
+    library         add, remove or list the tracked paths
+    search          find songs in the library
+    export          list every song in the library
+    db              check the library database
+    play            play files or directories in the running musicom, or start one
+    analyse         measure the loudness of the songs in the library
+    scrobble        manage the log of songs that were listened to
+    mpd             turn the MPD server on or off
+    ctl             control the running musicom
+    attach          a remote control for a musicom that runs with --daemon
+    --daemon        play without the UI
+
+Run `musicom COMMAND --help` for the options of a command.";
+
+/// Split `--json` off the arguments, the commands that take it print JSON instead of text.
+fn take_json_flag(args: &[String]) -> (bool, Vec<&str>) {
+    let json = args.iter().any(|arg| arg == "--json");
+    let args = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--json")
+        .collect();
+    (json, args)
+}
+
+fn track_to_json(track: &Track) -> Value {
+    json!({
+        "id": track.id,
+        "path": track.path.to_string_lossy(),
+        "title": track.title,
+        "artist": track.artist,
+        "album": track.album,
+        "track_num": track.track_num,
+        "rating": track.rating,
+        "loved": track.loved,
+        "track_gain": track.track_gain,
+        "track_peak": track.track_peak,
+        "album_gain": track.album_gain,
+        "album_peak": track.album_peak,
+    })
+}
+
+/// One song per line, with tab separated id, artist, album, track number, title and path
+fn print_tracks(tracks: &[Track], json: bool) {
+    if json {
+        println!(
+            "{}",
+            Value::Array(tracks.iter().map(track_to_json).collect())
+        );
+        return;
+    }
+    for track in tracks {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            track.id.unwrap_or_default(),
+            track.artist.as_deref().unwrap_or_default(),
+            track.album.as_deref().unwrap_or_default(),
+            track
+                .track_num
+                .map(|num| num.to_string())
+                .unwrap_or_default(),
+            track.title.as_deref().unwrap_or_default(),
+            track.path.display()
+        );
+    }
+}
+
+const SCAN_USAGE: &str = "\
+Usage: musicom scan [--full] [--json]
+
+Look for new songs in the tracked paths.
+    --full  also re-read the tags of the songs in the library, and remove the songs whose files
+            are gone";
+
+/// `musicom scan`, refreshes the library from the tracked paths.
+fn run_scan(args: &[String]) -> i32 {
+    let (json, args) = take_json_flag(args);
+    let full = match args.as_slice() {
+        [] => false,
+        ["--full"] => true,
+        _ => {
+            eprintln!("{}", SCAN_USAGE);
+            return 2;
+        }
+    };
+
+    let summary = library::scan_library(full);
+    if json {
+        println!(
+            "{}",
+            json!({
+                "added": summary.added,
+                "updated": summary.updated,
+                "removed": summary.removed,
+            })
+        );
+    } else {
+        println!(
+            "Added {} songs, updated {}, removed {}",
+            summary.added, summary.updated, summary.removed
+        );
+    }
+    0
+}
+
+const LIBRARY_USAGE: &str = "\
+Usage: musicom library add-path DIRECTORY [--json]
+       musicom library remove-path DIRECTORY [--json]
+       musicom library list-paths [--json]
+
+Manage the directories the library is made of.
+    add-path     track a directory, `musicom scan` adds its songs
+    remove-path  stop tracking a directory, `musicom scan --full` removes its songs
+    list-paths   list the tracked directories";
+
+fn tracked_path_to_json(tracked_path: &TrackedPath) -> Value {
+    json!({"id": tracked_path.id, "path": tracked_path.path.to_string_lossy()})
+}
+
+/// `musicom library`, manages the tracked paths.
+fn run_library(args: &[String]) -> i32 {
+    let (json, args) = take_json_flag(args);
+    let conn = get_library_db().unwrap();
+
+    match args.as_slice() {
+        ["add-path", path] => {
+            let path = match fs::canonicalize(path) {
+                Ok(path) if path.is_dir() => path,
+                Ok(path) => {
+                    eprintln!("{} is not a directory", path.display());
+                    return 1;
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    return 1;
+                }
+            };
+            let tracked_path = TrackedPath::iter_with_conn(&conn)
+                .find(|tracked_path| tracked_path.path == path)
+                .unwrap_or_else(|| {
+                    let mut tracked_path = TrackedPath { id: None, path };
+                    tracked_path.save_with_conn(&conn);
+                    tracked_path
+                });
+            if json {
+                println!("{}", tracked_path_to_json(&tracked_path));
+            } else {
+                println!("Tracking {}", tracked_path.path.display());
+            }
+            0
+        }
+        ["remove-path", path] => {
+            // The directory may be gone already
+            let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+            let tracked_path = match TrackedPath::iter_with_conn(&conn)
+                .find(|tracked_path| tracked_path.path == path)
+            {
+                Some(tracked_path) => tracked_path,
+                None => {
+                    eprintln!("{} is not tracked", path.display());
+                    return 1;
+                }
+            };
+            tracked_path.delete_with_conn(&conn);
+            if json {
+                println!("{}", tracked_path_to_json(&tracked_path));
+            } else {
+                println!("Stopped tracking {}", tracked_path.path.display());
+            }
+            0
+        }
+        ["list-paths"] => {
+            let tracked_paths = TrackedPath::iter_with_conn(&conn).collect::<Vec<_>>();
+            if json {
+                println!(
+                    "{}",
+                    Value::Array(tracked_paths.iter().map(tracked_path_to_json).collect())
+                );
+            } else {
+                for tracked_path in tracked_paths {
+                    println!("{}", tracked_path.path.display());
+                }
+            }
+            0
+        }
+        _ => {
+            eprintln!("{}", LIBRARY_USAGE);
+            2
+        }
+    }
+}
+
+const SEARCH_USAGE: &str = "\
+Usage: musicom search QUERY... [--json]
+
+List the songs whose title, artist, album or path contain the query, ignoring case.";
+
+/// `musicom search`, finds songs in the library.
+fn run_search(args: &[String]) -> i32 {
+    let (json, args) = take_json_flag(args);
+    if args.is_empty() {
+        eprintln!("{}", SEARCH_USAGE);
+        return 2;
+    }
+
+    let tracks = Track::search(&[(TrackField::Any, args.join(" "))], false);
+    print_tracks(&tracks, json);
+    0
+}
+
+const EXPORT_USAGE: &str = "\
+Usage: musicom export [--json]
+
+List every song in the library, with its tags, rating and ReplayGain values in the JSON output.";
+
+/// `musicom export`, dumps the library.
+fn run_export(args: &[String]) -> i32 {
+    let (json, args) = take_json_flag(args);
+    if !args.is_empty() {
+        eprintln!("{}", EXPORT_USAGE);
+        return 2;
+    }
+
+    print_tracks(&Track::search(&[], false), json);
+    0
+}
+
+const DB_USAGE: &str = "\
+Usage: musicom db check [--json]
+
+Check the library database for corruption, songs whose files are gone and tracked directories
+that don't exist anymore.";
+
+/// `musicom db`, looks for problems in the library.
+fn run_db(args: &[String]) -> i32 {
+    let (json, args) = take_json_flag(args);
+    if args.as_slice() != ["check"] {
+        eprintln!("{}", DB_USAGE);
+        return 2;
+    }
+
+    let conn = get_library_db().unwrap();
+    let problems = check_integrity(&conn).unwrap_or_else(|e| vec![e.to_string()]);
+    let missing_files = Track::iter_with_conn(&conn)
+        .map(|track| track.path)
+        .filter(|path| !path.exists())
+        .collect::<Vec<_>>();
+    let missing_paths = TrackedPath::iter_with_conn(&conn)
+        .map(|tracked_path| tracked_path.path)
+        .filter(|path| !path.is_dir())
+        .collect::<Vec<_>>();
+    let ok = problems.is_empty() && missing_files.is_empty() && missing_paths.is_empty();
+
+    if json {
+        let paths = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>()
+        };
+        println!(
+            "{}",
+            json!({
+                "ok": ok,
+                "problems": problems,
+                "missing_files": paths(&missing_files),
+                "missing_tracked_paths": paths(&missing_paths),
+            })
+        );
+    } else {
+        for problem in problems.iter() {
+            println!("Database: {}", problem);
+        }
+        for path in missing_files.iter() {
+            println!("Missing file: {}", path.display());
+        }
+        for path in missing_paths.iter() {
+            println!("Missing tracked path: {}", path.display());
+        }
+        if ok {
+            println!("No problems found");
+        }
+    }
+    if ok {
+        0
+    } else {
+        1
+    }
+}
+
+const PLAY_USAGE: &str = "\
+Usage: musicom play [--enqueue] PATH... [--json]
+
+Replace the queue of the running musicom with the songs in the files, directories and playlists,
+and play them. Directories are searched recursively, in sorted order. When musicom isn't running
+yet, it starts and plays them.
+    --enqueue  add the songs to the end of the queue instead";
+
+/// Hand the songs in the paths to the running musicom, through its control socket. They replace
+/// its queue and play, or are added to the end of the queue.
+fn forward_paths(paths: &[&str], enqueue: bool, json: bool) -> i32 {
+    let paths = match paths
+        .iter()
+        .map(|path| fs::canonicalize(path).map_err(|e| format!("{}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let songs = expand_paths(&paths);
+    if songs.is_empty() {
+        eprintln!("No songs found");
+        return 1;
+    }
+
+    let enqueue_commands = songs.iter().cloned().map(Command::EnqueuePath);
+    let commands: Vec<Command> = if enqueue {
+        enqueue_commands.collect()
+    } else {
+        iter::once(Command::Clear)
+            .chain(enqueue_commands)
+            .chain(iter::once(Command::PlayAt(0)))
+            .collect()
+    };
+    let socket_path = control::get_socket_path();
+    for command in commands.iter() {
+        if let Err(e) = control::send_command(&socket_path, command) {
+            eprintln!("{}", e);
+            return 1;
+        }
+    }
+
+    if json {
+        println!("{}", json!({ "queued": songs.len() }));
+    } else if enqueue {
+        println!("Added {} songs to the queue", songs.len());
+    } else {
+        println!("Playing {} songs", songs.len());
+    }
+    0
+}
+
+/// `musicom play`, plays files in the running player. Returns `None` when there is none, then
+/// this musicom starts the UI and plays them itself.
+fn run_play(args: &[String]) -> Option<i32> {
+    let (json, args) = take_json_flag(args);
+    let enqueue = args.contains(&"--enqueue");
+    let paths = args
+        .into_iter()
+        .filter(|arg| *arg != "--enqueue")
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        eprintln!("{}", PLAY_USAGE);
+        return Some(2);
+    }
+    if !is_running_elsewhere() {
+        return None;
+    }
+
+    Some(forward_paths(&paths, enqueue, json))
+}
+
+const ANALYSE_USAGE: &str = "\
+Usage: musicom analyse [--album] [--write-tags]
+
+Measure the loudness of every song in the library and store the ReplayGain values.
+    --album       also calculate the album gain
+    --write-tags  write the gain tags back into the files";
+
+/// `musicom analyse`, runs the loudness analysis over the whole library without the UI.
+fn run_analyse(args: &[String]) -> i32 {
+    let mut mode = AnalysisMode::Track;
+    let mut write_tags = false;
+    for arg in args {
+        match arg.as_str() {
+            "--album" => mode = AnalysisMode::Album,
+            "--write-tags" => write_tags = true,
+            _ => {
+                eprintln!("{}", ANALYSE_USAGE);
+                return 2;
+            }
+        }
+    }
+
+    let conn = get_library_db().unwrap();
+    let mut tracks = Track::iter_with_conn(&conn).collect::<Vec<_>>();
+    let failed = analyse_tracks(&conn, &mut tracks, mode, write_tags, |progress| {
+        if let Some(track) = progress.current.as_ref() {
+            println!(
+                "[{}/{}] {}",
+                progress.done,
+                progress.total,
+                track.path.display()
+            );
+        }
+    });
+
+    println!(
+        "Analysed {} songs, {} failed",
+        tracks.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+const SCROBBLE_USAGE: &str = "\
+Usage: musicom scrobble config [--url URL] [--token TOKEN]
+       musicom scrobble submit
+       musicom scrobble export FILE
+
+Manage the log of songs that were listened to.
+    config  set the ListenBrainz compatible server and the user token to submit listens with
+    submit  submit all listens that haven't been submitted yet
+    export  write the whole log to FILE in the .scrobbler.log format";
+
+/// `musicom scrobble`, submits or exports the scrobble log without the UI.
+fn run_scrobble(args: &[String]) -> i32 {
+    match args.first().map(String::as_str) {
+        Some("config") => {
+            let mut config = ScrobblerConfig::load().unwrap_or(ScrobblerConfig {
+                api_url: scrobbler::DEFAULT_API_URL.to_string(),
+                token: String::new(),
+            });
+            let mut options = args[1..].iter();
+            while let Some(option) = options.next() {
+                match (option.as_str(), options.next()) {
+                    ("--url", Some(url)) => config.api_url = url.clone(),
+                    ("--token", Some(token)) => config.token = token.clone(),
+                    _ => {
+                        eprintln!("{}", SCROBBLE_USAGE);
+                        return 2;
+                    }
+                }
+            }
+            config.save();
+            println!("Submitting listens to {}", config.api_url);
+            0
+        }
+        Some("submit") if args.len() == 1 => {
+            let config = match ScrobblerConfig::load() {
+                Some(config) => config,
+                None => {
+                    eprintln!("No token set, use `musicom scrobble config --token TOKEN` first");
+                    return 1;
+                }
+            };
+            match scrobbler::submit_pending(&config) {
+                Ok(submitted) => {
+                    println!("Submitted {} listens", submitted);
+                    0
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            }
+        }
+        Some("export") if args.len() == 2 => {
+            let result = File::create(&args[1])
+                .and_then(|file| write_scrobbler_log(&mut BufWriter::new(file), &Scrobble::all()));
+            match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("Could not write {}: {}", args[1], e);
+                    1
+                }
+            }
+        }
+        _ => {
+            eprintln!("{}", SCROBBLE_USAGE);
+            2
+        }
+    }
+}
+
+const MPD_USAGE: &str = "\
+Usage: musicom mpd enable [ADDRESS]
+       musicom mpd disable
+
+Serve the MPD protocol, so MPD clients can control musicom. The server listens on ADDRESS,
+127.0.0.1:6600 by default, the next time musicom starts.";
+
+/// `musicom mpd`, turns the MPD server on or off.
+fn run_mpd(args: &[String]) -> i32 {
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["enable"] | ["enable", _] => {
+            let address = args.get(1).map_or(mpd::DEFAULT_ADDRESS, String::as_str);
+            mpd::set_address(Some(address));
+            println!("Serving MPD on {}", address);
+            0
+        }
+        ["disable"] => {
+            mpd::set_address(None);
+            0
+        }
+        _ => {
+            eprintln!("{}", MPD_USAGE);
+            2
+        }
+    }
+}
+
+const CTL_USAGE: &str = "\
+Usage: musicom ctl COMMAND
+
+Control the musicom that is already running.
+    play | pause | toggle   resume, pause or toggle playback
+    play POSITION           play the song at a position in the queue
+    next | prev             skip to the next or previous song
+    seek SECONDS            jump to a position, +SECONDS or -SECONDS seek from the current one
+    volume PERCENT          set the volume, +PERCENT or -PERCENT change it
+    enqueue PATH            add a file to the end of the queue
+    enqueue --track ID      add a song in the library to the end of the queue
+    clear                   empty the queue
+    sleep DURATION | off    stop playback after a while, like 30m or 1h30m, or cancel that
+    status                  show what is playing
+    queue                   list the songs in the queue
+    reload                  read the config file again
+    quit                    stop a musicom that runs with --daemon";
+
+/// `musicom ctl`, sends a command to the control socket of the running player.
+fn run_ctl(args: &[String]) -> i32 {
+    let command = match Command::from_args(args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, CTL_USAGE);
+            return 2;
+        }
+    };
+
+    match control::send_command(&control::get_socket_path(), &command) {
+        Ok(reply) => {
+            if let Some(status) = reply.get("status") {
+                println!("{}", control::format_status(status));
+            }
+            if let Some(songs) = reply["queue"].as_array() {
+                let position = reply["queue_position"].as_u64();
+                for song in songs {
+                    let index = song["index"].as_u64();
+                    let marker = if index.is_some() && index == position {
+                        '>'
+                    } else {
+                        ' '
+                    };
+                    println!(
+                        "{} {:>4} {}",
+                        marker,
+                        index.unwrap_or_default(),
+                        control::format_song(song)
+                    );
+                }
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+const ATTACH_USAGE: &str = "\
+Usage: musicom attach
+
+A remote control for the musicom that runs with --daemon. It shows what is playing and the queue,
+plays the song picked in the queue and sends the playback keys. The library can't be browsed from
+here, add songs with `musicom play`, `musicom ctl enqueue` or an MPD client.";
+
+/// `musicom attach`, the remote control for a musicom that runs with `--daemon`.
+fn run_attach(args: &[String]) -> i32 {
+    if !args.is_empty() {
+        eprintln!("{}", ATTACH_USAGE);
+        return 2;
+    }
+    let socket_path = control::get_socket_path();
+    if let Err(e) = control::send_command(&socket_path, &Command::Status) {
+        eprintln!("{}", e);
+        return 1;
+    }
+    // Only the keys come from the config, they shouldn't keep the daemon from being reached
+    if let Err(e) = config::init() {
+        eprintln!("{}, using the default keys", e);
+    }
+
+    ui::run_attached(socket_path).unwrap();
+    0
+}
+
+fn usage(command: &str) -> Option<&'static str> {
+    let usage = match command {
+        "scan" => SCAN_USAGE,
+        "library" => LIBRARY_USAGE,
+        "search" => SEARCH_USAGE,
+        "export" => EXPORT_USAGE,
+        "db" => DB_USAGE,
+        "play" => PLAY_USAGE,
+        "analyse" => ANALYSE_USAGE,
+        "scrobble" => SCROBBLE_USAGE,
+        "mpd" => MPD_USAGE,
+        "ctl" => CTL_USAGE,
+        "attach" => ATTACH_USAGE,
+        _ => return None,
+    };
+    Some(usage)
+}
+
+/// Set up the config, GStreamer and the library, for the UI and the commands that play or analyse
+/// songs. Fails if the config is invalid.
+pub fn init() -> Result<(), String> {
+    config::init()?;
+    gst::init().unwrap();
+    init_library_db();
+    if config::get().library.scan_on_startup {
+        library::fast_refresh_library();
+    }
+    Ok(())
+}
+
+/// Take `--config PATH` out of the arguments, the config is read from there instead of the config
+/// directory.
+pub fn take_config_arg(args: &mut Vec<String>) -> Result<(), String> {
+    let path = match args.iter().position(|arg| arg == "--config") {
+        Some(idx) if idx + 1 < args.len() => {
+            let path = args.remove(idx + 1);
+            args.remove(idx);
+            Some(PathBuf::from(path))
+        }
+        Some(_) => return Err(format!("--config needs a path\n\n{}", USAGE)),
+        None => None,
+    };
+    config::set_path(path);
+    Ok(())
+}
+
+/// Replace the queue with the songs in the files, directories and playlists given on the command
+/// line, and start playing. Songs that are in the library are queued as tracks.
+pub fn play_paths(args: &[String]) {
+    // `musicom play PATH...` ends up here too when no other musicom was running
+    let args = match args.first().map(String::as_str) {
+        Some("play") => &args[1..],
+        _ => args,
+    };
+    let paths = args
+        .iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .filter_map(|arg| fs::canonicalize(arg).ok())
+        .collect::<Vec<_>>();
+    let conn = get_library_db().unwrap();
+    let items = expand_paths(&paths)
+        .into_iter()
+        .map(|path| match Track::from_path_with_conn(&path, &conn) {
+            Some(track) => QueueItem::new_from_track(track),
+            None => QueueItem::new_from_path(path),
+        })
+        .collect::<Vec<_>>();
+    if items.is_empty() {
+        return;
+    }
+
+    let player = PlayerHdl::new();
+    let mut queue = player.queue_mut();
+    queue.replace_queue(items);
+    queue.play_queue();
+}
+
+/// Run a command that only needs the library database
+fn with_library(run: fn(&[String]) -> i32, args: &[String]) -> i32 {
+    if let Err(e) = config::init() {
+        eprintln!("{}", e);
+        return 2;
+    }
+    init_library_db();
+    run(args)
+}
+
+fn with_player(run: fn(&[String]) -> i32, args: &[String]) -> i32 {
+    if let Err(e) = init() {
+        eprintln!("{}", e);
+        return 2;
+    }
+    run(args)
+}
+
+/// Whether another musicom holds the instance lock. If the lock can't be checked, this one runs
+/// anyway.
+fn is_running_elsewhere() -> bool {
+    match instance::acquire() {
+        Ok(acquired) => !acquired,
+        Err(e) => {
+            eprintln!(
+                "Could not check for a running musicom at {}: {}",
+                instance::get_lock_path().display(),
+                e
+            );
+            false
+        }
+    }
+}
+
+/// `musicom [PATH...]`, returns `None` when this musicom should start the UI. When another one
+/// is already running the songs are handed to it instead, unless --new-instance is given.
+fn run_ui(args: &[String]) -> Option<i32> {
+    let mut new_instance = false;
+    let mut enqueue = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--new-instance" => new_instance = true,
+            "--enqueue" => enqueue = true,
+            path if Path::new(path).exists() => paths.push(path),
+            _ => {
+                eprintln!("Unknown command or path {}\n\n{}", arg, USAGE);
+                return Some(2);
+            }
+        }
+    }
+
+    if new_instance || !is_running_elsewhere() {
+        None
+    } else if paths.is_empty() {
+        eprintln!(
+            "musicom is already running, control it with `musicom ctl` or `musicom attach`, or \
+             start another one with --new-instance"
+        );
+        Some(1)
+    } else {
+        Some(forward_paths(&paths, enqueue, false))
+    }
+}
+
+/// Run the command in the arguments and return its exit code, or `None` when the UI should start
+/// and play the paths in the arguments.
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = args.first().map_or("", String::as_str);
+    let command_args = args.get(1..).unwrap_or_default();
+
+    if command_args.iter().any(|arg| arg == "--help") {
+        if let Some(usage) = usage(command) {
+            println!("{}", usage);
+            return Some(0);
+        }
+    }
+
+    let exit_code = match command {
+        // The clients don't need the player or the library
+        "ctl" => run_ctl(command_args),
+        "attach" => run_attach(command_args),
+        "play" => return run_play(command_args),
+        "scan" => with_library(run_scan, command_args),
+        "library" => with_library(run_library, command_args),
+        "search" => with_library(run_search, command_args),
+        "export" => with_library(run_export, command_args),
+        "db" => with_library(run_db, command_args),
+        "scrobble" => with_library(run_scrobble, command_args),
+        "mpd" => with_library(run_mpd, command_args),
+        "analyse" => with_player(run_analyse, command_args),
+        "--daemon" if is_running_elsewhere() => {
+            eprintln!("musicom is already running");
+            1
+        }
+        "--daemon" => with_player(|_| daemon::run(), command_args),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            0
+        }
+        _ => return run_ui(args),
+    };
+    Some(exit_code)
+}
@@ -0,0 +1,267 @@
+//! The config file, `config.toml` in the config directory unless `--config` points somewhere
+//! else. Every setting is optional:
+//!
+//! ```toml
+//! [library]
+//! scan_on_startup = true
+//!
+//! [file_browser]
+//! start_dir = "~/Music"
+//! show_hidden = false
+//!
+//! [player]
+//! retry_limit = 1  # how often a song that fails to play is tried again
+//! skip_threshold_percent = 50  # songs cut off before this much of them played are skipped
+//!
+//! [ui]
+//! start_view = "albums"  # all_songs, albums, favorites, file_browser or stats
+//! title_width = 50
+//! queue_width = 50
+//!
+//! [keys]
+//! preset = "default"  # or vim
+//! ```
+//!
+//! The `[keys]` section also takes the keys of actions, see the keymap module.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::keymap::{self, Keymap, KeysConfig};
+use crate::util::{expand_home, Notifier, NotifierCb};
+
+const CONFIG_NAME: &str = "config.toml";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub library: LibraryConfig,
+    pub file_browser: FileBrowserConfig,
+    pub player: PlayerConfig,
+    pub ui: UiConfig,
+    pub keys: KeysConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryConfig {
+    /// Look for new songs in the tracked paths when musicom starts
+    pub scan_on_startup: bool,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            scan_on_startup: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileBrowserConfig {
+    /// The home directory if it isn't set
+    pub start_dir: Option<PathBuf>,
+    pub show_hidden: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerConfig {
+    /// How often a song that fails to play is tried again before it is skipped
+    pub retry_limit: usize,
+    /// A song that is cut off before this share of it was played counts as skipped
+    pub skip_threshold_percent: f64,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        Self {
+            retry_limit: 1,
+            skip_threshold_percent: 50.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StartView {
+    AllSongs,
+    Albums,
+    Favorites,
+    FileBrowser,
+    Stats,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+    /// The view shown next to the queue when musicom starts
+    pub start_view: StartView,
+    /// Song titles in the library are cut off after this many characters
+    pub title_width: usize,
+    /// The minimum width of the queue sidebar
+    pub queue_width: usize,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            start_view: StartView::Albums,
+            title_width: 50,
+            queue_width: 50,
+        }
+    }
+}
+
+lazy_static! {
+    static ref CONFIG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+    static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
+    static ref NOTIFIER: Mutex<Notifier> = Mutex::new(Notifier::new());
+}
+
+fn default_path() -> PathBuf {
+    crate::util::get_project_dirs()
+        .config_dir()
+        .join(CONFIG_NAME)
+}
+
+impl Config {
+    /// Parse and check the config, the errors say which setting is wrong.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut config: Config = toml::from_str(contents).map_err(|e| e.to_string())?;
+
+        if let Some(start_dir) = config.file_browser.start_dir.as_mut() {
+            *start_dir = expand_home(start_dir);
+            if !start_dir.is_dir() {
+                return Err(format!(
+                    "file_browser.start_dir: {} is not a directory",
+                    start_dir.display()
+                ));
+            }
+        }
+        let skip_threshold = config.player.skip_threshold_percent;
+        if !(0.0..=100.0).contains(&skip_threshold) {
+            return Err("player.skip_threshold_percent has to be between 0 and 100".to_string());
+        }
+        if config.ui.title_width == 0 {
+            return Err("ui.title_width has to be at least 1".to_string());
+        }
+        if config.ui.queue_width == 0 {
+            return Err("ui.queue_width has to be at least 1".to_string());
+        }
+        Keymap::new(&config.keys)?;
+
+        Ok(config)
+    }
+
+    /// Read the config file, a missing file is only an error if `required` is set.
+    fn load(path: &Path, required: bool) -> Result<Self, String> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Config::default())
+            }
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+        Self::parse(&contents).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+}
+
+/// Read the config from `path` instead of the config directory from now on. Only a config file
+/// given this way has to exist.
+pub fn set_path(path: Option<PathBuf>) {
+    *CONFIG_PATH.lock().unwrap() = path;
+}
+
+fn load_config() -> Result<Config, String> {
+    let path = CONFIG_PATH.lock().unwrap().clone();
+    let required = path.is_some();
+    Config::load(&path.unwrap_or_else(default_path), required)
+}
+
+/// Load the config file. Only the commands that use the config do this, so an invalid config
+/// doesn't get in the way of `musicom ctl`.
+pub fn init() -> Result<(), String> {
+    set(load_config()?);
+    Ok(())
+}
+
+fn set(config: Config) {
+    // The keys were checked when the config was parsed
+    keymap::set_keymap(Keymap::new(&config.keys).unwrap_or_default());
+    *CONFIG.write().unwrap() = config;
+}
+
+/// Read the config file again. If it is invalid the current config stays.
+pub fn reload() -> Result<(), String> {
+    set(load_config()?);
+    NOTIFIER.lock().unwrap().notify();
+    Ok(())
+}
+
+pub fn get() -> Config {
+    CONFIG.read().unwrap().clone()
+}
+
+/// Called after the config was reloaded
+pub fn register_reload_cb(cb: NotifierCb) {
+    NOTIFIER.lock().unwrap().register(cb);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keymap::KeyPreset;
+
+    #[test]
+    fn parse_config() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+
+        let config = Config::parse(
+            "[library]\n\
+             scan_on_startup = false\n\
+             [file_browser]\n\
+             start_dir = \"/\"\n\
+             show_hidden = true\n\
+             [player]\n\
+             retry_limit = 3\n\
+             skip_threshold_percent = 25\n\
+             [ui]\n\
+             start_view = \"file_browser\"\n\
+             title_width = 30\n\
+             [keys]\n\
+             preset = \"vim\"\n\
+             toggle_play = [\"Space\", \"C-p\"]\n",
+        )
+        .unwrap();
+        assert!(!config.library.scan_on_startup);
+        assert_eq!(config.file_browser.start_dir, Some(PathBuf::from("/")));
+        assert!(config.file_browser.show_hidden);
+        assert_eq!(config.player.retry_limit, 3);
+        assert_eq!(config.player.skip_threshold_percent, 25.0);
+        assert_eq!(config.ui.start_view, StartView::FileBrowser);
+        assert_eq!(config.ui.title_width, 30);
+        assert_eq!(config.ui.queue_width, 50);
+        assert_eq!(config.keys.preset, KeyPreset::Vim);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let error = |contents| Config::parse(contents).unwrap_err();
+
+        assert!(error("[ui]\nqueue_wdith = 40").contains("queue_wdith"));
+        assert!(error("[ui]\nstart_view = \"playlists\"").contains("playlists"));
+        assert!(error("[ui]\ntitle_width = \"wide\"").contains("title_width"));
+        assert!(error("[ui]\ntitle_width = 0").contains("title_width"));
+        assert!(error("[player]\nretry_limit = -1").contains("retry_limit"));
+        assert!(error("[player]\nskip_threshold_percent = 120").contains("skip_threshold"));
+        assert!(error("[file_browser]\nstart_dir = \"/BLARG_I_DONT_EXIST\"").contains("start_dir"));
+        assert!(error("[keys]\ntoggle_play = \"q\"").contains("toggle_queue"));
+    }
+}
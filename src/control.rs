@@ -0,0 +1,652 @@
+//! Remote control over a Unix socket, for scripts and window manager key bindings.
+//!
+//! Every request is one line of JSON like `{"command": "seek", "offset": -10}` and gets one line
+//! of JSON back, `{"ok": true}` or `{"ok": false, "error": "..."}`. The `status` and `queue`
+//! commands add their results to the reply. `musicom ctl` and `musicom attach` are the client side
+//! of this.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use chrono::Duration;
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::library::Track;
+use crate::player::{PlaybackState, PlayerHdl, QueueItem, SleepTimer};
+
+const SOCKET_NAME: &str = "control.sock";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SeekTarget {
+    Absolute(Duration),
+    Relative(Duration),
+}
+
+/// Volumes from 0.0 to 1.0
+#[derive(Clone, Debug, PartialEq)]
+pub enum VolumeTarget {
+    Absolute(f64),
+    Relative(f64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Play,
+    /// Play the song at an index of the queue
+    PlayAt(usize),
+    Pause,
+    Toggle,
+    Next,
+    Prev,
+    Seek(SeekTarget),
+    Volume(VolumeTarget),
+    EnqueuePath(PathBuf),
+    EnqueueTrack(i32),
+    Clear,
+    /// `None` cancels the sleep timer
+    Sleep(Option<Duration>),
+    Status,
+    Queue,
+    /// Read the config file again
+    Reload,
+    /// Stop the daemon, the UI can't be quit this way
+    Quit,
+}
+
+fn seconds(value: &Value) -> Option<Duration> {
+    value
+        .as_f64()
+        .map(|secs| Duration::milliseconds((secs * 1000.0) as i64))
+}
+
+fn as_seconds(duration: Duration) -> f64 {
+    duration.num_milliseconds() as f64 / 1000.0
+}
+
+/// A duration like `30m`, `1h30m` or `90s`, minutes without a unit
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    if let Ok(minutes) = text.parse::<u32>() {
+        return Some(Duration::minutes(i64::from(minutes)));
+    }
+
+    let mut duration = Duration::zero();
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value = i64::from(number.parse::<u32>().ok()?);
+        number.clear();
+        duration = duration
+            + match c {
+                'h' => Duration::hours(value),
+                'm' => Duration::minutes(value),
+                's' => Duration::seconds(value),
+                _ => return None,
+            };
+    }
+    if number.is_empty() && duration > Duration::zero() {
+        Some(duration)
+    } else {
+        None
+    }
+}
+
+impl Command {
+    pub fn from_json(request: &Value) -> Result<Self, String> {
+        let name = request["command"]
+            .as_str()
+            .ok_or_else(|| "missing command".to_string())?;
+        let command = match name {
+            "play" => match request["position"].as_u64() {
+                Some(position) => Self::PlayAt(position as usize),
+                None => Self::Play,
+            },
+            "pause" => Self::Pause,
+            "toggle" => Self::Toggle,
+            "next" => Self::Next,
+            "prev" => Self::Prev,
+            "seek" => match (seconds(&request["position"]), seconds(&request["offset"])) {
+                (Some(position), _) => Self::Seek(SeekTarget::Absolute(position)),
+                (None, Some(offset)) => Self::Seek(SeekTarget::Relative(offset)),
+                _ => return Err("seek needs a position or an offset".to_string()),
+            },
+            "volume" => match (request["volume"].as_f64(), request["change"].as_f64()) {
+                (Some(volume), _) => Self::Volume(VolumeTarget::Absolute(volume)),
+                (None, Some(change)) => Self::Volume(VolumeTarget::Relative(change)),
+                _ => return Err("volume needs a volume or a change".to_string()),
+            },
+            "enqueue" => match (request["path"].as_str(), request["track"].as_i64()) {
+                (Some(path), _) => Self::EnqueuePath(path.into()),
+                (None, Some(track)) => Self::EnqueueTrack(track as i32),
+                _ => return Err("enqueue needs a path or a track".to_string()),
+            },
+            "clear" => Self::Clear,
+            // Without a duration the sleep timer is turned off
+            "sleep" => Self::Sleep(seconds(&request["duration"])),
+            "status" => Self::Status,
+            "queue" => Self::Queue,
+            "reload" => Self::Reload,
+            "quit" => Self::Quit,
+            _ => return Err(format!("unknown command {}", name)),
+        };
+        Ok(command)
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            Self::Play => json!({"command": "play"}),
+            Self::PlayAt(position) => json!({"command": "play", "position": position}),
+            Self::Pause => json!({"command": "pause"}),
+            Self::Toggle => json!({"command": "toggle"}),
+            Self::Next => json!({"command": "next"}),
+            Self::Prev => json!({"command": "prev"}),
+            Self::Seek(SeekTarget::Absolute(position)) => {
+                json!({"command": "seek", "position": as_seconds(*position)})
+            }
+            Self::Seek(SeekTarget::Relative(offset)) => {
+                json!({"command": "seek", "offset": as_seconds(*offset)})
+            }
+            Self::Volume(VolumeTarget::Absolute(volume)) => {
+                json!({"command": "volume", "volume": volume})
+            }
+            Self::Volume(VolumeTarget::Relative(change)) => {
+                json!({"command": "volume", "change": change})
+            }
+            Self::EnqueuePath(path) => {
+                json!({"command": "enqueue", "path": path.to_string_lossy()})
+            }
+            Self::EnqueueTrack(track) => json!({"command": "enqueue", "track": track}),
+            Self::Clear => json!({"command": "clear"}),
+            Self::Sleep(duration) => json!({
+                "command": "sleep",
+                "duration": duration.map(as_seconds),
+            }),
+            Self::Status => json!({"command": "status"}),
+            Self::Queue => json!({"command": "queue"}),
+            Self::Reload => json!({"command": "reload"}),
+            Self::Quit => json!({"command": "quit"}),
+        }
+    }
+
+    /// The command for the arguments of `musicom ctl`. Paths are made absolute here, the
+    /// player doesn't know the working directory of the client.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        let command = match args.as_slice() {
+            ["play"] => Self::Play,
+            ["play", position] => Self::PlayAt(
+                position
+                    .parse()
+                    .map_err(|_| format!("{} is not a position in the queue", position))?,
+            ),
+            ["pause"] => Self::Pause,
+            ["toggle"] => Self::Toggle,
+            ["next"] => Self::Next,
+            ["prev"] => Self::Prev,
+            ["seek", secs] => {
+                let value = secs
+                    .parse::<f64>()
+                    .map_err(|_| format!("{} is not a number of seconds", secs))?;
+                let duration = Duration::milliseconds((value * 1000.0) as i64);
+                if secs.starts_with('+') || secs.starts_with('-') {
+                    Self::Seek(SeekTarget::Relative(duration))
+                } else {
+                    Self::Seek(SeekTarget::Absolute(duration))
+                }
+            }
+            ["volume", percent] => {
+                let value = percent
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|value| value.abs() <= 100.0)
+                    .ok_or_else(|| format!("{} is not a volume from 0 to 100", percent))?;
+                if percent.starts_with('+') || percent.starts_with('-') {
+                    Self::Volume(VolumeTarget::Relative(value / 100.0))
+                } else {
+                    Self::Volume(VolumeTarget::Absolute(value / 100.0))
+                }
+            }
+            ["enqueue", "--track", id] => Self::EnqueueTrack(
+                id.parse()
+                    .map_err(|_| format!("{} is not a track id", id))?,
+            ),
+            ["enqueue", path] => {
+                Self::EnqueuePath(fs::canonicalize(path).map_err(|e| format!("{}: {}", path, e))?)
+            }
+            ["clear"] => Self::Clear,
+            ["sleep", "off"] => Self::Sleep(None),
+            ["sleep", duration] => {
+                Self::Sleep(Some(parse_duration(duration).ok_or_else(|| {
+                    format!("{} is not a duration like 30m", duration)
+                })?))
+            }
+            ["status"] => Self::Status,
+            ["queue"] => Self::Queue,
+            ["reload"] => Self::Reload,
+            ["quit"] => Self::Quit,
+            _ => return Err(format!("Unknown command: {}", args.join(" "))),
+        };
+        Ok(command)
+    }
+}
+
+pub fn get_socket_path() -> PathBuf {
+    crate::util::get_runtime_dir().join(SOCKET_NAME)
+}
+
+fn get_status(player: &PlayerHdl) -> Value {
+    let state = match player.get_playback_state() {
+        PlaybackState::Playing => "playing",
+        PlaybackState::Paused => "paused",
+        PlaybackState::Stopped => "stopped",
+    };
+    let volume = player.get_volume();
+    let (queue_position, queue_length) = {
+        let queue = player.queue();
+        (queue.get_queue_position(), queue.get_queue_contents().len())
+    };
+    let now_playing = player.now_playing();
+    let (position, duration) = now_playing.get_song_progress();
+    let path = now_playing.get_item().and_then(|item| {
+        item.get_path()
+            .map(|path| path.to_string_lossy().to_string())
+    });
+
+    json!({
+        "state": state,
+        "artist": now_playing.get_artist(),
+        "title": now_playing.get_song_name(),
+        "album": now_playing.get_album(),
+        "path": path,
+        "position": as_seconds(position),
+        "duration": as_seconds(duration),
+        "queue_position": queue_position,
+        "queue_length": queue_length,
+        "volume": volume,
+    })
+}
+
+/// The songs in the queue with their indexes, the other queue items are left out.
+fn get_queue(player: &PlayerHdl) -> Value {
+    let queue = player.queue();
+    let songs = queue
+        .get_queue_contents()
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, item)| {
+            let path = item.get_path()?;
+            let (artist, title) = match item {
+                QueueItem::Track(track) => (track.artist.clone(), track.title.clone()),
+                _ => (None, None),
+            };
+            Some(json!({
+                "index": idx,
+                "path": path.to_string_lossy(),
+                "artist": artist,
+                "title": title,
+                "failed": queue.is_failed(item),
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "queue": songs,
+        "queue_position": queue.get_queue_position(),
+    })
+}
+
+/// Run a command on the player, returns the fields the status and queue commands add to the
+/// reply.
+pub fn execute(player: &PlayerHdl, command: Command) -> Result<Option<Value>, String> {
+    match command {
+        Command::Play => player.play(),
+        Command::PlayAt(idx) => {
+            let mut queue = player.queue_mut();
+            if idx >= queue.get_queue_contents().len() {
+                return Err(format!("there is nothing at position {} in the queue", idx));
+            }
+            queue.set_queue_index(idx);
+            queue.play_queue_at_selection();
+        }
+        Command::Pause => player.pause(),
+        Command::Toggle => match player.get_playback_state() {
+            PlaybackState::Stopped => player.play(),
+            _ => player.toggle_play_pause(),
+        },
+        Command::Next => player.play_next(),
+        Command::Prev => player.play_previous(),
+        Command::Seek(target) => {
+            let position = match target {
+                SeekTarget::Absolute(position) => position,
+                SeekTarget::Relative(offset) => player.now_playing().get_song_progress().0 + offset,
+            };
+            player.seek_to(position.max(Duration::zero()));
+        }
+        Command::Volume(target) => {
+            let volume = match target {
+                VolumeTarget::Absolute(volume) => volume,
+                VolumeTarget::Relative(change) => player.get_volume() + change,
+            };
+            player.set_volume(volume);
+        }
+        Command::EnqueuePath(path) => {
+            if !path.exists() {
+                return Err(format!("{} does not exist", path.display()));
+            }
+            // Songs that are in the library are queued as tracks, like `musicom PATH...` does
+            match Track::from_path(&path) {
+                Some(track) => player.queue_mut().add_track(&track),
+                None => player.queue_mut().add_song(&path),
+            }
+        }
+        Command::EnqueueTrack(id) => {
+            let track = Track::get(id).ok_or_else(|| format!("there is no track {}", id))?;
+            player.queue_mut().add_track(&track);
+        }
+        Command::Clear => player.queue_mut().clear_queue(),
+        Command::Sleep(duration) => {
+            player.set_sleep_timer(duration.map(|duration| SleepTimer::after(duration, false)))
+        }
+        Command::Status => return Ok(Some(json!({ "status": get_status(player) }))),
+        Command::Queue => return Ok(Some(get_queue(player))),
+        Command::Reload => config::reload()?,
+        Command::Quit => return Err("only a musicom daemon can be told to quit".to_string()),
+    }
+    Ok(None)
+}
+
+fn reply(result: Result<Option<Value>, String>) -> Value {
+    match result {
+        Ok(fields) => {
+            let mut reply = json!({"ok": true});
+            if let Some(Value::Object(fields)) = fields {
+                reply.as_object_mut().unwrap().extend(fields);
+            }
+            reply
+        }
+        Err(e) => json!({"ok": false, "error": e}),
+    }
+}
+
+fn handle_client<F>(stream: UnixStream, handler: &F) -> io::Result<()>
+where
+    F: Fn(Command) -> Result<Option<Value>, String>,
+{
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = serde_json::from_str(&line)
+            .map_err(|e| format!("invalid request: {}", e))
+            .and_then(|request| Command::from_json(&request))
+            .and_then(handler);
+        writeln!(writer, "{}", reply(result))?;
+    }
+    Ok(())
+}
+
+/// Accept clients on the listener in the background, every client gets its own thread.
+pub fn serve<F>(listener: UnixListener, handler: F)
+where
+    F: Fn(Command) -> Result<Option<Value>, String> + Clone + Send + 'static,
+{
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Could not accept a control connection: {}", e);
+                    continue;
+                }
+            };
+            let handler = handler.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle_client(stream, &handler) {
+                    log::warn!("Control connection failed: {}", e);
+                }
+            });
+        }
+    });
+}
+
+/// Bind the control socket, replacing the socket of a musicom that didn't shut down cleanly.
+/// Fails if another musicom is still listening on it. Only this user may connect.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "another musicom is already running",
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::remove_file(path).ok();
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Listen for commands on the control socket, they act on the same player as the UI. Quit
+/// requests are sent to `quit`, the UI doesn't take them.
+pub fn start(quit: Option<mpsc::Sender<()>>) -> io::Result<()> {
+    let path = get_socket_path();
+    let listener = crate::util::create_runtime_dir()
+        .and_then(|_| bind(&path))
+        .map_err(|e| {
+            log::warn!("Not listening on {}: {}", path.display(), e);
+            e
+        })?;
+
+    serve(listener, move |command| match (command, quit.as_ref()) {
+        (Command::Quit, Some(quit)) => {
+            quit.send(()).ok();
+            Ok(None)
+        }
+        (command, _) => execute(&PlayerHdl::new(), command),
+    });
+    Ok(())
+}
+
+/// Send a command to the running musicom, returns its reply.
+pub fn send_command(path: &Path, command: &Command) -> Result<Value, String> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|e| format!("Could not connect to musicom at {}: {}", path.display(), e))?;
+    writeln!(stream, "{}", command.to_json()).map_err(|e| e.to_string())?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    let reply: Value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+
+    if reply["ok"].as_bool() == Some(true) {
+        Ok(reply)
+    } else {
+        Err(reply["error"]
+            .as_str()
+            .unwrap_or("unknown error")
+            .to_string())
+    }
+}
+
+fn format_seconds(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// One line about what is playing, from the reply to the status command
+pub fn format_status(status: &Value) -> String {
+    let state = status["state"].as_str().unwrap_or("stopped");
+    if state == "stopped" {
+        return "Stopped".to_string();
+    }
+    format!(
+        "{} - {} [{}] {}/{}",
+        status["artist"].as_str().unwrap_or(""),
+        status["title"].as_str().unwrap_or(""),
+        state,
+        format_seconds(status["position"].as_f64().unwrap_or(0.0)),
+        format_seconds(status["duration"].as_f64().unwrap_or(0.0)),
+    )
+}
+
+/// A song from the reply to the queue command, by its tags if it's in the library
+pub fn format_song(song: &Value) -> String {
+    let name = match (song["artist"].as_str(), song["title"].as_str()) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.to_string(),
+        _ => {
+            let path = Path::new(song["path"].as_str().unwrap_or(""));
+            path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        }
+    };
+    if song["failed"].as_bool() == Some(true) {
+        format!("{} (failed)", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::from_args(&args(&["toggle"])), Ok(Command::Toggle));
+        assert_eq!(
+            Command::from_args(&args(&["seek", "-10"])),
+            Ok(Command::Seek(SeekTarget::Relative(Duration::seconds(-10))))
+        );
+        assert_eq!(
+            Command::from_args(&args(&["seek", "90.5"])),
+            Ok(Command::Seek(SeekTarget::Absolute(Duration::milliseconds(
+                90_500
+            ))))
+        );
+        assert_eq!(
+            Command::from_args(&args(&["enqueue", "--track", "12"])),
+            Ok(Command::EnqueueTrack(12))
+        );
+        assert!(Command::from_args(&args(&["seek", "soon"])).is_err());
+        assert_eq!(
+            Command::from_args(&args(&["sleep", "1h30m"])),
+            Ok(Command::Sleep(Some(Duration::minutes(90))))
+        );
+        assert_eq!(
+            Command::from_args(&args(&["sleep", "off"])),
+            Ok(Command::Sleep(None))
+        );
+        assert!(Command::from_args(&args(&["sleep", "30x"])).is_err());
+        assert_eq!(
+            Command::from_args(&args(&["volume", "60"])),
+            Ok(Command::Volume(VolumeTarget::Absolute(0.6)))
+        );
+        assert_eq!(
+            Command::from_args(&args(&["volume", "+5"])),
+            Ok(Command::Volume(VolumeTarget::Relative(0.05)))
+        );
+        assert!(Command::from_args(&args(&["volume", "150"])).is_err());
+        assert!(Command::from_args(&args(&["dance"])).is_err());
+
+        assert_eq!(
+            Command::from_args(&args(&["play", "3"])),
+            Ok(Command::PlayAt(3))
+        );
+
+        let commands = [
+            Command::Play,
+            Command::PlayAt(0),
+            Command::Prev,
+            Command::Seek(SeekTarget::Relative(Duration::seconds(5))),
+            Command::Volume(VolumeTarget::Absolute(0.6)),
+            Command::Volume(VolumeTarget::Relative(-0.05)),
+            Command::EnqueuePath("/tmp/test1.mp3".into()),
+            Command::EnqueueTrack(3),
+            Command::Sleep(Some(Duration::minutes(30))),
+            Command::Sleep(None),
+            Command::Status,
+            Command::Queue,
+            Command::Reload,
+        ];
+        for command in commands.iter() {
+            assert_eq!(Command::from_json(&command.to_json()).as_ref(), Ok(command));
+        }
+        assert!(Command::from_json(&json!({"command": "seek"})).is_err());
+    }
+
+    #[test]
+    fn format_replies() {
+        let status = json!({
+            "state": "paused",
+            "artist": "George",
+            "title": "Test 1: The Intro",
+            "position": 75.2,
+            "duration": 200.0,
+        });
+        assert_eq!(
+            format_status(&status),
+            "George - Test 1: The Intro [paused] 1:15/3:20"
+        );
+        assert_eq!(format_status(&json!({"state": "stopped"})), "Stopped");
+
+        let song = json!({"index": 2, "path": "/tmp/test1.mp3", "failed": true});
+        assert_eq!(format_song(&song), "test1.mp3 (failed)");
+    }
+
+    #[test]
+    fn socket_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("musicom-control-{}", std::process::id()))
+            .join(SOCKET_NAME);
+        let listener = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // A second musicom can't take over the socket
+        assert!(bind(&path).is_err());
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Arc::new(Mutex::new(sender));
+        serve(listener, move |command| {
+            sender.lock().unwrap().send(command.clone()).unwrap();
+            match command {
+                Command::Status => Ok(Some(json!({"status": {"state": "stopped"}}))),
+                Command::EnqueueTrack(_) => Err("there is no track 7".to_string()),
+                _ => Ok(None),
+            }
+        });
+
+        assert_eq!(send_command(&path, &Command::Next), Ok(json!({"ok": true})));
+        assert_eq!(receiver.recv().unwrap(), Command::Next);
+        assert_eq!(
+            send_command(&path, &Command::Status),
+            Ok(json!({"ok": true, "status": {"state": "stopped"}}))
+        );
+        assert_eq!(
+            send_command(&path, &Command::EnqueueTrack(7)),
+            Err("there is no track 7".to_string())
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
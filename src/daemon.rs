@@ -0,0 +1,75 @@
+//! Running without the UI, e.g. on a box that is only hooked up to speakers. The daemon is
+//! controlled through the control socket, MPRIS and the MPD server, and `musicom attach` is a
+//! remote control for it.
+
+use std::fs;
+use std::sync::mpsc;
+
+use gst::glib;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::player::{PlayEnd, PlayerHdl};
+use crate::{control, mpd, mpris, scrobbler};
+
+/// The UI shows the log in its console, the daemon writes it to stderr instead
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{} {:<5} {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Play until `musicom ctl quit` or until the daemon is killed, returns the exit code.
+pub fn run() -> i32 {
+    log::set_logger(&LOGGER)
+        .map(|()| log::set_max_level(LevelFilter::Info))
+        .ok();
+
+    // Without a control socket there would be no way to quit, or to attach to the daemon
+    let (quit, quit_requests) = mpsc::channel();
+    if control::start(Some(quit.clone())).is_err() {
+        return 1;
+    }
+    // Set up the player now rather than on the first request
+    let player = PlayerHdl::new();
+    player.get_volume();
+
+    // Being killed quits the same way, so the play record of the current song isn't lost. The
+    // player runs the default main context, which is where these get called.
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP].iter() {
+        let quit = quit.clone();
+        glib::unix_signal_add(*signal, move || {
+            quit.send(()).ok();
+            glib::Continue(false)
+        });
+    }
+
+    scrobbler::spawn_submitter();
+    mpris::start();
+    mpd::start();
+
+    log::info!("musicom is running, stop it with `musicom ctl quit`");
+    quit_requests.recv().ok();
+
+    // Stopping finishes the play record of the current song, quitting isn't skipping it
+    player.stop_with(PlayEnd::Stopped);
+    fs::remove_file(control::get_socket_path()).ok();
+    log::info!("musicom stopped");
+    0
+}
@@ -0,0 +1,71 @@
+//! Only one musicom plays at a time, otherwise two players would play over each other and write
+//! to the same library. The running one holds a lock in the runtime directory, the ones started
+//! after it hand their songs to it through the control socket.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+const LOCK_NAME: &str = "musicom.lock";
+
+lazy_static! {
+    /// The lock is released when the file is closed, so it stays open until musicom exits
+    static ref LOCK_FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+pub fn get_lock_path() -> PathBuf {
+    crate::util::get_runtime_dir().join(LOCK_NAME)
+}
+
+/// Lock the file, `None` if someone else holds the lock. The kernel releases it when the process
+/// dies, so a crash doesn't leave a stale lock behind.
+fn lock_file(path: &Path) -> io::Result<Option<File>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).write(true).open(path)?;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::EWOULDBLOCK) => Ok(None),
+            _ => Err(e),
+        };
+    }
+
+    // Only for people wondering which process holds the lock
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+    Ok(Some(file))
+}
+
+/// Become the running musicom, returns false when another one already is.
+pub fn acquire() -> io::Result<bool> {
+    let mut lock = LOCK_FILE.lock().unwrap();
+    if lock.is_none() {
+        crate::util::create_runtime_dir()?;
+        *lock = lock_file(&get_lock_path())?;
+    }
+    Ok(lock.is_some())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn one_lock_holder() {
+        let path = std::env::temp_dir().join("musicom-lock-test/musicom.lock");
+
+        let first = lock_file(&path).unwrap();
+        assert!(first.is_some());
+        assert!(lock_file(&path).unwrap().is_none());
+
+        drop(first);
+        assert!(lock_file(&path).unwrap().is_some());
+    }
+}
@@ -0,0 +1,1108 @@
+//! Everything keys can do in the UI, and the keys that do it. Every action has default keys,
+//! presets replace some of them, and the `[keys]` section of the config replaces the keys of
+//! single actions:
+//!
+//! ```toml
+//! [keys]
+//! preset = "vim"
+//! toggle_play = "Space"
+//! show_view_chooser = ["v", "g v"]
+//! help = []
+//! ```
+//!
+//! A binding is a sequence of keys separated by spaces, like `g v`. Keys are single characters,
+//! names like `Enter`, `Space` or `F1`, or have a `C-` (Ctrl), `A-` (Alt) or `S-` (Shift) prefix.
+//!
+//! Some actions only work in one view, like rating the selected song. Their keys take over the
+//! keys of the global actions while that view has the focus.
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use cursive::event::{Event, Key};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+/// How long the next key of a sequence is waited for
+const SEQUENCE_TIMEOUT_MS: u64 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    TogglePlay,
+    PlayNext,
+    PlayPrevious,
+    SeekBackward,
+    SeekForward,
+    ShortenCrossfade,
+    LengthenCrossfade,
+    ToggleCrossfadeWithinAlbum,
+    CycleReplayGainMode,
+    TogglePreventClipping,
+    LowerPreamp,
+    RaisePreamp,
+    SlowDown,
+    SpeedUp,
+    ResetSpeed,
+    RememberTrackSpeed,
+    RememberGenreSpeed,
+    ToggleStopAfterCurrent,
+    SetLoopPoint,
+    ClearLoop,
+    ShowViewChooser,
+    ToggleQueue,
+    AddBookmark,
+    ShowBookmarks,
+    ShowRatingChooser,
+    ShowSleepTimer,
+    ShowOutputs,
+    ShowEqualizer,
+    /// Handled by the focused view, for its selected item
+    OpenActions,
+    /// Handled by the focused view, or lists only the global keys
+    Help,
+    CommandLine,
+    MoveUp,
+    MoveDown,
+    PageUp,
+    PageDown,
+    GoToTop,
+    GoToBottom,
+    /// Give the selected song this many stars
+    RateSelection(u8),
+    ToggleSelectionLoved,
+    PreviousStatsPeriod,
+    NextStatsPeriod,
+    ShowStatsWeek,
+    ShowStatsMonth,
+    ShowStatsYear,
+    ShowStatsAllTime,
+    RefreshStats,
+    PreviousBand,
+    NextBand,
+    RaiseBand,
+    LowerBand,
+    ResetBand,
+    ToggleEqualizer,
+    ChooseEqPreset,
+    SaveEqPreset,
+    DeleteEqPreset,
+    ToggleAutoEqPresets,
+    SetAlbumEqPreset,
+    SetGenreEqPreset,
+}
+
+/// The view an action only works in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActionView {
+    Songs,
+    Stats,
+    Equalizer,
+}
+
+/// Where an action does something, the help lists the actions by it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActionGroup {
+    Playback,
+    Windows,
+    Navigation,
+    /// Only the views that support them do something for these
+    View,
+}
+
+impl ActionGroup {
+    pub const ALL: [ActionGroup; 4] = [Self::Playback, Self::Windows, Self::Navigation, Self::View];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Playback => "Playback",
+            Self::Windows => "Windows",
+            Self::Navigation => "Navigation",
+            Self::View => "Views",
+        }
+    }
+}
+
+/// The action, its name in the config, what it does, and its default keys
+type ActionInfo = (Action, &'static str, &'static str, &'static [&'static str]);
+
+const ACTIONS: &[ActionInfo] = &[
+    (
+        Action::TogglePlay,
+        "toggle_play",
+        "Pause or play the current song",
+        &["p"],
+    ),
+    (
+        Action::PlayNext,
+        "play_next",
+        "Skip to the next song",
+        &[">"],
+    ),
+    (
+        Action::PlayPrevious,
+        "play_previous",
+        "Go back to the previous song",
+        &["<"],
+    ),
+    (
+        Action::SeekBackward,
+        "seek_backward",
+        "Seek 10 seconds back",
+        &[","],
+    ),
+    (
+        Action::SeekForward,
+        "seek_forward",
+        "Seek 10 seconds forward",
+        &["."],
+    ),
+    (
+        Action::ShortenCrossfade,
+        "shorten_crossfade",
+        "Shorten the crossfade by a second",
+        &["["],
+    ),
+    (
+        Action::LengthenCrossfade,
+        "lengthen_crossfade",
+        "Lengthen the crossfade by a second",
+        &["]"],
+    ),
+    (
+        Action::ToggleCrossfadeWithinAlbum,
+        "toggle_crossfade_within_album",
+        "Turn crossfading between songs of the same album on or off",
+        &["\\"],
+    ),
+    (
+        Action::CycleReplayGainMode,
+        "cycle_replaygain_mode",
+        "Switch the ReplayGain mode",
+        &["g"],
+    ),
+    (
+        Action::TogglePreventClipping,
+        "toggle_prevent_clipping",
+        "Turn the ReplayGain clipping prevention on or off",
+        &["G"],
+    ),
+    (
+        Action::LowerPreamp,
+        "lower_preamp",
+        "Lower the ReplayGain pre-amp by 1 dB",
+        &["("],
+    ),
+    (
+        Action::RaisePreamp,
+        "raise_preamp",
+        "Raise the ReplayGain pre-amp by 1 dB",
+        &[")"],
+    ),
+    (Action::SlowDown, "slow_down", "Play slower", &["-"]),
+    (Action::SpeedUp, "speed_up", "Play faster", &["+"]),
+    (
+        Action::ResetSpeed,
+        "reset_speed",
+        "Play at the normal speed",
+        &["="],
+    ),
+    (
+        Action::RememberTrackSpeed,
+        "remember_track_speed",
+        "Remember the speed for the current song",
+        &["m"],
+    ),
+    (
+        Action::RememberGenreSpeed,
+        "remember_genre_speed",
+        "Remember the speed for the genre of the current song",
+        &["M"],
+    ),
+    (
+        Action::ToggleStopAfterCurrent,
+        "toggle_stop_after_current",
+        "Stop after the current song",
+        &["x"],
+    ),
+    (
+        Action::SetLoopPoint,
+        "set_loop_point",
+        "Set the start or the end of the A-B loop",
+        &["l"],
+    ),
+    (
+        Action::ClearLoop,
+        "clear_loop",
+        "Clear the A-B loop",
+        &["L"],
+    ),
+    (
+        Action::ShowViewChooser,
+        "show_view_chooser",
+        "Choose the view next to the queue",
+        &["v"],
+    ),
+    (
+        Action::ToggleQueue,
+        "toggle_queue",
+        "Show or hide the queue",
+        &["q"],
+    ),
+    (
+        Action::AddBookmark,
+        "add_bookmark",
+        "Bookmark the position in the current song",
+        &["k"],
+    ),
+    (
+        Action::ShowBookmarks,
+        "show_bookmarks",
+        "List the bookmarks of the current song",
+        &["K"],
+    ),
+    (
+        Action::ShowRatingChooser,
+        "show_rating_chooser",
+        "Rate the current song",
+        &["R"],
+    ),
+    (
+        Action::ShowSleepTimer,
+        "show_sleep_timer",
+        "Set the sleep timer",
+        &["t"],
+    ),
+    (
+        Action::ShowOutputs,
+        "show_outputs",
+        "Choose the audio output",
+        &["o"],
+    ),
+    (
+        Action::ShowEqualizer,
+        "show_equalizer",
+        "Open the equalizer",
+        &["e"],
+    ),
+    (
+        Action::OpenActions,
+        "open_actions",
+        "Open the action menu for the selection",
+        &["a"],
+    ),
+    (Action::Help, "help", "List the keys", &["?"]),
+    (
+        Action::CommandLine,
+        "command_line",
+        "Type a command",
+        &[":"],
+    ),
+    (Action::MoveUp, "move_up", "Select the item above", &[]),
+    (Action::MoveDown, "move_down", "Select the item below", &[]),
+    (
+        Action::PageUp,
+        "page_up",
+        "Move the selection a page up",
+        &[],
+    ),
+    (
+        Action::PageDown,
+        "page_down",
+        "Move the selection a page down",
+        &[],
+    ),
+    (Action::GoToTop, "go_to_top", "Select the first item", &[]),
+    (
+        Action::GoToBottom,
+        "go_to_bottom",
+        "Select the last item",
+        &[],
+    ),
+    (
+        Action::RateSelection(0),
+        "rate_0",
+        "Remove the rating of the selected song",
+        &["0"],
+    ),
+    (
+        Action::RateSelection(1),
+        "rate_1",
+        "Rate the selected song one star",
+        &["1"],
+    ),
+    (
+        Action::RateSelection(2),
+        "rate_2",
+        "Rate the selected song two stars",
+        &["2"],
+    ),
+    (
+        Action::RateSelection(3),
+        "rate_3",
+        "Rate the selected song three stars",
+        &["3"],
+    ),
+    (
+        Action::RateSelection(4),
+        "rate_4",
+        "Rate the selected song four stars",
+        &["4"],
+    ),
+    (
+        Action::RateSelection(5),
+        "rate_5",
+        "Rate the selected song five stars",
+        &["5"],
+    ),
+    (
+        Action::ToggleSelectionLoved,
+        "toggle_loved",
+        "Love the selected song, or stop loving it",
+        &["h"],
+    ),
+    (
+        Action::PreviousStatsPeriod,
+        "previous_stats_period",
+        "Show a shorter period",
+        &["Left"],
+    ),
+    (
+        Action::NextStatsPeriod,
+        "next_stats_period",
+        "Show a longer period",
+        &["Right"],
+    ),
+    (
+        Action::ShowStatsWeek,
+        "show_stats_week",
+        "Show the last week",
+        &["1"],
+    ),
+    (
+        Action::ShowStatsMonth,
+        "show_stats_month",
+        "Show the last month",
+        &["2"],
+    ),
+    (
+        Action::ShowStatsYear,
+        "show_stats_year",
+        "Show the last year",
+        &["3"],
+    ),
+    (
+        Action::ShowStatsAllTime,
+        "show_stats_all_time",
+        "Show all time",
+        &["4"],
+    ),
+    (
+        Action::RefreshStats,
+        "refresh_stats",
+        "Refresh the statistics",
+        &["r"],
+    ),
+    (
+        Action::PreviousBand,
+        "previous_band",
+        "Select the band to the left",
+        &["Left"],
+    ),
+    (
+        Action::NextBand,
+        "next_band",
+        "Select the band to the right",
+        &["Right"],
+    ),
+    (
+        Action::RaiseBand,
+        "raise_band",
+        "Raise the selected band",
+        &["Up"],
+    ),
+    (
+        Action::LowerBand,
+        "lower_band",
+        "Lower the selected band",
+        &["Down"],
+    ),
+    (
+        Action::ResetBand,
+        "reset_band",
+        "Reset the selected band",
+        &["0"],
+    ),
+    (
+        Action::ToggleEqualizer,
+        "toggle_equalizer",
+        "Turn the equalizer on or off",
+        &["e"],
+    ),
+    (
+        Action::ChooseEqPreset,
+        "choose_eq_preset",
+        "Pick a preset",
+        &["n"],
+    ),
+    (
+        Action::SaveEqPreset,
+        "save_eq_preset",
+        "Save the current settings as a preset",
+        &["s"],
+    ),
+    (
+        Action::DeleteEqPreset,
+        "delete_eq_preset",
+        "Delete the current preset",
+        &["d"],
+    ),
+    (
+        Action::ToggleAutoEqPresets,
+        "toggle_auto_eq_presets",
+        "Toggle picking presets by album and genre",
+        &["A"],
+    ),
+    (
+        Action::SetAlbumEqPreset,
+        "set_album_eq_preset",
+        "Always use the current preset for this album",
+        &["b"],
+    ),
+    (
+        Action::SetGenreEqPreset,
+        "set_genre_eq_preset",
+        "Always use the current preset for this genre",
+        &["r"],
+    ),
+];
+
+/// The keys the vim preset changes
+const VIM_KEYS: &[(Action, &[&str])] = &[
+    (Action::MoveUp, &["k"]),
+    (Action::MoveDown, &["j"]),
+    (Action::PageUp, &["C-u"]),
+    (Action::PageDown, &["C-d"]),
+    (Action::GoToTop, &["g g"]),
+    (Action::GoToBottom, &["G"]),
+    // The default keys of these are taken by the ones above
+    (Action::CycleReplayGainMode, &["y"]),
+    (Action::TogglePreventClipping, &["Y"]),
+    (Action::AddBookmark, &["b"]),
+    (Action::ShowBookmarks, &["B"]),
+];
+
+const NAMED_KEYS: &[(&str, Key)] = &[
+    ("Enter", Key::Enter),
+    ("Tab", Key::Tab),
+    ("Backspace", Key::Backspace),
+    ("Esc", Key::Esc),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Ins", Key::Ins),
+    ("Del", Key::Del),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+];
+
+impl Action {
+    pub fn all() -> impl Iterator<Item = Action> {
+        ACTIONS.iter().map(|info| info.0)
+    }
+
+    fn info(self) -> &'static ActionInfo {
+        ACTIONS.iter().find(|info| info.0 == self).unwrap()
+    }
+
+    /// The action for its name in the config
+    pub fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|info| info.1 == name)
+            .map(|info| info.0)
+    }
+
+    pub fn name(self) -> &'static str {
+        self.info().1
+    }
+
+    pub fn description(self) -> &'static str {
+        self.info().2
+    }
+
+    pub fn group(self) -> ActionGroup {
+        match self {
+            Self::ShowViewChooser
+            | Self::ToggleQueue
+            | Self::AddBookmark
+            | Self::ShowBookmarks
+            | Self::ShowRatingChooser
+            | Self::ShowSleepTimer
+            | Self::ShowOutputs
+            | Self::ShowEqualizer
+            | Self::Help
+            | Self::CommandLine => ActionGroup::Windows,
+            Self::MoveUp
+            | Self::MoveDown
+            | Self::PageUp
+            | Self::PageDown
+            | Self::GoToTop
+            | Self::GoToBottom => ActionGroup::Navigation,
+            Self::OpenActions => ActionGroup::View,
+            _ if self.view().is_some() => ActionGroup::View,
+            _ => ActionGroup::Playback,
+        }
+    }
+
+    /// The view the action only works in, `None` if it works everywhere
+    pub fn view(self) -> Option<ActionView> {
+        match self {
+            Self::RateSelection(_) | Self::ToggleSelectionLoved => Some(ActionView::Songs),
+            Self::PreviousStatsPeriod
+            | Self::NextStatsPeriod
+            | Self::ShowStatsWeek
+            | Self::ShowStatsMonth
+            | Self::ShowStatsYear
+            | Self::ShowStatsAllTime
+            | Self::RefreshStats => Some(ActionView::Stats),
+            Self::PreviousBand
+            | Self::NextBand
+            | Self::RaiseBand
+            | Self::LowerBand
+            | Self::ResetBand
+            | Self::ToggleEqualizer
+            | Self::ChooseEqPreset
+            | Self::SaveEqPreset
+            | Self::DeleteEqPreset
+            | Self::ToggleAutoEqPresets
+            | Self::SetAlbumEqPreset
+            | Self::SetGenreEqPreset => Some(ActionView::Equalizer),
+            _ => None,
+        }
+    }
+
+    /// The actions that move the selection send this key to the focused view instead
+    pub fn navigation_key(self) -> Option<Key> {
+        match self {
+            Self::MoveUp => Some(Key::Up),
+            Self::MoveDown => Some(Key::Down),
+            Self::PageUp => Some(Key::PageUp),
+            Self::PageDown => Some(Key::PageDown),
+            Self::GoToTop => Some(Key::Home),
+            Self::GoToBottom => Some(Key::End),
+            _ => None,
+        }
+    }
+}
+
+fn single_char(key: &str) -> Option<char> {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+fn named_key(name: &str) -> Option<Key> {
+    NAMED_KEYS
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|(_, key)| *key)
+}
+
+pub fn parse_key(key: &str) -> Option<Event> {
+    if let Some(c) = single_char(key) {
+        return Some(Event::Char(c));
+    }
+    if key.eq_ignore_ascii_case("Space") {
+        return Some(Event::Char(' '));
+    }
+    if let Some(key) = named_key(key) {
+        return Some(Event::Key(key));
+    }
+
+    let (modifier, rest) = (key.get(..2)?, key.get(2..)?);
+    match (modifier, single_char(rest), named_key(rest)) {
+        ("C-", Some(c), _) => Some(Event::CtrlChar(c)),
+        ("A-", Some(c), _) => Some(Event::AltChar(c)),
+        ("C-", None, Some(key)) => Some(Event::Ctrl(key)),
+        ("A-", None, Some(key)) => Some(Event::Alt(key)),
+        ("S-", None, Some(key)) => Some(Event::Shift(key)),
+        _ => None,
+    }
+}
+
+/// The keys of a binding like `g v`, `None` if one of them isn't a key
+pub fn parse_sequence(sequence: &str) -> Option<Vec<Event>> {
+    let keys = sequence
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Option<Vec<_>>>()?;
+    if keys.is_empty() {
+        None
+    } else {
+        Some(keys)
+    }
+}
+
+pub fn format_key(event: &Event) -> String {
+    let key_name = |key: &Key| {
+        NAMED_KEYS
+            .iter()
+            .find(|(_, named)| named == key)
+            .map_or_else(|| format!("{:?}", key), |(name, _)| name.to_string())
+    };
+    match event {
+        Event::Char(' ') => "Space".to_string(),
+        Event::Char(c) => c.to_string(),
+        Event::CtrlChar(c) => format!("C-{}", c),
+        Event::AltChar(c) => format!("A-{}", c),
+        Event::Key(key) => key_name(key),
+        Event::Ctrl(key) => format!("C-{}", key_name(key)),
+        Event::Alt(key) => format!("A-{}", key_name(key)),
+        Event::Shift(key) => format!("S-{}", key_name(key)),
+        _ => format!("{:?}", event),
+    }
+}
+
+pub fn format_sequence(keys: &[Event]) -> String {
+    keys.iter().map(format_key).collect::<Vec<_>>().join(" ")
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyPreset {
+    Default,
+    Vim,
+}
+
+impl Default for KeyPreset {
+    fn default() -> Self {
+        Self::Default
+    }
+}
+
+/// One binding or a list of them
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyList {
+    fn sequences(&self) -> Vec<String> {
+        match self {
+            Self::One(sequence) => vec![sequence.clone()],
+            Self::Many(sequences) => sequences.clone(),
+        }
+    }
+}
+
+/// The `[keys]` section of the config
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct KeysConfig {
+    pub preset: KeyPreset,
+    /// Keys for actions by their name, they replace the keys of the preset
+    #[serde(flatten)]
+    pub bindings: BTreeMap<String, KeyList>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyPress {
+    Action(Action),
+    /// The keys so far are the start of a longer binding
+    Pending,
+    Unbound,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Keymap {
+    bindings: Vec<(Vec<Event>, Action)>,
+}
+
+/// Whether two bindings can be pressed in the same place. The keys of a view action take over
+/// the ones of a global action in that view, which is only fine for the keys musicom comes with.
+fn bindings_conflict(
+    (action, from_config): (Action, bool),
+    (other, other_from_config): (Action, bool),
+) -> bool {
+    match (action.view(), other.view()) {
+        (Some(view), Some(other_view)) => view == other_view,
+        (None, None) => true,
+        _ => from_config || other_from_config,
+    }
+}
+
+impl Keymap {
+    /// The default keys, with the preset and the bindings of the config on top. Two actions can't
+    /// share keys, and a binding can't be the start of another one, or it would never be known
+    /// which action was meant. Only actions of different views can share keys, and the keys of a
+    /// view action can't be set to the ones of a global action or the other way around.
+    pub fn new(config: &KeysConfig) -> Result<Self, String> {
+        // The keys of every action, and whether they were set in the config
+        let mut keys = ACTIONS
+            .iter()
+            .map(|(action, _, _, keys)| {
+                let sequences = keys.iter().map(|sequence| sequence.to_string());
+                (*action, sequences.collect::<Vec<_>>(), false)
+            })
+            .collect::<Vec<_>>();
+        let mut set_keys = |action: Action, sequences: Vec<String>, from_config: bool| {
+            if let Some(entry) = keys.iter_mut().find(|(other, _, _)| *other == action) {
+                entry.1 = sequences;
+                entry.2 = from_config;
+            }
+        };
+
+        if config.preset == KeyPreset::Vim {
+            for (action, sequences) in VIM_KEYS {
+                let sequences = sequences.iter().map(|s| s.to_string()).collect();
+                set_keys(*action, sequences, false);
+            }
+        }
+        for (name, key_list) in config.bindings.iter() {
+            let action =
+                Action::from_name(name).ok_or_else(|| format!("keys.{} is not an action", name))?;
+            set_keys(action, key_list.sequences(), true);
+        }
+
+        let mut bindings: Vec<(Vec<Event>, Action, bool)> = Vec::new();
+        for (action, sequences, from_config) in keys {
+            for sequence in sequences {
+                let events = parse_sequence(&sequence).ok_or_else(|| {
+                    format!("keys.{}: `{}` is not a key", action.name(), sequence)
+                })?;
+                let conflict = bindings
+                    .iter()
+                    .find(|(other, other_action, other_from_config)| {
+                        (other.starts_with(&events) || events.starts_with(other))
+                            && bindings_conflict(
+                                (action, from_config),
+                                (*other_action, *other_from_config),
+                            )
+                    });
+                if let Some((other, other_action, _)) = conflict {
+                    return Err(format!(
+                        "keys: `{}` of {} and `{}` of {} conflict",
+                        format_sequence(other),
+                        other_action.name(),
+                        format_sequence(&events),
+                        action.name()
+                    ));
+                }
+                bindings.push((events, action, from_config));
+            }
+        }
+
+        let bindings = bindings
+            .into_iter()
+            .map(|(events, action, _)| (events, action))
+            .collect();
+        Ok(Self { bindings })
+    }
+
+    /// What the keys pressed so far mean, out of the actions that `is_wanted`. Keys that start
+    /// the binding of any action are pending, so the views can finish sequences as well.
+    fn lookup_among<F>(&self, keys: &[Event], is_wanted: F) -> KeyPress
+    where
+        F: Fn(Action) -> bool,
+    {
+        let mut result = KeyPress::Unbound;
+        for (sequence, action) in self.bindings.iter() {
+            if sequence.as_slice() == keys && is_wanted(*action) {
+                return KeyPress::Action(*action);
+            }
+            if sequence.len() > keys.len() && sequence.starts_with(keys) {
+                result = KeyPress::Pending;
+            }
+        }
+        result
+    }
+
+    /// What the keys pressed so far mean outside of the views
+    pub fn lookup(&self, keys: &[Event]) -> KeyPress {
+        self.lookup_among(keys, |action| action.view().is_none())
+    }
+
+    /// The bindings of an action, formatted like in the config
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        self.bindings
+            .iter()
+            .filter(|(_, bound)| *bound == action)
+            .map(|(sequence, _)| format_sequence(sequence))
+            .collect()
+    }
+}
+
+/// The keys of a sequence that were pressed so far
+#[derive(Debug, Default)]
+struct PendingKeys {
+    keys: Vec<Event>,
+    last_press: Option<Instant>,
+}
+
+impl PendingKeys {
+    /// The keys with the event added, a sequence that wasn't continued in time is dropped.
+    fn with(&self, event: &Event, now: Instant) -> Vec<Event> {
+        let timeout = Duration::from_millis(SEQUENCE_TIMEOUT_MS);
+        let mut keys = match self.last_press {
+            Some(last_press) if now.duration_since(last_press) < timeout => self.keys.clone(),
+            _ => Vec::new(),
+        };
+        keys.push(event.clone());
+        keys
+    }
+
+    /// Like `Keymap::lookup_among` for the pending keys and the event. A key that doesn't
+    /// continue the sequence starts over on its own.
+    fn lookup<F>(
+        &self,
+        keymap: &Keymap,
+        event: &Event,
+        now: Instant,
+        is_wanted: F,
+    ) -> (Vec<Event>, KeyPress)
+    where
+        F: Fn(Action) -> bool,
+    {
+        let keys = self.with(event, now);
+        match keymap.lookup_among(&keys, &is_wanted) {
+            KeyPress::Unbound if keys.len() > 1 => {
+                let keys = vec![event.clone()];
+                let result = keymap.lookup_among(&keys, &is_wanted);
+                (keys, result)
+            }
+            result => (keys, result),
+        }
+    }
+
+    fn press(&mut self, keymap: &Keymap, event: &Event, now: Instant) -> KeyPress {
+        let (keys, result) = self.lookup(keymap, event, now, |action| action.view().is_none());
+        if result == KeyPress::Pending {
+            self.keys = keys;
+            self.last_press = Some(now);
+        } else {
+            self.clear();
+        }
+        result
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.last_press = None;
+    }
+}
+
+lazy_static! {
+    static ref KEYMAP: RwLock<Keymap> = RwLock::new(Keymap::new(&KeysConfig::default()).unwrap());
+    static ref PENDING: Mutex<PendingKeys> = Mutex::new(PendingKeys::default());
+}
+
+fn is_key_press(event: &Event) -> bool {
+    match event {
+        Event::Char(_)
+        | Event::CtrlChar(_)
+        | Event::AltChar(_)
+        | Event::Key(_)
+        | Event::Ctrl(_)
+        | Event::Alt(_)
+        | Event::Shift(_)
+        | Event::CtrlShift(_)
+        | Event::AltShift(_)
+        | Event::CtrlAlt(_) => true,
+        _ => false,
+    }
+}
+
+/// The keymap in use, with the keys of the config
+pub fn get_keymap() -> Keymap {
+    KEYMAP.read().unwrap().clone()
+}
+
+pub fn set_keymap(keymap: Keymap) {
+    *KEYMAP.write().unwrap() = keymap;
+    PENDING.lock().unwrap().clear();
+}
+
+/// Feed a key press that no view took into the keymap.
+pub fn press(event: &Event) -> KeyPress {
+    if !is_key_press(event) {
+        return KeyPress::Unbound;
+    }
+    let keymap = KEYMAP.read().unwrap();
+    PENDING
+        .lock()
+        .unwrap()
+        .press(&keymap, event, Instant::now())
+}
+
+/// For views that handle some of the actions themselves: the action the event completes, if it
+/// is one of `actions`. The keys are only used up when it is.
+pub fn action_for(event: &Event, actions: &[Action]) -> Option<Action> {
+    if !is_key_press(event) {
+        return None;
+    }
+    let keymap = KEYMAP.read().unwrap();
+    let mut pending = PENDING.lock().unwrap();
+    let (_, result) = pending.lookup(&keymap, event, Instant::now(), |action| {
+        actions.contains(&action)
+    });
+    match result {
+        KeyPress::Action(action) => {
+            pending.clear();
+            Some(action)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys_config(preset: KeyPreset, bindings: &[(&str, &[&str])]) -> KeysConfig {
+        KeysConfig {
+            preset,
+            bindings: bindings
+                .iter()
+                .map(|(name, keys)| {
+                    let keys = keys.iter().map(|key| key.to_string()).collect();
+                    (name.to_string(), KeyList::Many(keys))
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parse_keys() {
+        let keys = [
+            "p", "Space", "enter", "F5", "C-d", "A-x", "C-Left", "S-Tab", "\\",
+        ];
+        for key in keys.iter() {
+            let event = parse_key(key).unwrap();
+            assert_eq!(parse_key(&format_key(&event)), Some(event));
+        }
+        assert_eq!(parse_key("C-d"), Some(Event::CtrlChar('d')));
+        assert_eq!(parse_key("Space"), Some(Event::Char(' ')));
+        assert_eq!(parse_key("Spcae"), None);
+        assert_eq!(parse_key("X-a"), None);
+        assert_eq!(
+            parse_sequence("g  v"),
+            Some(vec![Event::Char('g'), Event::Char('v')])
+        );
+        assert_eq!(parse_sequence(" "), None);
+    }
+
+    #[test]
+    fn presets_and_overrides() {
+        let keymap = Keymap::new(&KeysConfig::default()).unwrap();
+        assert_eq!(keymap.keys_for(Action::TogglePlay), vec!["p"]);
+        assert!(keymap.keys_for(Action::GoToTop).is_empty());
+
+        let vim = Keymap::new(&keys_config(KeyPreset::Vim, &[])).unwrap();
+        assert_eq!(vim.keys_for(Action::GoToTop), vec!["g g"]);
+        assert_eq!(vim.keys_for(Action::MoveDown), vec!["j"]);
+
+        let custom = keys_config(
+            KeyPreset::Default,
+            &[("toggle_play", &["Space", "C-p"]), ("help", &[])],
+        );
+        let keymap = Keymap::new(&custom).unwrap();
+        assert_eq!(keymap.keys_for(Action::TogglePlay), vec!["Space", "C-p"]);
+        assert!(keymap.keys_for(Action::Help).is_empty());
+        assert_eq!(keymap.lookup(&[Event::Char('p')]), KeyPress::Unbound);
+    }
+
+    #[test]
+    fn invalid_bindings() {
+        let error = |bindings: &[(&str, &[&str])]| {
+            Keymap::new(&keys_config(KeyPreset::Default, bindings)).unwrap_err()
+        };
+
+        assert!(error(&[("toggle_pley", &["p"])]).contains("toggle_pley"));
+        assert!(error(&[("toggle_play", &["Spcae"])]).contains("Spcae"));
+        // Taken by toggle_queue
+        let conflict = error(&[("toggle_play", &["q"])]);
+        assert!(conflict.contains("toggle_queue") && conflict.contains("toggle_play"));
+        // The start of cycle_replaygain_mode
+        assert!(error(&[("go_to_top", &["g g"])]).contains("cycle_replaygain_mode"));
+
+        // The song view takes h, and the equalizer takes e from show_equalizer by default
+        let shadowed = error(&[("toggle_play", &["h"])]);
+        assert!(shadowed.contains("toggle_loved") && shadowed.contains("toggle_play"));
+        assert!(error(&[("reset_band", &["p"])]).contains("toggle_play"));
+        assert!(error(&[("reset_band", &["n"])]).contains("choose_eq_preset"));
+        // Different views can share keys
+        let keymap = Keymap::new(&keys_config(KeyPreset::Default, &[("rate_1", &["r"])]));
+        assert!(keymap.is_ok());
+    }
+
+    #[test]
+    fn view_actions() {
+        let keymap = Keymap::new(&KeysConfig::default()).unwrap();
+        let e = [Event::Char('e')];
+        assert_eq!(keymap.lookup(&e), KeyPress::Action(Action::ShowEqualizer));
+        assert_eq!(
+            keymap.lookup_among(&e, |action| action.view() == Some(ActionView::Equalizer)),
+            KeyPress::Action(Action::ToggleEqualizer)
+        );
+        // Only the song view rates songs
+        assert_eq!(keymap.lookup(&[Event::Char('3')]), KeyPress::Unbound);
+        assert_eq!(Action::RateSelection(3).group(), ActionGroup::View);
+        assert_eq!(Action::from_name("rate_3"), Some(Action::RateSelection(3)));
+    }
+
+    #[test]
+    fn key_sequences() {
+        let keymap = Keymap::new(&keys_config(KeyPreset::Vim, &[])).unwrap();
+        let mut pending = PendingKeys::default();
+        let start = Instant::now();
+        let g = Event::Char('g');
+
+        assert_eq!(pending.press(&keymap, &g, start), KeyPress::Pending);
+        assert_eq!(
+            pending.press(&keymap, &g, start),
+            KeyPress::Action(Action::GoToTop)
+        );
+
+        // Too slow, the second g starts a new sequence
+        let late = start + Duration::from_millis(SEQUENCE_TIMEOUT_MS + 1);
+        assert_eq!(pending.press(&keymap, &g, start), KeyPress::Pending);
+        assert_eq!(pending.press(&keymap, &g, late), KeyPress::Pending);
+
+        // A key that doesn't continue the sequence counts on its own
+        assert_eq!(
+            pending.press(&keymap, &Event::Char('v'), late),
+            KeyPress::Action(Action::ShowViewChooser)
+        );
+        assert!(pending.keys.is_empty());
+
+        pending.clear();
+        assert_eq!(
+            pending.press(&keymap, &Event::Char('z'), start),
+            KeyPress::Unbound
+        );
+        assert_eq!(
+            pending.press(&keymap, &Event::Char('j'), start),
+            KeyPress::Action(Action::MoveDown)
+        );
+    }
+}
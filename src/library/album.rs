@@ -85,6 +85,7 @@ mod test {
                 artist: Some("George".to_string()),
                 album: None,
                 track_num: None,
+                ..Default::default()
             },
             Track {
                 id: None,
@@ -93,6 +94,7 @@ mod test {
                 artist: Some("George".to_string()),
                 album: None,
                 track_num: None,
+                ..Default::default()
             },
         ];
         let mut conn = rusqlite::Connection::open_in_memory().unwrap();
@@ -0,0 +1,137 @@
+use chrono::Duration;
+use rusqlite::{named_params, Connection, Row};
+
+use crate::library::db::get_library_db;
+
+/// A named position within a track
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bookmark {
+    pub id: Option<i32>,
+    pub track_id: i32,
+    pub name: String,
+    pub position: Duration,
+}
+
+impl Bookmark {
+    pub fn from_db_row(row: &Row) -> rusqlite::Result<Self> {
+        let id = row.get_unwrap(row.column_index("id")?);
+        let track_id = row.get_unwrap(row.column_index("track_id")?);
+        let name = row.get_unwrap(row.column_index("name")?);
+        let position_ms: i64 = row.get_unwrap(row.column_index("position_ms")?);
+
+        Ok(Bookmark {
+            id,
+            track_id,
+            name,
+            position: Duration::milliseconds(position_ms),
+        })
+    }
+
+    pub fn new(track_id: i32, name: &str, position: Duration) -> Self {
+        Self {
+            id: None,
+            track_id,
+            name: name.to_string(),
+            position,
+        }
+    }
+}
+
+// Database interactions
+impl Bookmark {
+    pub fn save(&mut self) {
+        let conn = get_library_db().unwrap();
+        self.save_with_conn(&conn);
+    }
+
+    pub fn save_with_conn(&mut self, conn: &Connection) {
+        conn.execute_named(
+            "INSERT OR REPLACE INTO bookmarks (id, track_id, name, position_ms)
+                VALUES (:id, :track_id, :name, :position_ms)",
+            named_params! {
+                ":id": self.id,
+                ":track_id": self.track_id,
+                ":name": self.name,
+                ":position_ms": self.position.num_milliseconds(),
+            },
+        )
+        .unwrap();
+
+        self.id = Some(conn.last_insert_rowid() as i32);
+    }
+
+    pub fn delete(&self) {
+        let conn = get_library_db().unwrap();
+        self.delete_with_conn(&conn);
+    }
+
+    pub fn delete_with_conn(&self, conn: &Connection) {
+        conn.execute_named(
+            "DELETE FROM bookmarks WHERE id = :id",
+            named_params! {":id": self.id},
+        )
+        .unwrap();
+    }
+
+    /// All bookmarks of a track, in the order they appear in the song
+    pub fn for_track(track_id: i32) -> Vec<Self> {
+        let conn = get_library_db().unwrap();
+        Self::for_track_with_conn(&conn, track_id)
+    }
+
+    pub fn for_track_with_conn(conn: &Connection, track_id: i32) -> Vec<Self> {
+        let mut statement = conn
+            .prepare(
+                "SELECT * FROM bookmarks
+                    WHERE track_id = :track_id
+                    ORDER BY position_ms",
+            )
+            .unwrap();
+
+        let bookmarks: Result<Vec<_>, _> = statement
+            .query_map_named(named_params! {":track_id": track_id}, |row| {
+                Self::from_db_row(row)
+            })
+            .unwrap()
+            .collect();
+
+        bookmarks.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::library::db::run_migrations;
+    use crate::library::Track;
+
+    #[test]
+    fn track_bookmarks() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        let mut track = Track {
+            path: "/tmp/test1.mp3".into(),
+            ..Default::default()
+        };
+        track.save_with_conn(&conn);
+        let track_id = track.id.unwrap();
+
+        let mut solo = Bookmark::new(track_id, "Solo", Duration::seconds(95));
+        let mut bridge = Bookmark::new(track_id, "Bridge", Duration::milliseconds(61500));
+        solo.save_with_conn(&conn);
+        bridge.save_with_conn(&conn);
+
+        assert_eq!(
+            Bookmark::for_track_with_conn(&conn, track_id),
+            vec![bridge.clone(), solo.clone()]
+        );
+        assert!(Bookmark::for_track_with_conn(&conn, track_id + 1).is_empty());
+
+        // Saving again updates the bookmark instead of adding another one
+        solo.position = Duration::seconds(90);
+        solo.save_with_conn(&conn);
+        bridge.delete_with_conn(&conn);
+        assert_eq!(Bookmark::for_track_with_conn(&conn, track_id), vec![solo]);
+    }
+}
@@ -1,6 +1,6 @@
 use std::fs;
 
-use rusqlite::Connection;
+use rusqlite::{Connection, NO_PARAMS};
 
 use refinery::embed_migrations;
 
@@ -24,4 +24,23 @@ pub fn run_migrations(conn: &mut Connection) {
     self::migrations::runner().run(conn).unwrap();
 }
 
+/// Create the database or bring it up to date, before anything else uses it
+pub fn init_library_db() {
+    let mut conn = get_library_db().unwrap();
+    run_migrations(&mut conn);
+}
+
+/// Problems SQLite finds in the database file, empty if it is intact
+pub fn check_integrity(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut statement = conn.prepare("PRAGMA integrity_check")?;
+    let messages = statement
+        .query_map(NO_PARAMS, |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(messages
+        .into_iter()
+        .filter(|message| message != "ok")
+        .collect())
+}
+
 embed_migrations!("./migrations");
@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+
+use rusqlite::{named_params, Connection, Row, NO_PARAMS};
+
+use crate::library::db::get_library_db;
+
+/// Number of bands of the equalizer-10bands element
+pub const NUM_BANDS: usize = 10;
+
+/// Gains of the equalizer bands in dB, from the lowest band to the highest
+pub type EqGains = [f64; NUM_BANDS];
+
+const BUILTIN_PRESETS: [(&str, EqGains); 8] = [
+    ("Flat", [0.0; NUM_BANDS]),
+    ("Rock", [5.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 3.0, 4.0, 5.0]),
+    (
+        "Pop",
+        [-1.0, 1.0, 3.0, 4.0, 4.0, 2.0, 0.0, -1.0, -1.0, -1.0],
+    ),
+    ("Jazz", [3.0, 2.0, 1.0, 2.0, -1.0, -1.0, 0.0, 1.0, 2.0, 3.0]),
+    (
+        "Classical",
+        [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -2.0, -4.0, -4.0, -6.0],
+    ),
+    (
+        "Electronic",
+        [5.0, 4.0, 1.0, 0.0, -2.0, 2.0, 1.0, 1.0, 4.0, 5.0],
+    ),
+    (
+        "Bass Boost",
+        [7.0, 6.0, 5.0, 3.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    ),
+    (
+        "Treble Boost",
+        [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 3.0, 5.0, 6.0, 7.0],
+    ),
+];
+
+/// What an automatic preset selection is keyed on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EqAutoKind {
+    Album,
+    Genre,
+}
+
+impl EqAutoKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Album => "album",
+            Self::Genre => "genre",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EqPreset {
+    /// `None` for the built-in presets and for presets that haven't been saved yet
+    pub id: Option<i32>,
+    pub name: String,
+    pub gains: EqGains,
+}
+
+fn parse_gains(gains_str: &str) -> Option<EqGains> {
+    let mut gains = [0.0; NUM_BANDS];
+    let mut split = gains_str.split_whitespace();
+    for gain in gains.iter_mut() {
+        *gain = split.next()?.parse().ok()?;
+    }
+    Some(gains)
+}
+
+fn format_gains(gains: &EqGains) -> String {
+    gains
+        .iter()
+        .map(|gain| gain.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl EqPreset {
+    pub fn from_db_row(row: &Row) -> rusqlite::Result<Self> {
+        let id = row.get_unwrap(row.column_index("id")?);
+        let name = row.get_unwrap(row.column_index("name")?);
+        let gains_str: String = row.get_unwrap(row.column_index("gains")?);
+        let gains = parse_gains(&gains_str).unwrap_or([0.0; NUM_BANDS]);
+
+        Ok(EqPreset { id, name, gains })
+    }
+
+    pub fn new(name: &str, gains: EqGains) -> Self {
+        Self {
+            id: None,
+            name: name.to_string(),
+            gains,
+        }
+    }
+
+    pub fn builtin() -> Vec<Self> {
+        BUILTIN_PRESETS
+            .iter()
+            .map(|(name, gains)| Self::new(name, *gains))
+            .collect()
+    }
+
+    pub fn is_builtin(name: &str) -> bool {
+        BUILTIN_PRESETS
+            .iter()
+            .any(|(builtin, _)| builtin.eq_ignore_ascii_case(name))
+    }
+}
+
+// Database interactions
+impl EqPreset {
+    /// The built-in presets followed by the ones saved by the user
+    pub fn all() -> Vec<Self> {
+        match get_library_db() {
+            Some(conn) => Self::all_with_conn(&conn),
+            None => Self::builtin(),
+        }
+    }
+
+    pub fn all_with_conn(conn: &Connection) -> Vec<Self> {
+        let mut presets = Self::builtin();
+        presets.extend(Self::iter_saved_with_conn(conn));
+        presets
+    }
+
+    pub fn iter_saved_with_conn(conn: &Connection) -> impl Iterator<Item = Self> {
+        let presets: rusqlite::Result<VecDeque<Self>> = conn
+            .prepare("SELECT * FROM eq_presets ORDER BY name COLLATE NOCASE")
+            .and_then(|mut statement| {
+                statement
+                    .query_map(NO_PARAMS, |row| Self::from_db_row(row))?
+                    .collect()
+            });
+
+        presets.unwrap_or_default().into_iter()
+    }
+
+    pub fn get_by_name_with_conn(conn: &Connection, name: &str) -> Option<Self> {
+        Self::all_with_conn(conn)
+            .into_iter()
+            .find(|preset| preset.name == name)
+    }
+
+    /// Whether the gains differ from the ones stored under the preset's name
+    pub fn is_modified(&self) -> bool {
+        match get_library_db() {
+            Some(conn) => self.is_modified_with_conn(&conn),
+            None => true,
+        }
+    }
+
+    pub fn is_modified_with_conn(&self, conn: &Connection) -> bool {
+        Self::get_by_name_with_conn(conn, &self.name)
+            .map_or(true, |saved| saved.gains != self.gains)
+    }
+
+    /// Saving a preset with the name of an existing one overwrites it
+    pub fn save(&mut self) {
+        let conn = get_library_db().unwrap();
+        self.save_with_conn(&conn);
+    }
+
+    pub fn save_with_conn(&mut self, conn: &Connection) {
+        if let Err(e) = conn.execute_named(
+            "INSERT OR REPLACE INTO eq_presets (name, gains)
+                VALUES (:name, :gains)",
+            named_params! {":name": self.name, ":gains": format_gains(&self.gains)},
+        ) {
+            log::warn!("Could not save equalizer preset {}: {}", self.name, e);
+            return;
+        }
+
+        self.id = Some(conn.last_insert_rowid() as i32);
+    }
+
+    pub fn delete(&self) {
+        let conn = get_library_db().unwrap();
+        self.delete_with_conn(&conn);
+    }
+
+    pub fn delete_with_conn(&self, conn: &Connection) {
+        conn.execute_named(
+            "DELETE FROM eq_presets WHERE name = :name",
+            named_params! {":name": self.name},
+        )
+        .unwrap();
+        conn.execute_named(
+            "DELETE FROM eq_auto_presets WHERE preset = :name",
+            named_params! {":name": self.name},
+        )
+        .unwrap();
+    }
+
+    /// Use the preset automatically for all songs of an album or genre
+    pub fn set_auto(kind: EqAutoKind, key: &str, preset: &str) {
+        let conn = get_library_db().unwrap();
+        Self::set_auto_with_conn(&conn, kind, key, preset);
+    }
+
+    pub fn set_auto_with_conn(conn: &Connection, kind: EqAutoKind, key: &str, preset: &str) {
+        conn.execute_named(
+            "INSERT OR REPLACE INTO eq_auto_presets (kind, key, preset)
+                VALUES (:kind, :key, :preset)",
+            named_params! {":kind": kind.as_str(), ":key": key, ":preset": preset},
+        )
+        .unwrap();
+    }
+
+    /// Find the preset to use for a song, a preset for the album wins over one for the genre.
+    /// Albums and genres are matched ignoring case.
+    pub fn get_auto(album: Option<&str>, genre: Option<&str>) -> Option<Self> {
+        let conn = get_library_db()?;
+        Self::get_auto_with_conn(&conn, album, genre)
+    }
+
+    pub fn get_auto_with_conn(
+        conn: &Connection,
+        album: Option<&str>,
+        genre: Option<&str>,
+    ) -> Option<Self> {
+        let lookup = |kind: EqAutoKind, key: &str| -> Option<String> {
+            conn.query_row_named(
+                "SELECT preset FROM eq_auto_presets
+                    WHERE kind = :kind AND key = :key COLLATE NOCASE",
+                named_params! {":kind": kind.as_str(), ":key": key},
+                |row| row.get(0),
+            )
+            .ok()
+        };
+
+        let name = album
+            .and_then(|album| lookup(EqAutoKind::Album, album))
+            .or_else(|| genre.and_then(|genre| lookup(EqAutoKind::Genre, genre)))?;
+        Self::get_by_name_with_conn(conn, &name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::library::db::run_migrations;
+
+    #[test]
+    fn save_and_auto_select_presets() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        assert_eq!(EqPreset::all_with_conn(&conn).len(), BUILTIN_PRESETS.len());
+
+        let mut gains = [0.0; NUM_BANDS];
+        gains[0] = 3.5;
+        gains[9] = -2.0;
+        let mut preset = EqPreset::new("Mine", gains);
+        preset.save_with_conn(&conn);
+        assert_eq!(
+            EqPreset::get_by_name_with_conn(&conn, "Mine")
+                .unwrap()
+                .gains,
+            gains
+        );
+        assert!(!preset.is_modified_with_conn(&conn));
+        preset.gains[0] = 4.0;
+        assert!(preset.is_modified_with_conn(&conn));
+        preset.gains[0] = 3.5;
+
+        EqPreset::set_auto_with_conn(&conn, EqAutoKind::Genre, "Rock", "Rock");
+        EqPreset::set_auto_with_conn(&conn, EqAutoKind::Album, "Abbey Road", "Mine");
+        let auto = |album, genre| {
+            EqPreset::get_auto_with_conn(&conn, album, genre).map(|preset| preset.name)
+        };
+        assert_eq!(auto(None, Some("rock")), Some("Rock".to_string()));
+        assert_eq!(
+            auto(Some("Abbey Road"), Some("Rock")),
+            Some("Mine".to_string())
+        );
+        assert_eq!(auto(Some("Help!"), Some("Rock")), Some("Rock".to_string()));
+        assert_eq!(auto(Some("Help!"), None), None);
+
+        // Deleting a preset also stops it from being selected automatically
+        preset.delete_with_conn(&conn);
+        assert!(EqPreset::get_by_name_with_conn(&conn, "Mine").is_none());
+        assert_eq!(auto(Some("Abbey Road"), None), None);
+    }
+
+    #[test]
+    fn gains_round_trip() {
+        let gains = BUILTIN_PRESETS[1].1;
+        assert_eq!(parse_gains(&format_gains(&gains)), Some(gains));
+        assert_eq!(parse_gains("1 2 3"), None);
+    }
+}
@@ -0,0 +1,552 @@
+use std::fmt;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Local, TimeZone, Timelike, Utc};
+use rusqlite::{named_params, Connection, Row};
+
+use crate::library::db::get_library_db;
+use crate::library::Track;
+
+/// One time a track was played, from the moment it started until something else played.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayRecord {
+    pub id: Option<i32>,
+    pub track_id: i32,
+    pub started_at: DateTime<Utc>,
+    /// How long the song was actually playing, pauses don't count
+    pub listened: Duration,
+    pub duration: Option<Duration>,
+    /// The song played until the end
+    pub completed: bool,
+    /// The song was cut off before the skip threshold
+    pub skipped: bool,
+}
+
+impl PlayRecord {
+    pub fn from_db_row(row: &Row) -> rusqlite::Result<Self> {
+        let id = row.get_unwrap(row.column_index("id")?);
+        let track_id = row.get_unwrap(row.column_index("track_id")?);
+        let started_at: i64 = row.get_unwrap(row.column_index("started_at")?);
+        let listened_ms: i64 = row.get_unwrap(row.column_index("listened_ms")?);
+        let duration_ms: Option<i64> = row.get_unwrap(row.column_index("duration_ms")?);
+        let completed = row.get_unwrap(row.column_index("completed")?);
+        let skipped = row.get_unwrap(row.column_index("skipped")?);
+
+        Ok(PlayRecord {
+            id,
+            track_id,
+            started_at: Utc.timestamp(started_at, 0),
+            listened: Duration::milliseconds(listened_ms),
+            duration: duration_ms.map(Duration::milliseconds),
+            completed,
+            skipped,
+        })
+    }
+
+    pub fn save(&mut self) {
+        let conn = get_library_db().unwrap();
+        self.save_with_conn(&conn);
+    }
+
+    pub fn save_with_conn(&mut self, conn: &Connection) {
+        conn.execute_named(
+            "INSERT INTO play_history
+                (track_id, started_at, listened_ms, duration_ms, completed, skipped)
+                VALUES (:track_id, :started_at, :listened_ms, :duration_ms, :completed, :skipped)",
+            named_params! {
+                ":track_id": self.track_id,
+                ":started_at": self.started_at.timestamp(),
+                ":listened_ms": self.listened.num_milliseconds(),
+                ":duration_ms": self.duration.map(|duration| duration.num_milliseconds()),
+                ":completed": self.completed,
+                ":skipped": self.skipped,
+            },
+        )
+        .unwrap_or_else(|e| {
+            log::warn!("Could not add play to the history: {}", e);
+            0
+        });
+
+        self.id = Some(conn.last_insert_rowid() as i32);
+    }
+
+    /// Every play since a point in time, oldest first
+    pub fn since_with_conn(conn: &Connection, since: DateTime<Utc>) -> Vec<Self> {
+        let mut statement = conn
+            .prepare(
+                "SELECT * FROM play_history
+                    WHERE started_at >= :since
+                    ORDER BY started_at",
+            )
+            .unwrap();
+
+        let records: Result<Vec<_>, _> = statement
+            .query_map_named(named_params! {":since": since.timestamp()}, |row| {
+                Self::from_db_row(row)
+            })
+            .unwrap()
+            .collect();
+
+        records.unwrap_or_default()
+    }
+}
+
+/// The library id of a file, for songs that were queued by path. Returns `None` for files that
+/// aren't in the library.
+pub fn get_track_id(path: &Path) -> Option<i32> {
+    let conn = get_library_db()?;
+    conn.query_row_named(
+        "SELECT id FROM tracks WHERE path_ = :path",
+        named_params! {":path": path.to_str()},
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+/// The play counts of every track from the plays since `:since`. Skipped plays only count as
+/// skips.
+const TRACK_STATS_SQL: &str = "
+    SELECT
+        track_id,
+        SUM(CASE WHEN skipped THEN 0 ELSE 1 END) AS play_count,
+        SUM(CASE WHEN skipped THEN 1 ELSE 0 END) AS skip_count,
+        MAX(started_at) AS last_played,
+        SUM(listened_ms) AS listened_ms
+    FROM play_history
+    WHERE started_at >= :since
+    GROUP BY track_id";
+
+/// Play counts of a track, derived from the play history
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackStats {
+    pub play_count: i64,
+    pub skip_count: i64,
+    pub last_played: Option<DateTime<Utc>>,
+    pub listened: Duration,
+}
+
+impl TrackStats {
+    fn from_db_row(row: &Row) -> rusqlite::Result<Self> {
+        let play_count: Option<i64> = row.get(row.column_index("play_count")?)?;
+        let skip_count: Option<i64> = row.get(row.column_index("skip_count")?)?;
+        let last_played: Option<i64> = row.get(row.column_index("last_played")?)?;
+        let listened_ms: Option<i64> = row.get(row.column_index("listened_ms")?)?;
+
+        Ok(TrackStats {
+            play_count: play_count.unwrap_or(0),
+            skip_count: skip_count.unwrap_or(0),
+            last_played: last_played.map(|last_played| Utc.timestamp(last_played, 0)),
+            listened: Duration::milliseconds(listened_ms.unwrap_or(0)),
+        })
+    }
+
+    pub fn for_track(track_id: i32) -> Self {
+        let conn = get_library_db().unwrap();
+        Self::for_track_with_conn(&conn, track_id)
+    }
+
+    pub fn for_track_with_conn(conn: &Connection, track_id: i32) -> Self {
+        conn.query_row_named(
+            &format!(
+                "SELECT * FROM ({}) WHERE track_id = :track_id",
+                TRACK_STATS_SQL
+            ),
+            named_params! {":since": 0, ":track_id": track_id},
+            |row| Self::from_db_row(row),
+        )
+        .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrackOrder {
+    PlayCount,
+    SkipCount,
+    LastPlayed,
+    ListenTime,
+}
+
+impl TrackOrder {
+    fn as_sql(self) -> &'static str {
+        match self {
+            Self::PlayCount => "play_count DESC",
+            Self::SkipCount => "skip_count DESC",
+            Self::LastPlayed => "last_played DESC",
+            Self::ListenTime => "listened_ms DESC",
+        }
+    }
+}
+
+/// Which tracks to return from `query_tracks`, the counts only include plays in the period.
+#[derive(Clone, Debug, Default)]
+pub struct TrackFilter {
+    /// Only count plays after this point in time
+    pub since: Option<DateTime<Utc>>,
+    pub min_play_count: Option<i64>,
+    pub min_skip_count: Option<i64>,
+    /// Only tracks that haven't been played in the period at all
+    pub never_played: bool,
+    pub limit: Option<usize>,
+}
+
+/// Tracks together with their play counts, sorted and filtered on the play history.
+pub fn query_tracks(order: TrackOrder, filter: &TrackFilter) -> Vec<(Track, TrackStats)> {
+    let conn = get_library_db().unwrap();
+    query_tracks_with_conn(&conn, order, filter)
+}
+
+pub fn query_tracks_with_conn(
+    conn: &Connection,
+    order: TrackOrder,
+    filter: &TrackFilter,
+) -> Vec<(Track, TrackStats)> {
+    let sql = format!(
+        "SELECT t.*,
+                COALESCE(s.play_count, 0) AS play_count,
+                COALESCE(s.skip_count, 0) AS skip_count,
+                s.last_played AS last_played,
+                COALESCE(s.listened_ms, 0) AS listened_ms
+            FROM tracks t
+            LEFT JOIN ({}) s ON s.track_id = t.id
+            WHERE COALESCE(s.play_count, 0) >= :min_play_count
+                AND COALESCE(s.skip_count, 0) >= :min_skip_count
+                AND (NOT :never_played OR s.track_id IS NULL)
+            ORDER BY {}, t.title COLLATE NOCASE
+            LIMIT :limit",
+        TRACK_STATS_SQL,
+        order.as_sql()
+    );
+
+    let mut statement = conn.prepare(&sql).unwrap();
+    let tracks: Result<Vec<_>, _> = statement
+        .query_map_named(
+            named_params! {
+                ":since": filter.since.map_or(0, |since| since.timestamp()),
+                ":min_play_count": filter.min_play_count.unwrap_or(0),
+                ":min_skip_count": filter.min_skip_count.unwrap_or(0),
+                ":never_played": filter.never_played,
+                ":limit": filter.limit.map_or(-1, |limit| limit as i64),
+            },
+            |row| Ok((Track::from_db_row(row)?, TrackStats::from_db_row(row)?)),
+        )
+        .unwrap()
+        .collect();
+
+    tracks.unwrap_or_default()
+}
+
+/// The periods the listening statistics can be shown for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatsPeriod {
+    Week,
+    Month,
+    Year,
+    AllTime,
+}
+
+impl StatsPeriod {
+    /// Start of the period, `None` for all time
+    pub fn since(self) -> Option<DateTime<Utc>> {
+        let period = match self {
+            Self::Week => Duration::weeks(1),
+            Self::Month => Duration::days(30),
+            Self::Year => Duration::days(365),
+            Self::AllTime => return None,
+        };
+        Some(Utc::now() - period)
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Self::Week => Self::Month,
+            Self::Month => Self::Year,
+            Self::Year | Self::AllTime => Self::AllTime,
+        }
+    }
+
+    pub fn previous(self) -> Self {
+        match self {
+            Self::Week | Self::Month => Self::Week,
+            Self::Year => Self::Month,
+            Self::AllTime => Self::Year,
+        }
+    }
+}
+
+impl fmt::Display for StatsPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Week => "Last week",
+            Self::Month => "Last month",
+            Self::Year => "Last year",
+            Self::AllTime => "All time",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatsGroup {
+    Artist,
+    Album,
+}
+
+impl StatsGroup {
+    fn column(self) -> &'static str {
+        match self {
+            Self::Artist => "t.artist",
+            Self::Album => "t.album",
+        }
+    }
+}
+
+/// Plays of all tracks by an artist or on an album
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupStats {
+    pub name: String,
+    pub play_count: i64,
+    pub listened: Duration,
+}
+
+/// How long music was playing in total since a point in time
+pub fn total_listened(since: Option<DateTime<Utc>>) -> Duration {
+    let conn = get_library_db().unwrap();
+    total_listened_with_conn(&conn, since)
+}
+
+pub fn total_listened_with_conn(conn: &Connection, since: Option<DateTime<Utc>>) -> Duration {
+    let listened_ms: Option<i64> = conn
+        .query_row_named(
+            "SELECT SUM(listened_ms) FROM play_history WHERE started_at >= :since",
+            named_params! {":since": since.map_or(0, |since| since.timestamp())},
+            |row| row.get(0),
+        )
+        .unwrap_or(None);
+
+    Duration::milliseconds(listened_ms.unwrap_or(0))
+}
+
+/// The most played artists or albums, skipped plays don't count.
+pub fn top_groups(
+    group: StatsGroup,
+    since: Option<DateTime<Utc>>,
+    limit: usize,
+) -> Vec<GroupStats> {
+    let conn = get_library_db().unwrap();
+    top_groups_with_conn(&conn, group, since, limit)
+}
+
+pub fn top_groups_with_conn(
+    conn: &Connection,
+    group: StatsGroup,
+    since: Option<DateTime<Utc>>,
+    limit: usize,
+) -> Vec<GroupStats> {
+    let sql = format!(
+        "SELECT {column} AS name,
+                COUNT(*) AS play_count,
+                SUM(h.listened_ms) AS listened_ms
+            FROM play_history h
+            JOIN tracks t ON t.id = h.track_id
+            WHERE h.started_at >= :since
+                AND NOT h.skipped
+                AND {column} NOT NULL
+            GROUP BY {column}
+            ORDER BY play_count DESC, listened_ms DESC, name COLLATE NOCASE
+            LIMIT :limit",
+        column = group.column()
+    );
+
+    let mut statement = conn.prepare(&sql).unwrap();
+    let groups: Result<Vec<_>, _> = statement
+        .query_map_named(
+            named_params! {
+                ":since": since.map_or(0, |since| since.timestamp()),
+                ":limit": limit as i64,
+            },
+            |row| {
+                Ok(GroupStats {
+                    name: row.get(0)?,
+                    play_count: row.get(1)?,
+                    listened: Duration::milliseconds(row.get(2)?),
+                })
+            },
+        )
+        .unwrap()
+        .collect();
+
+    groups.unwrap_or_default()
+}
+
+/// Listening time per hour of the day in local time, index 0 is midnight to 1 AM.
+pub fn listening_by_hour(since: Option<DateTime<Utc>>) -> [Duration; 24] {
+    let conn = get_library_db().unwrap();
+    listening_by_hour_with_conn(&conn, since)
+}
+
+pub fn listening_by_hour_with_conn(
+    conn: &Connection,
+    since: Option<DateTime<Utc>>,
+) -> [Duration; 24] {
+    let mut hours = [Duration::zero(); 24];
+    let since = since.unwrap_or_else(|| Utc.timestamp(0, 0));
+    for record in PlayRecord::since_with_conn(conn, since) {
+        let hour = record.started_at.with_timezone(&Local).hour() as usize;
+        hours[hour] = hours[hour] + record.listened;
+    }
+    hours
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::library::db::run_migrations;
+
+    fn add_play(conn: &Connection, track_id: i32, hours_ago: i64, skipped: bool) {
+        PlayRecord {
+            id: None,
+            track_id,
+            started_at: Utc::now() - Duration::hours(hours_ago),
+            listened: Duration::seconds(if skipped { 10 } else { 200 }),
+            duration: Some(Duration::seconds(200)),
+            completed: !skipped,
+            skipped,
+        }
+        .save_with_conn(conn);
+    }
+
+    #[test]
+    fn play_counts() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        let mut tracks = vec![];
+        for name in ["test1", "test2", "test3"].iter() {
+            let mut track = Track {
+                path: format!("/tmp/{}.mp3", name).into(),
+                title: Some(name.to_string()),
+                ..Default::default()
+            };
+            track.save_with_conn(&conn);
+            tracks.push(track.id.unwrap());
+        }
+
+        add_play(&conn, tracks[0], 1, false);
+        add_play(&conn, tracks[0], 2, false);
+        add_play(&conn, tracks[0], 24 * 30, true);
+        add_play(&conn, tracks[1], 3, true);
+
+        let stats = TrackStats::for_track_with_conn(&conn, tracks[0]);
+        assert_eq!(stats.play_count, 2);
+        assert_eq!(stats.skip_count, 1);
+        assert_eq!(stats.listened, Duration::seconds(410));
+        assert_eq!(
+            TrackStats::for_track_with_conn(&conn, tracks[2]),
+            TrackStats::default()
+        );
+
+        let ids = |order, filter: &TrackFilter| {
+            query_tracks_with_conn(&conn, order, filter)
+                .into_iter()
+                .map(|(track, _)| track.id.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(TrackOrder::PlayCount, &TrackFilter::default()),
+            vec![tracks[0], tracks[1], tracks[2]]
+        );
+        let skipped = TrackFilter {
+            min_skip_count: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(TrackOrder::SkipCount, &skipped),
+            vec![tracks[0], tracks[1]]
+        );
+
+        // The old skip falls out of the last week
+        let last_week = TrackFilter {
+            since: Some(Utc::now() - Duration::weeks(1)),
+            min_skip_count: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(ids(TrackOrder::SkipCount, &last_week), vec![tracks[1]]);
+
+        let never_played = TrackFilter {
+            never_played: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(TrackOrder::LastPlayed, &never_played), vec![tracks[2]]);
+        assert_eq!(
+            PlayRecord::since_with_conn(&conn, Utc::now() - Duration::weeks(1)).len(),
+            3
+        );
+    }
+
+    #[test]
+    fn group_stats() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        let mut tracks = vec![];
+        for (name, artist, album) in [
+            ("test1", "George", "First"),
+            ("test2", "George", "Second"),
+            ("test3", "Paul", "Third"),
+        ]
+        .iter()
+        {
+            let mut track = Track {
+                path: format!("/tmp/{}.mp3", name).into(),
+                title: Some(name.to_string()),
+                artist: Some(artist.to_string()),
+                album: Some(album.to_string()),
+                ..Default::default()
+            };
+            track.save_with_conn(&conn);
+            tracks.push(track.id.unwrap());
+        }
+
+        add_play(&conn, tracks[0], 1, false);
+        add_play(&conn, tracks[1], 2, false);
+        add_play(&conn, tracks[2], 3, false);
+        add_play(&conn, tracks[2], 4, true);
+        add_play(&conn, tracks[2], 24 * 30, false);
+
+        let last_week = StatsPeriod::Week.since();
+        let artists = top_groups_with_conn(&conn, StatsGroup::Artist, last_week, 10);
+        assert_eq!(
+            artists,
+            vec![
+                GroupStats {
+                    name: "George".to_string(),
+                    play_count: 2,
+                    listened: Duration::seconds(400),
+                },
+                GroupStats {
+                    name: "Paul".to_string(),
+                    play_count: 1,
+                    listened: Duration::seconds(200),
+                },
+            ]
+        );
+        let albums = top_groups_with_conn(&conn, StatsGroup::Album, None, 1);
+        assert_eq!(albums.len(), 1);
+        assert_eq!(albums[0].name, "Third");
+        assert_eq!(albums[0].play_count, 2);
+
+        assert_eq!(
+            total_listened_with_conn(&conn, last_week),
+            Duration::seconds(610)
+        );
+        assert_eq!(
+            total_listened_with_conn(&conn, None),
+            Duration::seconds(810)
+        );
+        let by_hour = listening_by_hour_with_conn(&conn, None);
+        assert_eq!(
+            by_hour.iter().fold(Duration::zero(), |total, hour| total + *hour),
+            Duration::seconds(810)
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use gst::prelude::*;
+use rusqlite::Connection;
+
+use crate::library::db::get_library_db;
+use crate::library::settings;
+use crate::library::Track;
+
+const WRITE_TAGS_SETTING: &str = "write_gain_tags";
+
+/// Give up on a file once the analysis hasn't moved on for this long
+const STALL_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnalysisMode {
+    /// Only calculate the track gain of every song
+    Track,
+    /// Also calculate the album gain, songs are grouped by their album
+    Album,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Loudness {
+    /// Gain in dB to bring the song to the ReplayGain reference level
+    pub gain: f64,
+    /// Peak sample as a ratio of full scale
+    pub peak: f64,
+    /// Length of the song in seconds
+    pub length: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnalysisProgress {
+    pub done: usize,
+    pub total: usize,
+    pub failed: usize,
+    /// The track that was just analysed, `None` once everything is finished
+    pub current: Option<Track>,
+}
+
+/// Whether the analysis also writes the gain tags into the files. Needs the `write-tags`
+/// feature.
+pub fn get_write_tags() -> bool {
+    settings::get_setting(WRITE_TAGS_SETTING).map_or(false, |value| value == "true")
+}
+
+pub fn set_write_tags(write_tags: bool) {
+    settings::set_setting(
+        WRITE_TAGS_SETTING,
+        if write_tags { "true" } else { "false" },
+    );
+}
+
+/// Measure the loudness of a single file using the rganalysis element.
+pub fn analyse_file(path: &Path) -> Result<Loudness, String> {
+    let uri = crate::player::create_gst_uri(path)
+        .ok_or_else(|| format!("{} can not be played", path.display()))?;
+
+    let pipeline = gst::parse_launch(
+        "uridecodebin name=source ! audioconvert ! audioresample ! rganalysis ! fakesink sync=false",
+    )
+    .map_err(|e| e.to_string())?
+    .downcast::<gst::Bin>()
+    .map_err(|_| "analysis pipeline is not a bin".to_string())?;
+    pipeline
+        .get_by_name("source")
+        .unwrap()
+        .set_property("uri", &uri)
+        .unwrap();
+
+    let bus = pipeline.get_bus().unwrap();
+    pipeline
+        .set_state(gst::State::Playing)
+        .map_err(|e| e.to_string())?;
+
+    let mut gain = None;
+    let mut peak = None;
+    let mut result = Err(format!("no analysis result for {}", path.display()));
+    // The result only shows up at the end, in between check that the pipeline is getting
+    // anywhere so a file that hangs the decoder doesn't hang the whole analysis.
+    let mut last_position = None;
+    let mut last_progress = Instant::now();
+    loop {
+        let msg = match bus.timed_pop_filtered(
+            gst::ClockTime::from_seconds(1),
+            &[
+                gst::MessageType::Tag,
+                gst::MessageType::Eos,
+                gst::MessageType::Error,
+            ],
+        ) {
+            Some(msg) => msg,
+            None => {
+                let position = pipeline.query_position::<gst::ClockTime>();
+                if position != last_position {
+                    last_position = position;
+                    last_progress = Instant::now();
+                } else if last_progress.elapsed() > Duration::from_secs(STALL_TIMEOUT_SECS) {
+                    result = Err(format!("the analysis of {} got stuck", path.display()));
+                    break;
+                }
+                continue;
+            }
+        };
+
+        match msg.view() {
+            gst::MessageView::Tag(tag) => {
+                let tags = tag.get_tags();
+                gain = tags
+                    .get::<gst::tags::TrackGain>()
+                    .and_then(|v| v.get())
+                    .or(gain);
+                peak = tags
+                    .get::<gst::tags::TrackPeak>()
+                    .and_then(|v| v.get())
+                    .or(peak);
+            }
+            gst::MessageView::Eos(..) => {
+                let length = pipeline
+                    .query_duration::<gst::ClockTime>()
+                    .and_then(|ct| ct.mseconds())
+                    .unwrap_or(0) as f64
+                    / 1000.0;
+                if let (Some(gain), Some(peak)) = (gain, peak) {
+                    result = Ok(Loudness { gain, peak, length });
+                }
+                break;
+            }
+            gst::MessageView::Error(err) => {
+                result = Err(err.get_error().to_string());
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    result
+}
+
+/// The album gain and peak for a set of songs from the same album.
+///
+/// rganalysis can only calculate album gain when all songs go through the same pipeline one
+/// after another, instead the loudness of the songs is averaged in the power domain, weighted
+/// by their length. That comes out within a fraction of a dB of the real thing.
+pub fn get_album_loudness(songs: &[Loudness]) -> Option<(f64, f64)> {
+    let total_length: f64 = songs.iter().map(|song| song.length.max(1.0)).sum();
+    if songs.is_empty() {
+        return None;
+    }
+
+    let mean_power = songs
+        .iter()
+        .map(|song| song.length.max(1.0) * 10f64.powf(-song.gain / 10.0))
+        .sum::<f64>()
+        / total_length;
+    let peak = songs.iter().map(|song| song.peak).fold(0.0, f64::max);
+
+    Some((-10.0 * mean_power.log10(), peak))
+}
+
+/// The album and directory of a track, `None` for songs that are analysed on their own
+type AlbumKey = Option<(String, PathBuf)>;
+
+/// Group the tracks by album so the album gain can be calculated as soon as the last song of an
+/// album is done. Albums with the same title are told apart by their directory, grouping by
+/// artist would split up compilations. Songs without an album are on their own.
+fn group_by_album(tracks: &[Track], mode: AnalysisMode) -> BTreeMap<AlbumKey, Vec<usize>> {
+    let mut groups: BTreeMap<AlbumKey, Vec<usize>> = BTreeMap::new();
+    for (idx, track) in tracks.iter().enumerate() {
+        let key = match (mode, &track.album) {
+            (AnalysisMode::Album, Some(album)) => {
+                let dir = track.path.parent().unwrap_or_else(|| Path::new(""));
+                Some((album.clone(), dir.to_path_buf()))
+            }
+            _ => None,
+        };
+        groups.entry(key).or_default().push(idx);
+    }
+    groups
+}
+
+/// Analyse all the tracks and store the results in the library. The tracks are updated with the
+/// results, `on_progress` is called after each one.
+pub fn analyse_tracks<F>(
+    conn: &Connection,
+    tracks: &mut [Track],
+    mode: AnalysisMode,
+    write_tags: bool,
+    mut on_progress: F,
+) -> usize
+where
+    F: FnMut(&AnalysisProgress),
+{
+    let groups = group_by_album(tracks, mode);
+    let mut progress = AnalysisProgress {
+        done: 0,
+        total: tracks.len(),
+        failed: 0,
+        current: None,
+    };
+
+    for (album, indices) in groups.iter() {
+        let mut results = Vec::new();
+        for &idx in indices.iter() {
+            let track = &mut tracks[idx];
+            match analyse_file(&track.path) {
+                Ok(loudness) => {
+                    track.track_gain = Some(loudness.gain);
+                    track.track_peak = Some(loudness.peak);
+                    results.push((idx, loudness));
+                }
+                Err(e) => {
+                    log::warn!("Could not analyse {}: {}", track.path.display(), e);
+                    progress.failed += 1;
+                }
+            }
+
+            progress.done += 1;
+            progress.current = Some(track.clone());
+            on_progress(&progress);
+        }
+
+        let album_loudness = if album.is_some() {
+            get_album_loudness(&results.iter().map(|(_, l)| *l).collect::<Vec<_>>())
+        } else {
+            None
+        };
+
+        for (idx, _) in results.iter() {
+            let track = &mut tracks[*idx];
+            if let Some((gain, peak)) = album_loudness {
+                track.album_gain = Some(gain);
+                track.album_peak = Some(peak);
+            }
+
+            if track.id.is_some() {
+                track.save_with_conn(conn);
+            }
+            if write_tags {
+                if let Err(e) = super::tag_writer::write_gain_tags(track) {
+                    log::warn!("Could not write tags to {}: {}", track.path.display(), e);
+                }
+            }
+        }
+    }
+
+    progress.current = None;
+    on_progress(&progress);
+    progress.failed
+}
+
+/// Run the analysis in a background thread with its own database connection.
+pub fn spawn_analysis<F>(
+    mut tracks: Vec<Track>,
+    mode: AnalysisMode,
+    write_tags: bool,
+    on_progress: F,
+) -> JoinHandle<Vec<Track>>
+where
+    F: FnMut(&AnalysisProgress) + Send + 'static,
+{
+    thread::spawn(move || {
+        let conn = get_library_db().unwrap();
+        analyse_tracks(&conn, &mut tracks, mode, write_tags, on_progress);
+        tracks
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn album_loudness() {
+        assert_eq!(get_album_loudness(&[]), None);
+
+        let song = Loudness {
+            gain: -6.0,
+            peak: 0.5,
+            length: 120.0,
+        };
+        let (gain, peak) = get_album_loudness(&[song, song]).unwrap();
+        assert!((gain - -6.0).abs() < 1e-9);
+        assert_eq!(peak, 0.5);
+
+        // A long loud song dominates a short quiet one
+        let loud = Loudness {
+            gain: -10.0,
+            peak: 1.0,
+            length: 600.0,
+        };
+        let quiet = Loudness {
+            gain: 0.0,
+            peak: 0.25,
+            length: 60.0,
+        };
+        let (gain, peak) = get_album_loudness(&[loud, quiet]).unwrap();
+        assert!(
+            gain < -9.0 && gain > -10.0,
+            "Unexpected album gain {}",
+            gain
+        );
+        assert_eq!(peak, 1.0);
+    }
+
+    #[test]
+    fn albums_are_grouped_by_directory() {
+        let track = |path: &str, album: Option<&str>| Track {
+            path: PathBuf::from(path),
+            album: album.map(|album| album.to_string()),
+            ..Default::default()
+        };
+        let tracks = [
+            track("/music/Beatles/Help!/1.mp3", Some("Help!")),
+            track("/music/Single.mp3", None),
+            track("/music/Oasis/Help!/1.mp3", Some("Help!")),
+            track("/music/Beatles/Help!/2.mp3", Some("Help!")),
+        ];
+
+        let groups = group_by_album(&tracks, AnalysisMode::Album)
+            .into_iter()
+            .map(|(_, indices)| indices)
+            .collect::<Vec<_>>();
+        assert_eq!(groups, vec![vec![1], vec![0, 3], vec![2]]);
+
+        let groups = group_by_album(&tracks, AnalysisMode::Track);
+        assert_eq!(groups.get(&None), Some(&vec![0, 1, 2, 3]));
+    }
+}
@@ -1,50 +1,185 @@
 pub mod db;
+pub mod loudness;
 mod album;
+mod bookmark;
+mod eq_preset;
+pub mod history;
+pub mod playback_speed;
+pub mod rating;
+pub mod scrobble;
+pub mod settings;
+mod tag_writer;
 mod track;
 mod tracked_path;
 
+use std::collections::HashSet;
 use std::path::PathBuf;
 
+use rusqlite::Connection;
+
 pub use album::Album;
-pub use track::Track;
+pub use bookmark::Bookmark;
+pub use eq_preset::{EqAutoKind, EqGains, EqPreset, NUM_BANDS};
+pub use track::{Track, TrackField};
 pub use tracked_path::TrackedPath;
 
+/// What a scan of the library changed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScanSummary {
+    pub added: usize,
+    /// Songs whose tags changed, only a full scan reads the tags of known songs
+    pub updated: usize,
+    /// Songs whose files are gone or no longer under a tracked path, only a full scan removes them
+    pub removed: usize,
+}
+
 /// Fast refresh of the library database
 ///
 /// This function can detect new files, but if a file already exists in the database, it doesn't
 /// re-inspect its tags, it just assumes they are correct. This speeds up the library refresh
 /// considerably.
 pub fn fast_refresh_library() {
-    let mut tracked_paths: Vec<PathBuf> = TrackedPath::iter()
-        .map(|tp| tp.path.to_path_buf())
-        .collect();
+    scan_library(false);
+}
 
-    if tracked_paths.is_empty() {
-        return;
+/// Look for songs in the tracked paths. A full scan also re-reads the tags of the songs that are
+/// already in the library, and removes the songs that weren't found. Songs under tracked paths
+/// that are missing or can't be read are kept.
+pub fn scan_library(full: bool) -> ScanSummary {
+    let mut conn = db::get_library_db().unwrap();
+    scan_library_with_conn(&mut conn, full)
+}
+
+pub fn scan_library_with_conn(conn: &mut Connection, full: bool) -> ScanSummary {
+    let mut tracked_paths: Vec<PathBuf> = TrackedPath::iter_with_conn(conn)
+        .map(|tp| tp.path)
+        .collect();
+    // The songs under these weren't looked at, like on a drive that isn't mounted, so even a
+    // full scan keeps them
+    let mut unreadable: Vec<PathBuf> = tracked_paths
+        .iter()
+        .filter(|path| !path.is_dir())
+        .cloned()
+        .collect();
+    for path in unreadable.iter() {
+        log::warn!("Tracked path {} is missing", path.display());
     }
+    let mut summary = ScanSummary::default();
+    let mut found = HashSet::new();
 
-    let mut conn = db::get_library_db().unwrap();
     let transaction = conn.transaction().unwrap();
 
-    loop {
-        let path = match tracked_paths.pop() {
-            Some(path) => path,
-            None => break,
-        };
-
+    while let Some(path) = tracked_paths.pop() {
         if path.is_dir() {
-            for item in path.read_dir().unwrap() {
-                tracked_paths.push(item.unwrap().path());
+            match path.read_dir() {
+                Ok(items) => {
+                    tracked_paths.extend(items.filter_map(Result::ok).map(|item| item.path()))
+                }
+                Err(e) => {
+                    log::warn!("Could not read {}: {}", path.display(), e);
+                    unreadable.push(path);
+                }
             }
         } else if crate::player::is_audio_file_guess(&path) {
-            if let Some(mut track) = Track::from_path_with_conn(&path, &transaction) {
-                // If the track already exists in the database, it will already hae an ID
-                // associated with it. Only save the track if it isn't in the database yet.
-                if track.id.is_none() {
-                    track.save_with_conn(&transaction);
+            let mut track = match Track::from_path_with_conn(&path, &transaction) {
+                Some(track) => track,
+                None => continue,
+            };
+            // If the track already exists in the database, it will already have an ID
+            // associated with it. A fast scan assumes the tags in the database are correct.
+            if track.id.is_none() {
+                track.save_with_conn(&transaction);
+                summary.added += 1;
+            } else if full {
+                if let Some(tags) = Track::from_tags(&path) {
+                    let changed = (&tags.title, &tags.artist, &tags.album, tags.track_num)
+                        != (&track.title, &track.artist, &track.album, track.track_num);
+                    if changed {
+                        track.title = tags.title;
+                        track.artist = tags.artist;
+                        track.album = tags.album;
+                        track.track_num = tags.track_num;
+                        track.save_with_conn(&transaction);
+                        summary.updated += 1;
+                    }
                 }
             }
+            found.insert(path);
+        }
+    }
+
+    if full {
+        for track in Track::iter_with_conn(&transaction) {
+            let skipped = unreadable.iter().any(|path| track.path.starts_with(path));
+            if !found.contains(&track.path) && !skipped {
+                track.delete_with_conn(&transaction);
+                summary.removed += 1;
+            }
         }
     }
     transaction.commit().unwrap();
+
+    summary
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::library::db::run_migrations;
+    use std::fs;
+
+    #[test]
+    fn full_scan_removes_missing_songs() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        let dir = std::env::temp_dir().join(format!("musicom-scan-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TrackedPath {
+            id: None,
+            path: dir.clone(),
+        }
+        .save_with_conn(&conn);
+        Track {
+            path: dir.join("gone.mp3"),
+            title: Some("Gone".to_string()),
+            ..Default::default()
+        }
+        .save_with_conn(&conn);
+
+        assert_eq!(
+            scan_library_with_conn(&mut conn, false),
+            ScanSummary::default()
+        );
+        assert_eq!(Track::get_track_count_with_conn(&conn), 1);
+
+        let summary = scan_library_with_conn(&mut conn, true);
+        assert_eq!(summary.removed, 1);
+        assert_eq!(Track::get_track_count_with_conn(&conn), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn full_scan_keeps_songs_of_missing_paths() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        // Like a drive that isn't mounted
+        let dir = std::env::temp_dir().join(format!("musicom-unmounted-{}", std::process::id()));
+        TrackedPath {
+            id: None,
+            path: dir.clone(),
+        }
+        .save_with_conn(&conn);
+        Track {
+            path: dir.join("still-there.mp3"),
+            title: Some("Still there".to_string()),
+            ..Default::default()
+        }
+        .save_with_conn(&conn);
+
+        let summary = scan_library_with_conn(&mut conn, true);
+        assert_eq!(summary.removed, 0);
+        assert_eq!(Track::get_track_count_with_conn(&conn), 1);
+    }
 }
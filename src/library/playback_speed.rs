@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use rusqlite::{named_params, Connection};
+
+use crate::library::db::get_library_db;
+
+/// What a remembered playback speed applies to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpeedKind {
+    /// A single file, keyed by its path
+    Track,
+    Genre,
+}
+
+impl SpeedKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Track => "track",
+            Self::Genre => "genre",
+        }
+    }
+}
+
+pub fn remember_speed(kind: SpeedKind, key: &str, rate: f64) {
+    let conn = get_library_db().unwrap();
+    remember_speed_with_conn(&conn, kind, key, rate);
+}
+
+pub fn remember_speed_with_conn(conn: &Connection, kind: SpeedKind, key: &str, rate: f64) {
+    conn.execute_named(
+        "INSERT OR REPLACE INTO playback_speeds (kind, key, rate)
+            VALUES (:kind, :key, :rate)",
+        named_params! {":kind": kind.as_str(), ":key": key, ":rate": rate},
+    )
+    .unwrap();
+}
+
+pub fn forget_speed(kind: SpeedKind, key: &str) {
+    let conn = get_library_db().unwrap();
+    forget_speed_with_conn(&conn, kind, key);
+}
+
+pub fn forget_speed_with_conn(conn: &Connection, kind: SpeedKind, key: &str) {
+    conn.execute_named(
+        "DELETE FROM playback_speeds WHERE kind = :kind AND key = :key",
+        named_params! {":kind": kind.as_str(), ":key": key},
+    )
+    .unwrap();
+}
+
+/// The speed to play a song at, a speed remembered for the track wins over one for its genre.
+pub fn get_remembered_speed(path: &Path, genre: Option<&str>) -> Option<f64> {
+    let conn = get_library_db()?;
+    get_remembered_speed_with_conn(&conn, path, genre)
+}
+
+pub fn get_remembered_speed_with_conn(
+    conn: &Connection,
+    path: &Path,
+    genre: Option<&str>,
+) -> Option<f64> {
+    let lookup = |kind: SpeedKind, key: &str| -> Option<f64> {
+        conn.query_row_named(
+            "SELECT rate FROM playback_speeds
+                WHERE kind = :kind AND key = :key",
+            named_params! {":kind": kind.as_str(), ":key": key},
+            |row| row.get(0),
+        )
+        .ok()
+    };
+
+    path.to_str()
+        .and_then(|path| lookup(SpeedKind::Track, path))
+        .or_else(|| genre.and_then(|genre| lookup(SpeedKind::Genre, &genre.to_lowercase())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::library::db::run_migrations;
+
+    #[test]
+    fn remembered_speeds() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        let path = Path::new("/tmp/episode1.mp3");
+        assert_eq!(
+            get_remembered_speed_with_conn(&conn, path, Some("Podcast")),
+            None
+        );
+
+        remember_speed_with_conn(&conn, SpeedKind::Genre, "podcast", 1.5);
+        assert_eq!(
+            get_remembered_speed_with_conn(&conn, path, Some("Podcast")),
+            Some(1.5)
+        );
+
+        remember_speed_with_conn(&conn, SpeedKind::Track, path.to_str().unwrap(), 2.0);
+        assert_eq!(
+            get_remembered_speed_with_conn(&conn, path, Some("Podcast")),
+            Some(2.0)
+        );
+
+        forget_speed_with_conn(&conn, SpeedKind::Track, path.to_str().unwrap());
+        assert_eq!(
+            get_remembered_speed_with_conn(&conn, path, Some("Podcast")),
+            Some(1.5)
+        );
+        assert_eq!(get_remembered_speed_with_conn(&conn, path, None), None);
+    }
+}
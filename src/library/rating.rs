@@ -0,0 +1,348 @@
+//! Star ratings of tracks, and reading and writing them from the tags of the music files.
+//!
+//! Every format has its own way of storing ratings: ID3 uses POPM frames with a rating from 1 to
+//! 255, Vorbis comments use FMPS_RATING with a rating from 0.0 to 1.0 and MP4 uses the iTunes
+//! `rate` atom with a rating from 0 to 100. POPM frames are read and written straight from the
+//! ID3v2 tag, the others go through the TagLib property interface and need the `write-tags`
+//! feature.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::library::settings;
+use crate::library::tag_writer;
+use crate::library::Track;
+
+pub const MAX_RATING: u8 = 5;
+
+const WRITE_TAGS_SETTING: &str = "write_rating_tags";
+
+/// The email Windows Media Player writes into the POPM frame, most players look for this one.
+const POPM_EMAIL: &[u8] = b"Windows Media Player 9 Series";
+
+/// Whether changing a rating also writes it into the tags of the file.
+pub fn get_write_tags() -> bool {
+    settings::get_setting(WRITE_TAGS_SETTING).map_or(false, |value| value == "true")
+}
+
+pub fn set_write_tags(write_tags: bool) {
+    settings::set_setting(
+        WRITE_TAGS_SETTING,
+        if write_tags { "true" } else { "false" },
+    );
+}
+
+/// Store the rating and loved flag of a track in the library, and in the tags of the file if
+/// that is turned on. The library is always updated, even if writing the tags fails.
+pub fn save_rating(track: &Track) -> Result<(), String> {
+    track.save_rating();
+    if get_write_tags() {
+        write_rating_tags(track).map_err(|e| {
+            format!(
+                "Could not write the rating to {}: {}",
+                track.path.display(),
+                e
+            )
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Read the rating from the tags of a file, `None` if the file isn't rated.
+pub fn read_rating_tags(path: &Path) -> Option<u8> {
+    if let Some(rating) = read_popm(path) {
+        return Some(stars_from_popm(rating)).filter(|stars| *stars > 0);
+    }
+
+    let rating = match tag_writer::read_property(path, "FMPS_RATING") {
+        Some(value) => stars_from_fmps(value.trim().parse().ok()?),
+        None => stars_from_itunes(
+            tag_writer::read_property(path, "RATING")?
+                .trim()
+                .parse()
+                .ok()?,
+        ),
+    };
+    Some(rating).filter(|stars| *stars > 0)
+}
+
+fn write_rating_tags(track: &Track) -> Result<(), String> {
+    if let Some(result) = write_popm(&track.path, stars_to_popm(track.rating)) {
+        return result.map_err(|e| e.to_string());
+    }
+
+    let is_mp4 = track
+        .path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            ["m4a", "mp4", "m4b", "aac"].contains(&ext.to_lowercase().as_str())
+        });
+    if is_mp4 {
+        tag_writer::write_property(track, "RATING", stars_to_itunes(track.rating).to_string())
+    } else {
+        tag_writer::write_property(track, "FMPS_RATING", stars_to_fmps(track.rating))
+    }
+}
+
+/// The ranges other players use, so 1 star written by anyone reads back as 1 star.
+fn stars_from_popm(rating: u8) -> u8 {
+    match rating {
+        0 => 0,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    }
+}
+
+fn stars_to_popm(stars: u8) -> u8 {
+    match stars {
+        0 => 0,
+        1 => 1,
+        2 => 64,
+        3 => 128,
+        4 => 196,
+        _ => 255,
+    }
+}
+
+fn stars_from_fmps(rating: f64) -> u8 {
+    (rating.max(0.0).min(1.0) * MAX_RATING as f64).round() as u8
+}
+
+fn stars_to_fmps(stars: u8) -> String {
+    format!("{:.1}", stars.min(MAX_RATING) as f64 / MAX_RATING as f64)
+}
+
+fn stars_from_itunes(rating: u32) -> u8 {
+    ((rating.min(100) as f64) / 20.0).round() as u8
+}
+
+fn stars_to_itunes(stars: u8) -> u32 {
+    stars.min(MAX_RATING) as u32 * 20
+}
+
+/// Where the rating goes in an ID3v2 tag, all offsets are from the start of the tag.
+#[derive(Debug, PartialEq)]
+struct PopmSlot {
+    major_version: u8,
+    /// The rating byte of the first POPM frame
+    rating_offset: Option<usize>,
+    /// Start and length of the padding after the last frame
+    padding: (usize, usize),
+}
+
+fn read_id3v2_tag(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut header = [0; 10];
+    file.read_exact(&mut header)?;
+    if &header[..3] != b"ID3" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no ID3v2 tag"));
+    }
+
+    let mut tag = header.to_vec();
+    tag.resize(10 + synchsafe(&header[6..10]), 0);
+    file.read_exact(&mut tag[10..])?;
+    Ok(tag)
+}
+
+fn synchsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, byte| (size << 7) | (*byte & 0x7f) as usize)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, byte| (size << 8) | *byte as usize)
+}
+
+fn to_synchsafe(size: usize) -> [u8; 4] {
+    [
+        (size >> 21) as u8 & 0x7f,
+        (size >> 14) as u8 & 0x7f,
+        (size >> 7) as u8 & 0x7f,
+        size as u8 & 0x7f,
+    ]
+}
+
+/// Walk the frames of an ID3v2.3 or 2.4 tag. Returns `None` for tags we can't safely change in
+/// place, like ID3v2.2 and unsynchronised tags.
+fn find_popm(tag: &[u8]) -> Option<PopmSlot> {
+    let major_version = tag[3];
+    let flags = tag[5];
+    if !(major_version == 3 || major_version == 4) || flags & 0x80 != 0 {
+        return None;
+    }
+
+    let mut offset = 10;
+    if flags & 0x40 != 0 {
+        // Extended header, its size doesn't include the size field itself in ID3v2.3
+        let size_bytes = tag.get(offset..offset + 4)?;
+        offset += match major_version {
+            3 => big_endian(size_bytes) + 4,
+            _ => synchsafe(size_bytes),
+        };
+    }
+
+    let mut rating_offset = None;
+    while offset + 10 <= tag.len() && tag[offset] != 0 {
+        let id = &tag[offset..offset + 4];
+        let size_bytes = &tag[offset + 4..offset + 8];
+        let size = match major_version {
+            3 => big_endian(size_bytes),
+            _ => synchsafe(size_bytes),
+        };
+        let body = offset + 10;
+        if body + size > tag.len() {
+            return None;
+        }
+
+        if id == b"POPM" && rating_offset.is_none() {
+            let email_len = tag[body..body + size].iter().position(|byte| *byte == 0)?;
+            if email_len + 1 < size {
+                rating_offset = Some(body + email_len + 1);
+            }
+        }
+        offset = body + size;
+    }
+
+    Some(PopmSlot {
+        major_version,
+        rating_offset,
+        padding: (offset, tag.len().saturating_sub(offset)),
+    })
+}
+
+fn new_popm_frame(major_version: u8, rating: u8) -> Vec<u8> {
+    let size = POPM_EMAIL.len() + 2;
+    let mut frame = b"POPM".to_vec();
+    match major_version {
+        3 => frame.extend_from_slice(&(size as u32).to_be_bytes()),
+        _ => frame.extend_from_slice(&to_synchsafe(size)),
+    }
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(POPM_EMAIL);
+    frame.push(0);
+    frame.push(rating);
+    frame
+}
+
+/// The raw POPM rating, `None` if the file has no ID3v2 tag or no POPM frame in it.
+fn read_popm(path: &Path) -> Option<u8> {
+    let tag = read_id3v2_tag(&mut File::open(path).ok()?).ok()?;
+    let slot = find_popm(&tag)?;
+    slot.rating_offset.map(|offset| tag[offset])
+}
+
+/// Change the rating in an existing POPM frame, or add one in the padding of the tag. Returns
+/// `None` if the file doesn't have an ID3v2 tag.
+fn write_popm(path: &Path, rating: u8) -> Option<io::Result<()>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path).ok()?;
+    let tag = read_id3v2_tag(&mut file).ok()?;
+
+    Some(patch_popm(&mut file, &tag, rating))
+}
+
+fn patch_popm(file: &mut File, tag: &[u8], rating: u8) -> io::Result<()> {
+    let slot = find_popm(tag)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unsupported ID3v2 tag"))?;
+
+    if let Some(offset) = slot.rating_offset {
+        file.seek(SeekFrom::Start(offset as u64))?;
+        return file.write_all(&[rating]);
+    }
+
+    let frame = new_popm_frame(slot.major_version, rating);
+    let (padding_start, padding_len) = slot.padding;
+    if frame.len() > padding_len {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "not enough room in the ID3v2 tag for a rating",
+        ));
+    }
+    file.seek(SeekFrom::Start(padding_start as u64))?;
+    file.write_all(&frame)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_tag(major_version: u8, frames: &[Vec<u8>], padding: usize) -> Vec<u8> {
+        let body: Vec<u8> = frames.concat();
+        let mut tag = b"ID3".to_vec();
+        tag.extend_from_slice(&[major_version, 0, 0]);
+        tag.extend_from_slice(&to_synchsafe(body.len() + padding));
+        tag.extend_from_slice(&body);
+        tag.resize(tag.len() + padding, 0);
+        tag
+    }
+
+    fn text_frame(id: &[u8], text: &str) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0]);
+        frame.extend_from_slice(text.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn rating_conversions() {
+        for stars in 0..=MAX_RATING {
+            assert_eq!(stars_from_popm(stars_to_popm(stars)), stars);
+            assert_eq!(
+                stars_from_fmps(stars_to_fmps(stars).parse().unwrap()),
+                stars
+            );
+            assert_eq!(stars_from_itunes(stars_to_itunes(stars)), stars);
+        }
+
+        assert_eq!(stars_from_popm(255), 5);
+        assert_eq!(stars_from_popm(186), 4);
+        assert_eq!(stars_from_fmps(0.7), 4);
+        assert_eq!(stars_from_fmps(2.0), 5);
+        assert_eq!(stars_from_itunes(60), 3);
+    }
+
+    #[test]
+    fn find_popm_frames() {
+        let title = text_frame(b"TIT2", "Test 1: The Intro");
+        let tag = build_tag(3, &[title.clone(), new_popm_frame(3, 196)], 0);
+        let slot = find_popm(&tag).unwrap();
+        assert_eq!(tag[slot.rating_offset.unwrap()], 196);
+        assert_eq!(slot.padding.1, 0);
+
+        let tag = build_tag(4, &[title], 100);
+        let slot = find_popm(&tag).unwrap();
+        assert_eq!(slot.rating_offset, None);
+        assert_eq!(slot.padding.1, 100);
+
+        // ID3v2.2 uses three letter frame ids
+        assert_eq!(find_popm(&build_tag(2, &[], 0)), None);
+    }
+
+    #[test]
+    fn write_popm_in_padding() {
+        let path = std::env::temp_dir().join(format!("musicom-rating-{}.mp3", std::process::id()));
+        let mut contents = build_tag(4, &[text_frame(b"TIT2", "Test")], 64);
+        contents.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        std::fs::write(&path, &contents).unwrap();
+
+        assert_eq!(read_popm(&path), None);
+        write_popm(&path, stars_to_popm(3)).unwrap().unwrap();
+        assert_eq!(read_popm(&path).map(stars_from_popm), Some(3));
+        write_popm(&path, stars_to_popm(5)).unwrap().unwrap();
+        assert_eq!(read_popm(&path).map(stars_from_popm), Some(5));
+
+        // The audio after the tag is left alone
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(written.len(), contents.len());
+        assert_eq!(written[written.len() - 4..], contents[contents.len() - 4..]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+//! The local scrobble log. Plays that count as a listen are stored here first, so nothing is
+//! lost while offline, and are submitted by the scrobbler later.
+
+use std::io::{self, Write};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rusqlite::{named_params, Connection, Row, NO_PARAMS};
+
+use crate::library::db::get_library_db;
+use crate::library::history::PlayRecord;
+use crate::library::Track;
+
+/// A play counts as a listen once half of the song or this much of it was played
+pub const SCROBBLE_MIN_LISTEN_SECS: i64 = 4 * 60;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scrobble {
+    pub id: Option<i32>,
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub track_num: Option<i32>,
+    pub duration: Option<Duration>,
+    /// When the song started playing
+    pub listened_at: DateTime<Utc>,
+    pub submitted: bool,
+}
+
+pub fn is_scrobble_eligible(listened: Duration, duration: Option<Duration>) -> bool {
+    if listened >= Duration::seconds(SCROBBLE_MIN_LISTEN_SECS) {
+        return true;
+    }
+    match duration {
+        Some(duration) if duration > Duration::zero() => listened * 2 >= duration,
+        _ => false,
+    }
+}
+
+impl Scrobble {
+    /// The scrobble for a finished play, `None` if not enough of the song was played or the
+    /// track doesn't have an artist and title to submit.
+    pub fn from_play_record(track: &Track, record: &PlayRecord) -> Option<Self> {
+        if !is_scrobble_eligible(record.listened, record.duration) {
+            return None;
+        }
+
+        Some(Scrobble {
+            id: None,
+            artist: track.artist.clone().filter(|artist| !artist.is_empty())?,
+            title: track.title.clone().filter(|title| !title.is_empty())?,
+            album: track.album.clone(),
+            track_num: track.track_num,
+            duration: record.duration,
+            listened_at: record.started_at,
+            submitted: false,
+        })
+    }
+
+    pub fn from_db_row(row: &Row) -> rusqlite::Result<Self> {
+        let duration_ms: Option<i64> = row.get(row.column_index("duration_ms")?)?;
+        let listened_at: i64 = row.get(row.column_index("listened_at")?)?;
+
+        Ok(Scrobble {
+            id: row.get(row.column_index("id")?)?,
+            artist: row.get(row.column_index("artist")?)?,
+            title: row.get(row.column_index("title")?)?,
+            album: row.get(row.column_index("album")?)?,
+            track_num: row.get(row.column_index("track_num")?)?,
+            duration: duration_ms.map(Duration::milliseconds),
+            listened_at: Utc.timestamp(listened_at, 0),
+            submitted: row.get(row.column_index("submitted")?)?,
+        })
+    }
+
+    /// Record a finished play in the scrobble log if it counts as a listen. Doesn't panic when
+    /// the library can't be opened, the player calls this.
+    pub fn record_play(record: &PlayRecord) {
+        let conn = match get_library_db() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let track = conn
+            .query_row_named(
+                "SELECT * FROM tracks WHERE id = :id",
+                named_params! {":id": record.track_id},
+                |row| Track::from_db_row(row),
+            )
+            .ok();
+
+        if let Some(mut scrobble) = track.and_then(|track| Self::from_play_record(&track, record)) {
+            scrobble.save_with_conn(&conn);
+        }
+    }
+
+    pub fn save_with_conn(&mut self, conn: &Connection) {
+        conn.execute_named(
+            "INSERT INTO scrobbles
+                (artist, title, album, track_num, duration_ms, listened_at, submitted)
+                VALUES (:artist, :title, :album, :track_num, :duration_ms, :listened_at,
+                        :submitted)",
+            named_params! {
+                ":artist": self.artist,
+                ":title": self.title,
+                ":album": self.album,
+                ":track_num": self.track_num,
+                ":duration_ms": self.duration.map(|duration| duration.num_milliseconds()),
+                ":listened_at": self.listened_at.timestamp(),
+                ":submitted": self.submitted,
+            },
+        )
+        .unwrap_or_else(|e| {
+            log::warn!("Could not add the scrobble to the log: {}", e);
+            0
+        });
+
+        self.id = Some(conn.last_insert_rowid() as i32);
+    }
+
+    /// The oldest scrobbles that haven't been submitted yet. The submitter runs for as long as
+    /// musicom does, so errors are returned for it to try again later instead of panicking.
+    pub fn pending_with_conn(conn: &Connection, limit: usize) -> rusqlite::Result<Vec<Self>> {
+        let mut statement = conn.prepare(
+            "SELECT * FROM scrobbles
+                WHERE NOT submitted
+                ORDER BY listened_at
+                LIMIT :limit",
+        )?;
+
+        let scrobbles = statement
+            .query_map_named(named_params! {":limit": limit as i64}, |row| {
+                Self::from_db_row(row)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(scrobbles)
+    }
+
+    pub fn mark_submitted_with_conn(conn: &Connection, scrobbles: &[Self]) -> rusqlite::Result<()> {
+        for id in scrobbles.iter().filter_map(|scrobble| scrobble.id) {
+            conn.execute_named(
+                "UPDATE scrobbles SET submitted = 1 WHERE id = :id",
+                named_params! {":id": id},
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The whole log, submitted or not, oldest first
+    pub fn all() -> Vec<Self> {
+        let conn = get_library_db().unwrap();
+        Self::all_with_conn(&conn)
+    }
+
+    pub fn all_with_conn(conn: &Connection) -> Vec<Self> {
+        let mut statement = conn
+            .prepare("SELECT * FROM scrobbles ORDER BY listened_at")
+            .unwrap();
+
+        let scrobbles: Result<Vec<_>, _> = statement
+            .query_map(NO_PARAMS, |row| Self::from_db_row(row))
+            .unwrap()
+            .collect();
+
+        scrobbles.unwrap_or_default()
+    }
+}
+
+/// Write scrobbles in the `.scrobbler.log` format of Rockbox, which scrobbling tools can import
+/// and submit later.
+pub fn write_scrobbler_log<W: Write>(out: &mut W, scrobbles: &[Scrobble]) -> io::Result<()> {
+    // Tabs separate the fields, so they can't show up in them
+    fn field(value: &str) -> String {
+        value.replace(|c: char| c == '\t' || c == '\n' || c == '\r', " ")
+    }
+
+    writeln!(out, "#AUDIOSCROBBLER/1.1")?;
+    writeln!(out, "#TZ/UTC")?;
+    writeln!(out, "#CLIENT/musicom {}", env!("CARGO_PKG_VERSION"))?;
+
+    for scrobble in scrobbles {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\tL\t{}\t",
+            field(&scrobble.artist),
+            field(scrobble.album.as_deref().unwrap_or("")),
+            field(&scrobble.title),
+            scrobble
+                .track_num
+                .map_or(String::new(), |track_num| track_num.to_string()),
+            scrobble
+                .duration
+                .map_or(0, |duration| duration.num_seconds()),
+            scrobble.listened_at.timestamp()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::library::db::run_migrations;
+
+    fn play(listened_secs: i64, duration_secs: i64) -> PlayRecord {
+        PlayRecord {
+            id: None,
+            track_id: 1,
+            started_at: Utc.timestamp(1_600_000_000, 0),
+            listened: Duration::seconds(listened_secs),
+            duration: Some(Duration::seconds(duration_secs)),
+            completed: false,
+            skipped: false,
+        }
+    }
+
+    #[test]
+    fn eligibility() {
+        let minutes = |m| Some(Duration::minutes(m));
+        assert!(is_scrobble_eligible(Duration::minutes(2), minutes(4)));
+        assert!(!is_scrobble_eligible(Duration::seconds(119), minutes(4)));
+        // Long songs count after four minutes
+        assert!(is_scrobble_eligible(Duration::minutes(4), minutes(20)));
+        assert!(!is_scrobble_eligible(Duration::minutes(3), None));
+
+        let track = Track {
+            artist: Some("George".to_string()),
+            title: Some("Test 1: The Intro".to_string()),
+            ..Default::default()
+        };
+        assert!(Scrobble::from_play_record(&track, &play(200, 300)).is_some());
+        assert!(Scrobble::from_play_record(&track, &play(20, 300)).is_none());
+        // Skipping a long song after four minutes still counts as a listen
+        let skipped = PlayRecord {
+            skipped: true,
+            ..play(250, 20 * 60)
+        };
+        assert!(Scrobble::from_play_record(&track, &skipped).is_some());
+        let no_artist = Track {
+            artist: None,
+            ..track
+        };
+        assert!(Scrobble::from_play_record(&no_artist, &play(200, 300)).is_none());
+    }
+
+    #[test]
+    fn scrobble_queue() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        let track = Track {
+            artist: Some("George".to_string()),
+            title: Some("Solo\tTake 2".to_string()),
+            album: Some("Test".to_string()),
+            track_num: Some(3),
+            ..Default::default()
+        };
+        let mut first = Scrobble::from_play_record(&track, &play(200, 300)).unwrap();
+        first.save_with_conn(&conn);
+        let mut second = Scrobble::from_play_record(&track, &play(300, 300)).unwrap();
+        second.listened_at = second.listened_at + Duration::minutes(5);
+        second.save_with_conn(&conn);
+
+        assert_eq!(
+            Scrobble::pending_with_conn(&conn, 10).unwrap(),
+            vec![first.clone(), second.clone()]
+        );
+        Scrobble::mark_submitted_with_conn(&conn, &[first]).unwrap();
+        assert_eq!(
+            Scrobble::pending_with_conn(&conn, 10).unwrap(),
+            vec![second]
+        );
+        assert_eq!(Scrobble::all_with_conn(&conn).len(), 2);
+
+        let mut log = Vec::new();
+        write_scrobbler_log(&mut log, &Scrobble::all_with_conn(&conn)[..1]).unwrap();
+        let log = String::from_utf8(log).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "#AUDIOSCROBBLER/1.1");
+        assert_eq!(lines[1], "#TZ/UTC");
+        assert_eq!(
+            lines[3],
+            "George\tTest\tSolo Take 2\t3\t300\tL\t1600000000\t"
+        );
+    }
+}
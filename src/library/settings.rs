@@ -0,0 +1,52 @@
+//! Small bits of state that should survive a restart, stored as strings by key.
+
+use rusqlite::{named_params, Connection};
+
+use crate::library::db::get_library_db;
+
+pub fn get_setting(key: &str) -> Option<String> {
+    let conn = get_library_db()?;
+    get_setting_with_conn(&conn, key)
+}
+
+pub fn get_setting_with_conn(conn: &Connection, key: &str) -> Option<String> {
+    conn.query_row_named(
+        "SELECT value FROM settings WHERE key = :key",
+        named_params! {":key": key},
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+pub fn set_setting(key: &str, value: &str) {
+    let conn = get_library_db().unwrap();
+    set_setting_with_conn(&conn, key, value);
+}
+
+pub fn set_setting_with_conn(conn: &Connection, key: &str, value: &str) {
+    conn.execute_named(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (:key, :value)",
+        named_params! {":key": key, ":value": value},
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::library::db::run_migrations;
+
+    #[test]
+    fn settings_round_trip() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        assert_eq!(get_setting_with_conn(&conn, "audio_output"), None);
+        set_setting_with_conn(&conn, "audio_output", "fake");
+        set_setting_with_conn(&conn, "audio_output", "alsa:hw:0");
+        assert_eq!(
+            get_setting_with_conn(&conn, "audio_output"),
+            Some("alsa:hw:0".to_string())
+        );
+    }
+}
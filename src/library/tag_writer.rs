@@ -0,0 +1,152 @@
+//! Writing library data back into the tags of the music files.
+//!
+//! The taglib crate only knows about the basic tags, so this uses the property interface of the
+//! TagLib 2 C bindings directly. That needs a recent TagLib, hence the `write-tags` feature. The
+//! same goes for reading tags like ratings that the taglib crate doesn't expose.
+
+use std::path::Path;
+
+use crate::library::Track;
+
+/// Format the ReplayGain tags of a track the way other taggers write them.
+pub fn get_gain_properties(track: &Track) -> Vec<(&'static str, String)> {
+    let mut properties = Vec::new();
+    if let Some(gain) = track.track_gain {
+        properties.push(("REPLAYGAIN_TRACK_GAIN", format!("{:.2} dB", gain)));
+    }
+    if let Some(peak) = track.track_peak {
+        properties.push(("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", peak)));
+    }
+    if let Some(gain) = track.album_gain {
+        properties.push(("REPLAYGAIN_ALBUM_GAIN", format!("{:.2} dB", gain)));
+    }
+    if let Some(peak) = track.album_peak {
+        properties.push(("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", peak)));
+    }
+    properties
+}
+
+pub fn write_gain_tags(track: &Track) -> Result<(), String> {
+    write_properties(track, &get_gain_properties(track))
+}
+
+pub fn write_property(track: &Track, key: &'static str, value: String) -> Result<(), String> {
+    write_properties(track, &[(key, value)])
+}
+
+#[cfg(feature = "write-tags")]
+fn write_properties(track: &Track, properties: &[(&str, String)]) -> Result<(), String> {
+    use std::ffi::CString;
+    use std::os::raw::c_char;
+
+    use taglib_sys::TagLib_File;
+
+    extern "C" {
+        fn taglib_property_set(file: *mut TagLib_File, prop: *const c_char, value: *const c_char);
+    }
+
+    let path = track
+        .path
+        .to_str()
+        .and_then(|path| CString::new(path).ok())
+        .ok_or_else(|| format!("{} is not a valid path", track.path.display()))?;
+    let properties = properties
+        .iter()
+        .map(|(key, value)| Ok((CString::new(*key)?, CString::new(value.as_str())?)))
+        .collect::<Result<Vec<_>, std::ffi::NulError>>()
+        .map_err(|e| e.to_string())?;
+
+    unsafe {
+        let file = taglib_sys::taglib_file_new(path.as_ptr());
+        if file.is_null() {
+            return Err("could not open file".to_string());
+        }
+        if taglib_sys::taglib_file_is_valid(file) == 0 {
+            taglib_sys::taglib_file_free(file);
+            return Err("unsupported file type".to_string());
+        }
+
+        for (key, value) in properties.iter() {
+            taglib_property_set(file, key.as_ptr(), value.as_ptr());
+        }
+
+        let saved = taglib_sys::taglib_file_save(file) != 0;
+        taglib_sys::taglib_file_free(file);
+        if !saved {
+            return Err("could not save tags".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// The first value of a tag the taglib crate doesn't know about, `None` if the file doesn't have
+/// it.
+#[cfg(feature = "write-tags")]
+pub fn read_property(path: &Path, key: &str) -> Option<String> {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::c_char;
+
+    use taglib_sys::TagLib_File;
+
+    extern "C" {
+        fn taglib_property_get(file: *const TagLib_File, prop: *const c_char) -> *mut *mut c_char;
+        fn taglib_property_free(props: *mut *mut c_char);
+    }
+
+    let path = CString::new(path.to_str()?).ok()?;
+    let key = CString::new(key).ok()?;
+
+    unsafe {
+        let file = taglib_sys::taglib_file_new(path.as_ptr());
+        if file.is_null() {
+            return None;
+        }
+
+        let mut value = None;
+        if taglib_sys::taglib_file_is_valid(file) != 0 {
+            let values = taglib_property_get(file, key.as_ptr());
+            if !values.is_null() {
+                if !(*values).is_null() {
+                    value = Some(CStr::from_ptr(*values).to_string_lossy().into_owned());
+                }
+                taglib_property_free(values);
+            }
+        }
+        taglib_sys::taglib_file_free(file);
+        value
+    }
+}
+
+#[cfg(not(feature = "write-tags"))]
+pub fn read_property(_path: &Path, _key: &str) -> Option<String> {
+    None
+}
+
+#[cfg(not(feature = "write-tags"))]
+fn write_properties(_track: &Track, _properties: &[(&str, String)]) -> Result<(), String> {
+    Err("musicom was built without the write-tags feature".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gain_properties() {
+        let track = Track {
+            track_gain: Some(-6.5),
+            track_peak: Some(0.98765432),
+            album_gain: Some(1.0),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            get_gain_properties(&track),
+            vec![
+                ("REPLAYGAIN_TRACK_GAIN", "-6.50 dB".to_string()),
+                ("REPLAYGAIN_TRACK_PEAK", "0.987654".to_string()),
+                ("REPLAYGAIN_ALBUM_GAIN", "1.00 dB".to_string()),
+            ]
+        );
+    }
+}
@@ -5,7 +5,7 @@ use rusqlite::{named_params, types::FromSql, Connection, Row, NO_PARAMS};
 
 use crate::library::db::get_library_db;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Track {
     pub id: Option<i32>,
     pub path: PathBuf,
@@ -13,6 +13,85 @@ pub struct Track {
     pub artist: Option<String>,
     pub album: Option<String>,
     pub track_num: Option<i32>,
+    /// ReplayGain values from the loudness analysis, gains in dB and peaks as a ratio of full
+    /// scale.
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+    /// Stars from 1 to 5, 0 if the track isn't rated
+    pub rating: u8,
+    pub loved: bool,
+}
+
+/// The tags tracks can be searched by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrackField {
+    Title,
+    Artist,
+    Album,
+    TrackNum,
+    Path,
+    /// Any of the tags above
+    Any,
+}
+
+impl TrackField {
+    /// The field for its name in searches like `artist:George`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "title" => Some(Self::Title),
+            "artist" => Some(Self::Artist),
+            "album" => Some(Self::Album),
+            "track" => Some(Self::TrackNum),
+            "path" => Some(Self::Path),
+            "any" => Some(Self::Any),
+            _ => None,
+        }
+    }
+
+    /// The columns as text, so every field can be compared the same way
+    fn columns(self) -> &'static [&'static str] {
+        match self {
+            Self::Title => &["title"],
+            Self::Artist => &["artist"],
+            Self::Album => &["album"],
+            Self::TrackNum => &["CAST(track_num AS TEXT)"],
+            Self::Path => &["path_"],
+            Self::Any => &["title", "artist", "album", "path_"],
+        }
+    }
+}
+
+/// The WHERE clause for the filters and its parameters. Exact filters have to match the whole
+/// value, the others only have to be part of it, ignoring case.
+fn filter_clause(filters: &[(TrackField, String)], exact: bool) -> (String, Vec<String>) {
+    if filters.is_empty() {
+        return ("1".to_string(), Vec::new());
+    }
+
+    let clause = filters
+        .iter()
+        .enumerate()
+        .map(|(idx, (field, _))| {
+            let conditions = field
+                .columns()
+                .iter()
+                .map(|column| {
+                    if exact {
+                        format!("{} = ?{}", column, idx + 1)
+                    } else {
+                        format!("instr(lower({}), lower(?{})) > 0", column, idx + 1)
+                    }
+                })
+                .collect::<Vec<_>>();
+            format!("({})", conditions.join(" OR "))
+        })
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let params = filters.iter().map(|(_, value)| value.clone()).collect();
+
+    (clause, params)
 }
 
 impl PartialEq<Track> for Track {
@@ -40,6 +119,12 @@ impl Track {
         let artist = get_column("artist", row).ok();
         let album = get_column("album", row).ok();
         let track_num = get_column("track_num", row).ok();
+        let track_gain = get_column("track_gain", row).ok();
+        let track_peak = get_column("track_peak", row).ok();
+        let album_gain = get_column("album_gain", row).ok();
+        let album_peak = get_column("album_peak", row).ok();
+        let rating = get_column("rating", row).unwrap_or(0);
+        let loved = get_column("loved", row).unwrap_or(false);
         Ok(Track {
             id,
             path,
@@ -47,13 +132,18 @@ impl Track {
             artist,
             album,
             track_num,
+            track_gain,
+            track_peak,
+            album_gain,
+            album_peak,
+            rating,
+            loved,
         })
     }
 }
 
 // Functions to fetch tracks from the database
 impl Track {
-    #[allow(dead_code)]
     pub fn from_path<PB>(pb: PB) -> Option<Self>
     where
         PB: Into<PathBuf>,
@@ -85,8 +175,19 @@ impl Track {
             return Self::get_with_conn(&conn, id);
         }
 
+        Self::from_tags(path)
+    }
+
+    /// Read the track from the tags in the file, without looking it up in the library
+    pub fn from_tags<PB>(pb: PB) -> Option<Self>
+    where
+        PB: Into<PathBuf>,
+    {
+        let path = pb.into();
+
         let taglib_file = taglib::File::new(&path).ok()?;
         let tags = taglib_file.tag().ok()?;
+        let rating = super::rating::read_rating_tags(&path).unwrap_or(0);
 
         Some(Self {
             id: None,
@@ -95,8 +196,9 @@ impl Track {
             artist: tags.artist(),
             album: tags.album(),
             track_num: tags.track().map(|val| val as i32),
+            rating,
+            ..Default::default()
         })
-
     }
 
     #[allow(dead_code)]
@@ -129,8 +231,12 @@ impl Track {
 
     pub fn save_with_conn(&mut self, conn: &Connection) {
         let sql = "\
-            INSERT OR REPLACE INTO tracks (id, path_, title, artist, album, track_num)
-                VALUES (:id, :path, :title, :artist, :album, :track_num)";
+            INSERT OR REPLACE INTO tracks (id, path_, title, artist, album, track_num,
+                                           track_gain, track_peak, album_gain, album_peak,
+                                           rating, loved)
+                VALUES (:id, :path, :title, :artist, :album, :track_num,
+                        :track_gain, :track_peak, :album_gain, :album_peak,
+                        :rating, :loved)";
         conn.execute_named(
             sql,
             named_params! {
@@ -140,6 +246,12 @@ impl Track {
                 ":artist": self.artist,
                 ":album": self.album,
                 ":track_num": self.track_num,
+                ":track_gain": self.track_gain,
+                ":track_peak": self.track_peak,
+                ":album_gain": self.album_gain,
+                ":album_peak": self.album_peak,
+                ":rating": self.rating,
+                ":loved": self.loved,
             },
         )
         .unwrap_or_else(|e| {
@@ -153,14 +265,145 @@ impl Track {
         self.id = Some(new_id as i32);
     }
 
+    /// Remove the track and its bookmarks from the library. Its play history stays, so the
+    /// statistics don't change.
+    pub fn delete_with_conn(&self, conn: &Connection) {
+        conn.execute_named(
+            "DELETE FROM tracks WHERE id = :id",
+            named_params! {":id": self.id},
+        )
+        .unwrap();
+        conn.execute_named(
+            "DELETE FROM bookmarks WHERE track_id = :id",
+            named_params! {":id": self.id},
+        )
+        .unwrap();
+    }
+
+    /// Only update the rating and loved flag of a track that is already in the library.
+    pub fn save_rating(&self) {
+        let conn = get_library_db().unwrap();
+        self.save_rating_with_conn(&conn)
+    }
+
+    pub fn save_rating_with_conn(&self, conn: &Connection) {
+        conn.execute_named(
+            "UPDATE tracks SET rating = :rating, loved = :loved WHERE path_ = :path",
+            named_params! {
+                ":rating": self.rating,
+                ":loved": self.loved,
+                ":path": self.path.to_str(),
+            },
+        )
+        .unwrap_or_else(|e| {
+            log::warn!("Could not save the rating of {}: {}", self.path.display(), e);
+            0
+        });
+    }
+
+    /// Loved tracks and tracks rated 4 stars or more, the best ones first.
+    pub fn iter_favorites() -> impl Iterator<Item = Track> {
+        let conn = get_library_db().unwrap();
+        Self::iter_favorites_with_conn(&conn)
+    }
+
+    pub fn iter_favorites_with_conn(conn: &Connection) -> impl Iterator<Item = Track> {
+        let mut statement = conn
+            .prepare(
+                "SELECT * FROM tracks
+                    WHERE loved OR rating >= 4
+                    ORDER BY
+                        loved DESC,
+                        rating DESC,
+                        title COLLATE NOCASE",
+            )
+            .unwrap();
+
+        let tracks: Result<VecDeque<Track>, _> = statement
+            .query_map(NO_PARAMS, |row| Track::from_db_row(&row))
+            .unwrap()
+            .collect();
+
+        tracks.unwrap_or_default().into_iter()
+    }
+
+    /// Tracks that match all of the filters, sorted like they are on their albums.
+    pub fn search(filters: &[(TrackField, String)], exact: bool) -> Vec<Track> {
+        let conn = get_library_db().unwrap();
+        Self::search_with_conn(&conn, filters, exact)
+    }
+
+    pub fn search_with_conn(
+        conn: &Connection,
+        filters: &[(TrackField, String)],
+        exact: bool,
+    ) -> Vec<Track> {
+        let (clause, params) = filter_clause(filters, exact);
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT * FROM tracks
+                    WHERE {}
+                    ORDER BY
+                        artist COLLATE NOCASE,
+                        album COLLATE NOCASE,
+                        track_num,
+                        title COLLATE NOCASE",
+                clause
+            ))
+            .unwrap();
+
+        let tracks: Result<Vec<Track>, _> = statement
+            .query_map(params, |row| Track::from_db_row(&row))
+            .unwrap()
+            .collect();
+
+        tracks.unwrap_or_default()
+    }
+
+    /// The different values of a field in the tracks that match all of the filters.
+    #[allow(dead_code)]
+    pub fn list_values(
+        field: TrackField,
+        filters: &[(TrackField, String)],
+        exact: bool,
+    ) -> Vec<String> {
+        let conn = get_library_db().unwrap();
+        Self::list_values_with_conn(&conn, field, filters, exact)
+    }
+
+    pub fn list_values_with_conn(
+        conn: &Connection,
+        field: TrackField,
+        filters: &[(TrackField, String)],
+        exact: bool,
+    ) -> Vec<String> {
+        let column = field.columns()[0];
+        let (clause, params) = filter_clause(filters, exact);
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT DISTINCT {column} FROM tracks
+                    WHERE {column} NOT NULL AND {clause}
+                    ORDER BY {column} COLLATE NOCASE",
+                column = column,
+                clause = clause
+            ))
+            .unwrap();
+
+        let values: Result<Vec<String>, _> = statement
+            .query_map(params, |row| row.get(0))
+            .unwrap()
+            .collect();
+
+        values.unwrap_or_default()
+    }
+
     #[allow(dead_code)]
     pub fn get_track_count() -> usize {
         let conn = get_library_db().unwrap();
         Self::get_track_count_with_conn(&conn)
     }
 
-    #[allow(dead_code)]
-    fn get_track_count_with_conn(conn: &Connection) -> usize {
+    pub fn get_track_count_with_conn(conn: &Connection) -> usize {
         let mut statement = conn.prepare("SELECT COUNT(*) FROM tracks").unwrap();
 
         statement
@@ -200,6 +443,7 @@ mod test {
                 artist: Some("George".to_string()),
                 album: None,
                 track_num: None,
+                ..Default::default()
             },
             Track {
                 id: None,
@@ -208,6 +452,7 @@ mod test {
                 artist: Some("George".to_string()),
                 album: None,
                 track_num: None,
+                ..Default::default()
             },
         ];
         let mut conn = rusqlite::Connection::open_in_memory().unwrap();
@@ -228,4 +473,88 @@ mod test {
             assert_eq!(track1noid, *track2, "Tracks aren't equal");
         }
     }
+
+    #[test]
+    fn favorites() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        let mut tracks = vec![];
+        let ratings = [("ok", 3, false), ("good", 4, false), ("loved", 0, true)];
+        for (name, rating, loved) in ratings.iter() {
+            let mut track = Track {
+                path: PathBuf::from(format!("/tmp/{}.mp3", name)),
+                title: Some(name.to_string()),
+                ..Default::default()
+            };
+            track.save_with_conn(&conn);
+            track.rating = *rating;
+            track.loved = *loved;
+            track.save_rating_with_conn(&conn);
+            tracks.push(track);
+        }
+
+        let saved = Track::get_with_conn(&conn, tracks[1].id.unwrap()).unwrap();
+        assert_eq!(saved.rating, 4);
+        assert!(!saved.loved);
+
+        let favorites = Track::iter_favorites_with_conn(&conn)
+            .map(|track| track.title.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(favorites, vec!["loved", "good"]);
+    }
+
+    #[test]
+    fn search_tracks() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn);
+
+        let songs = [
+            ("Test 1: The Intro", "George", "Test", 1),
+            ("Test 2: The Outro", "George", "Test", 2),
+            ("Solo", "Georgina", "Other", 1),
+        ];
+        for (idx, (title, artist, album, track_num)) in songs.iter().enumerate() {
+            Track {
+                path: PathBuf::from(format!("/tmp/test{}.mp3", idx)),
+                title: Some(title.to_string()),
+                artist: Some(artist.to_string()),
+                album: Some(album.to_string()),
+                track_num: Some(*track_num),
+                ..Default::default()
+            }
+            .save_with_conn(&conn);
+        }
+
+        let titles = |tracks: Vec<Track>| {
+            tracks
+                .into_iter()
+                .map(|track| track.title.unwrap())
+                .collect::<Vec<_>>()
+        };
+        let george = [(TrackField::Artist, "George".to_string())];
+        assert_eq!(
+            titles(Track::search_with_conn(&conn, &george, true)),
+            vec!["Test 1: The Intro", "Test 2: The Outro"]
+        );
+        assert_eq!(Track::search_with_conn(&conn, &george, false).len(), 3);
+
+        let outro = [
+            (TrackField::Any, "OUTRO".to_string()),
+            (TrackField::TrackNum, "2".to_string()),
+        ];
+        assert_eq!(
+            titles(Track::search_with_conn(&conn, &outro, false)),
+            vec!["Test 2: The Outro"]
+        );
+
+        assert_eq!(
+            Track::list_values_with_conn(&conn, TrackField::Album, &[], true),
+            vec!["Other", "Test"]
+        );
+        assert_eq!(
+            Track::list_values_with_conn(&conn, TrackField::Artist, &george, false),
+            vec!["George", "Georgina"]
+        );
+    }
 }
@@ -29,7 +29,7 @@ impl TrackedPath {
         self.save_with_conn(&conn);
     }
 
-    fn save_with_conn(&mut self, conn: &Connection) {
+    pub fn save_with_conn(&mut self, conn: &Connection) {
         conn.execute_named(
             "INSERT INTO tracked_paths (path_)
                 VALUES (:path)",
@@ -43,12 +43,27 @@ impl TrackedPath {
         self.id = Some(new_id as i32);
     }
 
+    /// Stop tracking the path. Its songs stay in the library until the next full scan.
+    #[allow(dead_code)]
+    pub fn delete(&self) {
+        let conn = get_library_db().unwrap();
+        self.delete_with_conn(&conn);
+    }
+
+    pub fn delete_with_conn(&self, conn: &Connection) {
+        conn.execute_named(
+            "DELETE FROM tracked_paths WHERE id = :id",
+            named_params! {":id": self.id},
+        )
+        .unwrap();
+    }
+
     pub fn iter() -> TrackedPathIter {
         let conn = get_library_db().unwrap();
         Self::iter_with_conn(&conn)
     }
 
-    fn iter_with_conn(conn: &Connection) -> TrackedPathIter {
+    pub fn iter_with_conn(conn: &Connection) -> TrackedPathIter {
         let mut statement = conn.prepare("SELECT * FROM tracked_paths").unwrap();
 
         let tracked_paths: Result<VecDeque<TrackedPath>, _> = statement
@@ -103,5 +118,9 @@ mod test {
         {
             assert_eq!(&tracked_path, path, "Paths not in database");
         }
+
+        tracked_paths[0].delete_with_conn(&conn);
+        let remaining = TrackedPath::iter_with_conn(&conn).collect::<Vec<_>>();
+        assert_eq!(remaining, &tracked_paths[1..]);
     }
 }
@@ -1,19 +1,40 @@
 extern crate gstreamer as gst;
 
+mod cli;
+mod config;
+mod control;
+mod daemon;
+mod instance;
+mod keymap;
 mod library;
+mod mpd;
+mod mpris;
 mod player;
+mod scrobbler;
 mod ui;
 mod util;
 
 fn main() {
-    gst::init().unwrap();
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Err(e) = cli::take_config_arg(&mut args) {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    if let Some(exit_code) = cli::run(&args) {
+        std::process::exit(exit_code);
+    }
 
-    {
-        let mut conn = library::db::get_library_db().unwrap();
-        library::db::run_migrations(&mut conn);
+    if let Err(e) = cli::init() {
+        eprintln!("{}", e);
+        std::process::exit(2);
     }
 
-    library::fast_refresh_library();
+    scrobbler::spawn_submitter();
+    mpris::start();
+    control::start(None).ok();
+    mpd::start();
+
+    cli::play_paths(&args);
 
     let mut ui = ui::UI::new();
 
@@ -0,0 +1,1190 @@
+//! A server for the part of the MPD protocol that clients need to control playback, edit the
+//! queue and browse the library, so musicom can be used from ncmpcpp, phone apps and the like.
+//!
+//! The queue is the MPD playlist, without the repeat and shuffle items, and the id of a song is
+//! the id of its queue item, which stays the same while the queue changes. Songs are named by
+//! their paths. `idle` is driven by a thread that watches
+//! the player the same way the MPRIS one does and counts the changes of every subsystem.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration as StdDuration, Instant};
+
+use chrono::Duration;
+use rusqlite::Connection;
+use url::Url;
+
+use crate::library::db::get_library_db;
+use crate::library::{settings, Album, Track, TrackField};
+use crate::player::{is_audio_file_guess, PlaybackState, PlayerHdl, Queue, QueueItem};
+
+const PROTOCOL_VERSION: &str = "0.21.0";
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:6600";
+const ADDRESS_SETTING: &str = "mpd_address";
+
+/// The playback state isn't covered by the notifiers, so check for changes this often too
+const POLL_INTERVAL_MS: u64 = 500;
+/// How often an idle client checks for changes and for a noidle
+const IDLE_POLL_MS: u64 = 100;
+
+const COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "close",
+    "commands",
+    "count",
+    "currentsong",
+    "delete",
+    "deleteid",
+    "find",
+    "findadd",
+    "getvol",
+    "idle",
+    "list",
+    "move",
+    "moveid",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "search",
+    "searchadd",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "urlhandlers",
+];
+
+const TAG_TYPES: &[&str] = &["Artist", "AlbumArtist", "Album", "Title", "Track"];
+
+/// The address the server listens on, `None` while it's disabled.
+pub fn get_address() -> Option<String> {
+    settings::get_setting(ADDRESS_SETTING).filter(|address| !address.is_empty())
+}
+
+pub fn set_address(address: Option<&str>) {
+    settings::set_setting(ADDRESS_SETTING, address.unwrap_or(""));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AckCode {
+    NotList = 1,
+    Arg = 2,
+    Unknown = 5,
+    NoExist = 50,
+}
+
+#[derive(Debug, PartialEq)]
+struct Ack {
+    code: AckCode,
+    message: String,
+}
+
+impl Ack {
+    fn new<S: Into<String>>(code: AckCode, message: S) -> Self {
+        Ack {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn arg<S: Into<String>>(message: S) -> Self {
+        Self::new(AckCode::Arg, message)
+    }
+
+    fn no_exist<S: Into<String>>(message: S) -> Self {
+        Self::new(AckCode::NoExist, message)
+    }
+
+    /// The error line, `index` is the position of the command in a command list
+    fn format(&self, index: usize, command: &str) -> String {
+        format!(
+            "ACK [{}@{}] {{{}}} {}\n",
+            self.code as u8, index, command, self.message
+        )
+    }
+}
+
+/// Split a request into the command and its arguments. Arguments with spaces are quoted, and
+/// quotes and backslashes in them are escaped with a backslash.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut token = String::new();
+        match chars.peek() {
+            None => return Ok(tokens),
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => token.push(chars.next().ok_or("Missing closing quote")?),
+                        Some(c) => token.push(c),
+                        None => return Err("Missing closing quote".to_string()),
+                    }
+                }
+            }
+            Some(_) => {
+                while let Some(c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                    token.push(*c);
+                    chars.next();
+                }
+            }
+        }
+        tokens.push(token);
+    }
+}
+
+fn get_arg<'a>(args: &'a [String], idx: usize) -> Result<&'a str, Ack> {
+    args.get(idx)
+        .map(String::as_str)
+        .ok_or_else(|| Ack::arg("Too few arguments"))
+}
+
+fn parse_arg<T: FromStr>(args: &[String], idx: usize) -> Result<T, Ack> {
+    let arg = get_arg(args, idx)?;
+    arg.parse()
+        .map_err(|_| Ack::arg(format!("Invalid argument: {}", arg)))
+}
+
+fn parse_seconds(arg: &str) -> Result<Duration, Ack> {
+    arg.parse::<f64>()
+        .map(|secs| Duration::milliseconds((secs * 1000.0) as i64))
+        .map_err(|_| Ack::arg(format!("Invalid time: {}", arg)))
+}
+
+/// A position or a `START:END` range of positions, an open end goes to the end of the playlist.
+fn parse_range(arg: &str, len: usize) -> Result<(usize, usize), Ack> {
+    let invalid = || Ack::arg(format!("Invalid range: {}", arg));
+    let (start, end) = match arg.find(':') {
+        Some(idx) => {
+            let start = arg[..idx].parse().map_err(|_| invalid())?;
+            let end = match &arg[idx + 1..] {
+                "" => len,
+                end => end.parse().map_err(|_| invalid())?,
+            };
+            (start, end)
+        }
+        None => {
+            let position: usize = arg.parse().map_err(|_| invalid())?;
+            (position, position + 1)
+        }
+    };
+    if start > end || end > len {
+        return Err(Ack::arg("Bad song index"));
+    }
+    Ok((start, end))
+}
+
+fn parse_tag(tag: &str) -> Option<TrackField> {
+    match tag.to_lowercase().as_str() {
+        "artist" | "albumartist" => Some(TrackField::Artist),
+        "album" => Some(TrackField::Album),
+        "title" => Some(TrackField::Title),
+        "track" => Some(TrackField::TrackNum),
+        "file" => Some(TrackField::Path),
+        "any" => Some(TrackField::Any),
+        _ => None,
+    }
+}
+
+fn tag_name(field: TrackField) -> &'static str {
+    match field {
+        TrackField::Artist => "Artist",
+        TrackField::Album => "Album",
+        TrackField::Title => "Title",
+        TrackField::TrackNum => "Track",
+        TrackField::Path => "file",
+        TrackField::Any => "any",
+    }
+}
+
+/// The `TAG VALUE` pairs of find, search and list. The filter expressions of newer clients aren't
+/// supported.
+fn parse_filters(args: &[String]) -> Result<Vec<(TrackField, String)>, Ack> {
+    if args.len() % 2 != 0 {
+        return Err(Ack::arg("Incorrect number of filter arguments"));
+    }
+    args.chunks(2)
+        .map(|pair| {
+            parse_tag(&pair[0])
+                .map(|field| (field, pair[1].clone()))
+                .ok_or_else(|| Ack::arg(format!("Unknown tag type: {}", pair[0])))
+        })
+        .collect()
+}
+
+/// The indexes of the songs in the queue, leaving out the repeat and shuffle items
+fn playlist(items: &[QueueItem]) -> Vec<usize> {
+    items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item.get_path().is_some())
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// The ids of the songs in the playlist, by position
+fn song_ids(queue: &Queue) -> Vec<u32> {
+    let ids = queue.get_item_ids();
+    playlist(&queue.get_queue_contents())
+        .into_iter()
+        .map(|idx| ids[idx])
+        .collect()
+}
+
+/// The songs of a range argument, or the whole playlist without one
+fn range_or_all(range: Option<&str>, len: usize) -> Result<(usize, usize), Ack> {
+    match range {
+        Some(range) => parse_range(range, len),
+        None => Ok((0, len)),
+    }
+}
+
+/// The song with an id, as a range of one position
+fn id_range(ids: &[u32], id: u32) -> Result<(usize, usize), Ack> {
+    let position = ids
+        .iter()
+        .position(|&song_id| song_id == id)
+        .ok_or_else(|| Ack::no_exist("No such song"))?;
+    Ok((position, position + 1))
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    if uri.starts_with("file://") {
+        Url::parse(uri).ok()?.to_file_path().ok()
+    } else {
+        Some(PathBuf::from(uri))
+    }
+}
+
+fn write_track(out: &mut String, track: &Track) {
+    out.push_str(&format!("file: {}\n", track.path.display()));
+    if let Some(ref artist) = track.artist {
+        out.push_str(&format!("Artist: {}\n", artist));
+    }
+    if let Some(ref album) = track.album {
+        out.push_str(&format!("Album: {}\n", album));
+    }
+    if let Some(ref title) = track.title {
+        out.push_str(&format!("Title: {}\n", title));
+    }
+    if let Some(track_num) = track.track_num {
+        out.push_str(&format!("Track: {}\n", track_num));
+    }
+}
+
+fn write_item(out: &mut String, item: &QueueItem, position: usize, id: u32) {
+    match item {
+        QueueItem::Track(track) => write_track(out, track),
+        item => {
+            if let Some(path) = item.get_path() {
+                out.push_str(&format!("file: {}\n", path.display()));
+            }
+        }
+    }
+    out.push_str(&format!("Pos: {}\nId: {}\n", position, id));
+}
+
+/// How often every subsystem changed, clients compare these to tell what happened while they
+/// weren't looking.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Versions {
+    player: u32,
+    playlist: u32,
+    mixer: u32,
+}
+
+#[derive(PartialEq)]
+struct PlayerSnapshot {
+    state: PlaybackState,
+    position: Option<usize>,
+    item: Option<PathBuf>,
+    artist: String,
+    title: String,
+}
+
+impl PlayerSnapshot {
+    fn take(player: &PlayerHdl) -> Self {
+        let state = player.get_playback_state();
+        let position = player.queue().get_queue_position();
+        let now_playing = player.now_playing();
+        PlayerSnapshot {
+            state,
+            position,
+            item: now_playing
+                .get_item()
+                .and_then(|item| item.get_path().map(Path::to_path_buf)),
+            artist: now_playing.get_artist(),
+            title: now_playing.get_song_name(),
+        }
+    }
+}
+
+fn take_playlist_snapshot(player: &PlayerHdl) -> Vec<Option<PathBuf>> {
+    player
+        .queue()
+        .get_queue_contents()
+        .iter()
+        .map(|item| item.get_path().map(Path::to_path_buf))
+        .collect()
+}
+
+/// Count the changes whenever the player or the queue report one.
+fn watch_player(changes: Arc<Mutex<Versions>>) {
+    let player = PlayerHdl::new();
+    let (sender, receiver) = mpsc::channel();
+    let now_playing_sender = Mutex::new(sender.clone());
+    let queue_sender = Mutex::new(sender);
+    player
+        .now_playing_mut()
+        .register_changed_cb(Box::new(move || {
+            now_playing_sender.lock().unwrap().send(()).ok();
+        }));
+    player
+        .queue_mut()
+        .register_queue_change_cb(Box::new(move || {
+            queue_sender.lock().unwrap().send(()).ok();
+        }));
+
+    let mut last_player = PlayerSnapshot::take(&player);
+    let mut last_playlist = take_playlist_snapshot(&player);
+    let mut last_volume = player.get_volume();
+    loop {
+        match receiver.recv_timeout(StdDuration::from_millis(POLL_INTERVAL_MS)) {
+            Ok(()) => while receiver.try_recv().is_ok() {},
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let player_snapshot = PlayerSnapshot::take(&player);
+        let playlist = take_playlist_snapshot(&player);
+        let volume = player.get_volume();
+
+        let mut changes = changes.lock().unwrap();
+        if player_snapshot != last_player {
+            changes.player += 1;
+            last_player = player_snapshot;
+        }
+        if playlist != last_playlist {
+            changes.playlist += 1;
+            last_playlist = playlist;
+        }
+        if volume != last_volume {
+            changes.mixer += 1;
+            last_volume = volume;
+        }
+    }
+}
+
+struct Session {
+    player: PlayerHdl,
+    library: Arc<Mutex<Connection>>,
+    changes: Arc<Mutex<Versions>>,
+    /// The changes this client was told about, the ones after it are reported by the next idle
+    seen: Versions,
+    started: Instant,
+}
+
+impl Session {
+    fn playlist_version(&self) -> u32 {
+        self.changes.lock().unwrap().playlist + 1
+    }
+
+    /// The queue index of a song in the playlist
+    fn queue_index(&self, position: usize) -> Result<usize, Ack> {
+        playlist(&self.player.queue().get_queue_contents())
+            .get(position)
+            .copied()
+            .ok_or_else(|| Ack::arg("Bad song index"))
+    }
+
+    fn position_of_id(&self, id: u32) -> Result<usize, Ack> {
+        id_range(&song_ids(&self.player.queue()), id).map(|(position, _)| position)
+    }
+
+    /// The position and the id of the current song in the playlist
+    fn current_song(&self) -> Option<(usize, u32)> {
+        let queue = self.player.queue();
+        let current = queue.get_queue_position()?;
+        let position = playlist(&queue.get_queue_contents())
+            .iter()
+            .position(|&idx| idx == current)?;
+        song_ids(&queue).get(position).map(|&id| (position, id))
+    }
+
+    fn current_position(&self) -> Option<usize> {
+        self.current_song().map(|(position, _)| position)
+    }
+
+    fn write_status(&self, out: &mut String) {
+        let volume = self.player.get_volume();
+        let state = self.player.get_playback_state();
+        let (songs, repeat) = {
+            let items = self.player.queue().get_queue_contents();
+            let repeat = items
+                .iter()
+                .any(|item| matches!(item, QueueItem::RepeatQueue));
+            (playlist(&items).len(), repeat)
+        };
+        let current = self.current_song();
+
+        out.push_str(&format!("volume: {}\n", (volume * 100.0).round()));
+        out.push_str(&format!("repeat: {}\n", repeat as u8));
+        out.push_str("random: 0\nsingle: 0\nconsume: 0\n");
+        out.push_str(&format!("playlist: {}\n", self.playlist_version()));
+        out.push_str(&format!("playlistlength: {}\n", songs));
+        let state = match state {
+            PlaybackState::Playing => "play",
+            PlaybackState::Paused => "pause",
+            PlaybackState::Stopped => "stop",
+        };
+        out.push_str(&format!("state: {}\n", state));
+
+        if let (Some((position, id)), false) = (current, state == "stop") {
+            let (elapsed, duration) = self.player.now_playing().get_song_progress();
+            let seconds = |duration: Duration| duration.num_milliseconds() as f64 / 1000.0;
+            out.push_str(&format!("song: {}\nsongid: {}\n", position, id));
+            out.push_str(&format!(
+                "time: {}:{}\n",
+                elapsed.num_seconds(),
+                duration.num_seconds()
+            ));
+            out.push_str(&format!("elapsed: {:.3}\n", seconds(elapsed)));
+            out.push_str(&format!("duration: {:.3}\n", seconds(duration)));
+        }
+    }
+
+    fn write_current_song(&self, out: &mut String) {
+        let current = self.current_song();
+        let now_playing = self.player.now_playing();
+        if let (Some(item), Some((position, id))) = (now_playing.get_item(), current) {
+            write_item(out, &item, position, id);
+            let (_, duration) = now_playing.get_song_progress();
+            out.push_str(&format!("Time: {}\n", duration.num_seconds()));
+        }
+    }
+
+    /// Write the songs `select` picks from the ids of the playlist. Picking and writing happen
+    /// on the same state of the queue, so other clients can't change it in between.
+    fn write_playlist<F>(&self, out: &mut String, select: F) -> Result<(), Ack>
+    where
+        F: FnOnce(&[u32]) -> Result<(usize, usize), Ack>,
+    {
+        let queue = self.player.queue();
+        let items = queue.get_queue_contents();
+        let songs = playlist(&items);
+        let ids = song_ids(&queue);
+        let (start, end) = select(&ids)?;
+        for position in start..end {
+            match (songs.get(position), ids.get(position)) {
+                (Some(&idx), Some(&id)) => write_item(out, &items[idx], position, id),
+                _ => return Err(Ack::arg("Bad song index")),
+            }
+        }
+        Ok(())
+    }
+
+    fn play_position(&self, position: usize) -> Result<(), Ack> {
+        let idx = self.queue_index(position)?;
+        let mut queue = self.player.queue_mut();
+        queue.set_queue_index(idx);
+        queue.play_queue_at_selection();
+        Ok(())
+    }
+
+    fn seek(&self, position: usize, time: Duration) -> Result<(), Ack> {
+        if self.current_position() != Some(position) {
+            self.play_position(position)?;
+        }
+        self.player.seek_to(time);
+        Ok(())
+    }
+
+    /// The songs for a URI: a song in the library, a file or all songs of the library in a
+    /// directory.
+    fn resolve_uri(&self, uri: &str) -> Result<Vec<QueueItem>, Ack> {
+        let path = uri_to_path(uri).ok_or_else(|| Ack::no_exist("Unsupported URI"))?;
+        let library = self.library.lock().unwrap();
+
+        if path.is_dir() {
+            let mut tracks = Track::iter_with_conn(&library)
+                .filter(|track| track.path.starts_with(&path))
+                .collect::<Vec<_>>();
+            tracks.sort_by(|a, b| a.path.cmp(&b.path));
+            if tracks.is_empty() {
+                return Err(Ack::no_exist("No songs in the library in that directory"));
+            }
+            return Ok(tracks.into_iter().map(QueueItem::new_from_track).collect());
+        }
+
+        match Track::from_path_with_conn(path.clone(), &library) {
+            Some(track) if track.id.is_some() => Ok(vec![QueueItem::new_from_track(track)]),
+            _ if path.is_file() && is_audio_file_guess(&path) => {
+                Ok(vec![QueueItem::new_from_path(path)])
+            }
+            _ => Err(Ack::no_exist("No such song")),
+        }
+    }
+
+    /// Add songs to the end of the queue, returns the ids they got.
+    fn add_items(&self, items: Vec<QueueItem>) -> Vec<u32> {
+        let mut queue = self.player.queue_mut();
+        let position = playlist(&queue.get_queue_contents()).len();
+        for item in items {
+            match item {
+                QueueItem::Track(track) => queue.add_track(&track),
+                QueueItem::Path(path) => queue.add_song(&path),
+                _ => (),
+            }
+        }
+        song_ids(&queue).split_off(position)
+    }
+
+    /// Remove the songs `select` picks from the ids of the playlist, under one lock of the
+    /// queue so the positions can't go stale.
+    fn delete<F>(&self, select: F) -> Result<(), Ack>
+    where
+        F: FnOnce(&[u32]) -> Result<(usize, usize), Ack>,
+    {
+        let mut queue = self.player.queue_mut();
+        let songs = playlist(&queue.get_queue_contents());
+        let (start, end) = select(&song_ids(&queue))?;
+        for position in (start..end).rev() {
+            let idx = *songs
+                .get(position)
+                .ok_or_else(|| Ack::arg("Bad song index"))?;
+            queue.remove_item(idx);
+        }
+        Ok(())
+    }
+
+    /// Move the songs `select` picks so that the first one ends up at `to`.
+    fn move_songs<F>(&self, select: F, to: usize) -> Result<(), Ack>
+    where
+        F: FnOnce(&[u32]) -> Result<(usize, usize), Ack>,
+    {
+        let mut queue = self.player.queue_mut();
+        let ids = song_ids(&queue);
+        let (start, end) = select(&ids)?;
+        let len = ids.len();
+        if start >= end || end > len || to + (end - start) > len {
+            return Err(Ack::arg("Bad song index"));
+        }
+
+        for offset in 0..end - start {
+            let songs = playlist(&queue.get_queue_contents());
+            if to > start {
+                // Moving a song down shifts the rest of the range up
+                queue.move_item(songs[start], songs[to + end - start - 1]);
+            } else {
+                queue.move_item(songs[start + offset], songs[to + offset]);
+            }
+        }
+        Ok(())
+    }
+
+    /// find and search. A single exact album filter lists the album in order.
+    fn find(&self, args: &[String], exact: bool) -> Result<Vec<Track>, Ack> {
+        let filters = parse_filters(args)?;
+        if filters.is_empty() {
+            return Err(Ack::arg("Too few arguments"));
+        }
+        let library = self.library.lock().unwrap();
+        match filters.as_slice() {
+            [(TrackField::Album, album)] if exact => {
+                Ok(Album::get_album_with_conn(&library, album).track_list)
+            }
+            _ => Ok(Track::search_with_conn(&library, &filters, exact)),
+        }
+    }
+
+    fn list(&self, args: &[String], out: &mut String) -> Result<(), Ack> {
+        let tag = get_arg(args, 0)?;
+        let field = match parse_tag(tag) {
+            Some(TrackField::Any) | None => {
+                return Err(Ack::arg(format!("Unknown tag type: {}", tag)))
+            }
+            Some(field) => field,
+        };
+
+        // Grouping isn't supported, the values are only listed
+        let mut filter_args = args[1..].to_vec();
+        while let Some(idx) = filter_args.iter().position(|arg| arg == "group") {
+            filter_args.drain(idx..(idx + 2).min(filter_args.len()));
+        }
+        // Old clients only pass the artist to list the albums of
+        let filters = match (field, filter_args.as_slice()) {
+            (TrackField::Album, [artist]) => vec![(TrackField::Artist, artist.clone())],
+            _ => parse_filters(&filter_args)?,
+        };
+
+        let library = self.library.lock().unwrap();
+        for value in Track::list_values_with_conn(&library, field, &filters, true) {
+            out.push_str(&format!("{}: {}\n", tag_name(field), value));
+        }
+        Ok(())
+    }
+
+    fn write_stats(&self, out: &mut String) {
+        let library = self.library.lock().unwrap();
+        let artists = Track::list_values_with_conn(&library, TrackField::Artist, &[], true).len();
+        let albums = Album::get_all_album_keys_with_conn(&library).len();
+        let songs = Track::get_track_count_with_conn(&library);
+        out.push_str(&format!(
+            "artists: {}\nalbums: {}\nsongs: {}\nuptime: {}\nplaytime: 0\ndb_playtime: 0\n",
+            artists,
+            albums,
+            songs,
+            self.started.elapsed().as_secs()
+        ));
+    }
+
+    fn run(&self, command: &str, args: &[String]) -> Result<String, Ack> {
+        let mut out = String::new();
+        match command {
+            "ping" => (),
+            "status" => self.write_status(&mut out),
+            "currentsong" => self.write_current_song(&mut out),
+            "stats" => self.write_stats(&mut out),
+            "play" => match args.first() {
+                Some(_) => self.play_position(parse_arg(args, 0)?)?,
+                None => self.player.play(),
+            },
+            "playid" => match args.first() {
+                Some(_) => self.play_position(self.position_of_id(parse_arg(args, 0)?)?)?,
+                None => self.player.play(),
+            },
+            "pause" => match args.first().map(String::as_str) {
+                Some("1") => self.player.pause(),
+                Some("0") => self.player.play(),
+                _ => self.player.toggle_play_pause(),
+            },
+            "stop" => self.player.stop(),
+            "next" => self.player.play_next(),
+            "previous" => self.player.play_previous(),
+            "seek" => self.seek(parse_arg(args, 0)?, parse_seconds(get_arg(args, 1)?)?)?,
+            "seekid" => {
+                let position = self.position_of_id(parse_arg(args, 0)?)?;
+                self.seek(position, parse_seconds(get_arg(args, 1)?)?)?
+            }
+            "seekcur" => {
+                let time = get_arg(args, 0)?;
+                let offset = parse_seconds(time)?;
+                let position = if time.starts_with('+') || time.starts_with('-') {
+                    self.player.now_playing().get_song_progress().0 + offset
+                } else {
+                    offset
+                };
+                self.player.seek_to(position.max(Duration::zero()));
+            }
+            "setvol" => {
+                let volume: u8 = parse_arg(args, 0)?;
+                self.player.set_volume(f64::from(volume.min(100)) / 100.0);
+            }
+            "getvol" => out.push_str(&format!(
+                "volume: {}\n",
+                (self.player.get_volume() * 100.0).round()
+            )),
+            "playlistinfo" => {
+                let range = args.first().map(String::as_str);
+                self.write_playlist(&mut out, |ids| range_or_all(range, ids.len()))?;
+            }
+            "playlistid" => match args.first() {
+                Some(_) => {
+                    let id = parse_arg(args, 0)?;
+                    self.write_playlist(&mut out, |ids| id_range(ids, id))?
+                }
+                None => self.write_playlist(&mut out, |ids| Ok((0, ids.len())))?,
+            },
+            "plchanges" => {
+                if parse_arg::<u32>(args, 0)? != self.playlist_version() {
+                    self.write_playlist(&mut out, |ids| Ok((0, ids.len())))?;
+                }
+            }
+            "plchangesposid" => {
+                if parse_arg::<u32>(args, 0)? != self.playlist_version() {
+                    for (position, id) in song_ids(&self.player.queue()).iter().enumerate() {
+                        out.push_str(&format!("cpos: {}\nId: {}\n", position, id));
+                    }
+                }
+            }
+            "add" => {
+                self.add_items(self.resolve_uri(get_arg(args, 0)?)?);
+            }
+            "addid" => {
+                let mut items = self.resolve_uri(get_arg(args, 0)?)?;
+                items.truncate(1);
+                let id = *self
+                    .add_items(items)
+                    .first()
+                    .ok_or_else(|| Ack::no_exist("No such song"))?;
+                if args.len() > 1 {
+                    self.move_songs(|ids| id_range(ids, id), parse_arg(args, 1)?)?;
+                }
+                out.push_str(&format!("Id: {}\n", id));
+            }
+            "delete" => {
+                let range = get_arg(args, 0)?;
+                self.delete(|ids| parse_range(range, ids.len()))?;
+            }
+            "deleteid" => {
+                let id = parse_arg(args, 0)?;
+                self.delete(|ids| id_range(ids, id))?;
+            }
+            "move" => {
+                let range = get_arg(args, 0)?;
+                self.move_songs(|ids| parse_range(range, ids.len()), parse_arg(args, 1)?)?;
+            }
+            "moveid" => {
+                let id = parse_arg(args, 0)?;
+                self.move_songs(|ids| id_range(ids, id), parse_arg(args, 1)?)?;
+            }
+            "clear" => self.player.queue_mut().clear_queue(),
+            "find" | "search" => {
+                for track in self.find(args, command == "find")? {
+                    write_track(&mut out, &track);
+                }
+            }
+            "findadd" | "searchadd" => {
+                let tracks = self.find(args, command == "findadd")?;
+                self.add_items(tracks.into_iter().map(QueueItem::new_from_track).collect());
+            }
+            "list" => self.list(args, &mut out)?,
+            "count" => {
+                let songs = self.find(args, true)?.len();
+                out.push_str(&format!("songs: {}\nplaytime: 0\n", songs));
+            }
+            "commands" => {
+                for command in COMMANDS {
+                    out.push_str(&format!("command: {}\n", command));
+                }
+            }
+            "notcommands" => (),
+            "tagtypes" => {
+                if args.is_empty() {
+                    for tag in TAG_TYPES {
+                        out.push_str(&format!("tagtype: {}\n", tag));
+                    }
+                }
+            }
+            "outputs" => out.push_str(&format!(
+                "outputid: 0\noutputname: {}\nplugin: gstreamer\noutputenabled: 1\n",
+                self.player.get_output()
+            )),
+            "urlhandlers" => out.push_str("handler: file://\n"),
+            _ => {
+                return Err(Ack::new(
+                    AckCode::Unknown,
+                    format!("unknown command \"{}\"", command),
+                ))
+            }
+        }
+        Ok(out)
+    }
+
+    /// Wait for one of the subsystems to change, all of them if none are given. Returns `None`
+    /// when the client disconnects. Anything the client sends, usually noidle, ends the wait.
+    fn idle(&mut self, subsystems: &[String], lines: &Receiver<String>) -> Option<String> {
+        let wanted = |name: &str| subsystems.is_empty() || subsystems.iter().any(|s| s == name);
+
+        loop {
+            let changes = *self.changes.lock().unwrap();
+            let mut out = String::new();
+            if wanted("playlist") && changes.playlist != self.seen.playlist {
+                out.push_str("changed: playlist\n");
+                self.seen.playlist = changes.playlist;
+            }
+            if wanted("player") && changes.player != self.seen.player {
+                out.push_str("changed: player\n");
+                self.seen.player = changes.player;
+            }
+            if wanted("mixer") && changes.mixer != self.seen.mixer {
+                out.push_str("changed: mixer\n");
+                self.seen.mixer = changes.mixer;
+            }
+            if !out.is_empty() {
+                return Some(out);
+            }
+
+            match lines.recv_timeout(StdDuration::from_millis(IDLE_POLL_MS)) {
+                Ok(_) => return Some(out),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// Run the commands of a command list, stopping at the first one that fails.
+    fn run_list(&self, commands: &[Vec<String>], list_ok: bool) -> String {
+        let mut out = String::new();
+        for (idx, tokens) in commands.iter().enumerate() {
+            match self.run(&tokens[0], &tokens[1..]) {
+                Ok(reply) => out.push_str(&reply),
+                Err(ack) => {
+                    out.push_str(&ack.format(idx, &tokens[0]));
+                    return out;
+                }
+            }
+            if list_ok {
+                out.push_str("list_OK\n");
+            }
+        }
+        out.push_str("OK\n");
+        out
+    }
+}
+
+fn handle_client(stream: TcpStream, mut session: Session) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    writeln!(writer, "OK MPD {}", PROTOCOL_VERSION)?;
+
+    // Read in the background, so that a client waiting in idle can still send noidle
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            match line {
+                Ok(line) if sender.send(line).is_ok() => (),
+                _ => break,
+            }
+        }
+    });
+
+    // The commands of a command list, and whether every one of them gets a list_OK
+    let mut command_list: Option<(Vec<Vec<String>>, bool)> = None;
+    while let Ok(line) = lines.recv() {
+        let tokens = match tokenize(&line) {
+            Ok(tokens) if !tokens.is_empty() => tokens,
+            Ok(_) => {
+                writer.write_all(
+                    Ack::new(AckCode::Unknown, "No command given")
+                        .format(0, "")
+                        .as_bytes(),
+                )?;
+                continue;
+            }
+            Err(e) => {
+                writer.write_all(Ack::arg(e).format(0, "").as_bytes())?;
+                continue;
+            }
+        };
+        if let Some((commands, list_ok)) = command_list.as_mut() {
+            if tokens[0] != "command_list_end" {
+                commands.push(tokens);
+                continue;
+            }
+            let reply = session.run_list(commands, *list_ok);
+            command_list = None;
+            writer.write_all(reply.as_bytes())?;
+            continue;
+        }
+
+        let command = tokens[0].as_str();
+        let reply = match command {
+            "command_list_begin" | "command_list_ok_begin" => {
+                command_list = Some((Vec::new(), command == "command_list_ok_begin"));
+                continue;
+            }
+            "command_list_end" => {
+                Ack::new(AckCode::NotList, "not in command list mode").format(0, command)
+            }
+            "close" => return Ok(()),
+            // Only ends an idle, which reads it itself
+            "noidle" => continue,
+            "idle" => match session.idle(&tokens[1..], &lines) {
+                Some(changes) => changes + "OK\n",
+                None => return Ok(()),
+            },
+            _ => match session.run(command, &tokens[1..]) {
+                Ok(reply) => reply + "OK\n",
+                Err(ack) => ack.format(0, command),
+            },
+        };
+        writer.write_all(reply.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Serve the MPD protocol to the clients that connect to the listener, in the background.
+pub fn serve(listener: TcpListener, library: Connection) {
+    let changes = Arc::new(Mutex::new(Versions::default()));
+    let watched = changes.clone();
+    thread::spawn(move || watch_player(watched));
+
+    let library = Arc::new(Mutex::new(library));
+    let started = Instant::now();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Could not accept an MPD connection: {}", e);
+                    continue;
+                }
+            };
+            let session = Session {
+                player: PlayerHdl::new(),
+                library: library.clone(),
+                changes: changes.clone(),
+                seen: *changes.lock().unwrap(),
+                started,
+            };
+            thread::spawn(move || {
+                if let Err(e) = handle_client(stream, session) {
+                    log::warn!("MPD connection failed: {}", e);
+                }
+            });
+        }
+    });
+}
+
+/// Serve MPD on the configured address, if there is one.
+pub fn start() {
+    let address = match get_address() {
+        Some(address) => address,
+        None => return,
+    };
+    let library = match get_library_db() {
+        Some(library) => library,
+        None => return,
+    };
+    match TcpListener::bind(&address) {
+        Ok(listener) => serve(listener, library),
+        Err(e) => log::warn!("Not serving MPD on {}: {}", address, e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use crate::library::db::run_migrations;
+
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            tokenize("find artist \"George \\\"G\\\" Jones\"  album Test"),
+            Ok(vec![
+                "find".to_string(),
+                "artist".to_string(),
+                "George \"G\" Jones".to_string(),
+                "album".to_string(),
+                "Test".to_string(),
+            ])
+        );
+        assert_eq!(tokenize("  "), Ok(vec![]));
+        assert!(tokenize("add \"/tmp/test1.mp3").is_err());
+
+        assert_eq!(parse_range("3", 5), Ok((3, 4)));
+        assert_eq!(parse_range("1:3", 5), Ok((1, 3)));
+        assert_eq!(parse_range("2:", 5), Ok((2, 5)));
+        assert!(parse_range("5", 5).is_err());
+        assert!(parse_range("3:1", 5).is_err());
+    }
+
+    /// A client that talks to the server like a script would
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn connect(address: SocketAddr) -> Self {
+            let stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(StdDuration::from_secs(5)))
+                .unwrap();
+            let mut client = Client {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+            };
+            let mut greeting = String::new();
+            client.reader.read_line(&mut greeting).unwrap();
+            assert!(greeting.starts_with("OK MPD "));
+            client
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.writer, "{}", line).unwrap();
+        }
+
+        /// The lines of the reply, or the ACK line if the command failed
+        fn reply(&mut self) -> Result<Vec<String>, String> {
+            let mut lines = Vec::new();
+            loop {
+                let mut line = String::new();
+                self.reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_string();
+                if line == "OK" {
+                    return Ok(lines);
+                } else if line.starts_with("ACK ") {
+                    return Err(line);
+                }
+                lines.push(line);
+            }
+        }
+
+        fn command(&mut self, line: &str) -> Result<Vec<String>, String> {
+            self.send(line);
+            self.reply()
+        }
+
+        /// The values of one key in the reply
+        fn values(&mut self, line: &str, key: &str) -> Vec<String> {
+            let prefix = format!("{}: ", key);
+            self.command(line)
+                .unwrap()
+                .iter()
+                .filter_map(|line| line.strip_prefix(&prefix).map(str::to_string))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn scripted_client() {
+        let _lock = crate::player::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        gst::init().unwrap();
+        let player = PlayerHdl::new();
+        player.queue_mut().replace_queue(Vec::new());
+
+        let mut library = Connection::open_in_memory().unwrap();
+        run_migrations(&mut library);
+        let songs = [
+            ("Test 1: The Intro", "George", "Test", 1),
+            ("Test 2: The Outro", "George", "Test", 2),
+            ("Solo", "Georgina", "Other", 1),
+        ];
+        for (idx, (title, artist, album, track_num)) in songs.iter().enumerate() {
+            Track {
+                path: PathBuf::from(format!("/tmp/musicom-mpd-{}.mp3", idx)),
+                title: Some(title.to_string()),
+                artist: Some(artist.to_string()),
+                album: Some(album.to_string()),
+                track_num: Some(*track_num),
+                ..Default::default()
+            }
+            .save_with_conn(&library);
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        serve(listener, library);
+        let mut client = Client::connect(address);
+
+        assert_eq!(client.command("ping"), Ok(vec![]));
+        assert_eq!(client.values("list album", "Album"), vec!["Other", "Test"]);
+        assert_eq!(client.values("list album George", "Album"), vec!["Test"]);
+        assert_eq!(
+            client.values("find album Test", "Title"),
+            vec!["Test 1: The Intro", "Test 2: The Outro"]
+        );
+        assert_eq!(
+            client.values("search any \"the outro\"", "file"),
+            vec!["/tmp/musicom-mpd-1.mp3"]
+        );
+
+        // Another client waits for the playlist to change
+        let mut idler = Client::connect(address);
+        idler.send("idle playlist");
+        assert_eq!(client.command("findadd album Test"), Ok(vec![]));
+        let added = client.command("addid /tmp/musicom-mpd-2.mp3 0");
+        assert_eq!(idler.reply(), Ok(vec!["changed: playlist".to_string()]));
+        assert_eq!(
+            client.values("playlistinfo", "Title"),
+            vec!["Solo", "Test 1: The Intro", "Test 2: The Outro"]
+        );
+        let ids = client.values("playlistinfo", "Id");
+        assert_eq!(added, Ok(vec![format!("Id: {}", ids[0])]));
+
+        // Ids stay with their songs while the playlist changes around them
+        assert_eq!(client.command("move 0 2"), Ok(vec![]));
+        assert_eq!(client.command("delete 0"), Ok(vec![]));
+        assert_eq!(
+            client.values("playlistinfo", "Title"),
+            vec!["Test 2: The Outro", "Solo"]
+        );
+        assert_eq!(
+            client.values("playlistinfo", "Id"),
+            vec![ids[2].clone(), ids[0].clone()]
+        );
+        assert_eq!(
+            client.values(&format!("playlistid {}", ids[0]), "Title"),
+            vec!["Solo"]
+        );
+        assert_eq!(client.values("playlistinfo 1", "Pos"), vec!["1"]);
+        assert_eq!(client.values("status", "playlistlength"), vec!["2"]);
+        assert_eq!(client.values("status", "state"), vec!["stop"]);
+        assert_eq!(
+            client.command(&format!("deleteid {}", ids[1])),
+            Err("ACK [50@0] {deleteid} No such song".to_string())
+        );
+
+        // An idle without changes ends with noidle
+        idler.send("idle player");
+        idler.send("noidle");
+        assert_eq!(idler.reply(), Ok(vec![]));
+
+        client.send("command_list_ok_begin");
+        client.send("ping");
+        client.send("list title artist Georgina");
+        client.send("command_list_end");
+        assert_eq!(
+            client.reply(),
+            Ok(vec![
+                "list_OK".to_string(),
+                "Title: Solo".to_string(),
+                "list_OK".to_string(),
+            ])
+        );
+        client.send("command_list_begin");
+        client.send("ping");
+        client.send("frobnicate");
+        client.send("ping");
+        client.send("command_list_end");
+        assert_eq!(
+            client.reply(),
+            Err("ACK [5@1] {frobnicate} unknown command \"frobnicate\"".to_string())
+        );
+
+        assert_eq!(
+            client.command("add /tmp/musicom-mpd-missing.mp3"),
+            Err("ACK [50@0] {add} No such song".to_string())
+        );
+        assert_eq!(
+            client.command("delete 5"),
+            Err("ACK [2@0] {delete} Bad song index".to_string())
+        );
+
+        player.queue_mut().replace_queue(Vec::new());
+    }
+}
@@ -0,0 +1,674 @@
+//! The MPRIS D-Bus interface, so media keys, status bars and `playerctl` can control musicom.
+//!
+//! Two threads serve it: one answers the method calls and property reads, the other waits for
+//! the `NowPlaying` and `Queue` notifiers and sends PropertiesChanged for whatever changed, and
+//! Seeked for every seek, wherever it came from. The notifiers run with the player locks held,
+//! so all they do is wake the second thread up.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use chrono::Duration;
+use url::Url;
+use zbus::{dbus_interface, fdo};
+use zvariant::{ObjectPath, Value};
+
+use crate::library::rating::MAX_RATING;
+use crate::player::{PlaybackState, PlayerHdl, QueueItem, MAX_RATE, MIN_RATE};
+
+pub const BUS_NAME: &str = "org.mpris.MediaPlayer2.musicom";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// The playback state isn't covered by the notifiers, so check for changes this often too
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// Pictures in the song's directory that are used as its cover art, in order of preference
+const COVER_ART_NAMES: &[&str] = &[
+    "cover.jpg",
+    "cover.png",
+    "folder.jpg",
+    "folder.png",
+    "front.jpg",
+    "front.png",
+    "album.jpg",
+    "album.png",
+];
+
+fn to_micros(duration: Duration) -> i64 {
+    duration.num_microseconds().unwrap_or(i64::MAX)
+}
+
+pub fn find_cover_art(song: &Path) -> Option<PathBuf> {
+    let dir = song.parent()?;
+    COVER_ART_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Every song needs an id, songs from the library use their library id.
+fn track_id(item: &QueueItem, queue_position: Option<usize>) -> ObjectPath<'static> {
+    let path = match item {
+        QueueItem::Track(track) if track.id.is_some() => {
+            format!("/org/musicom/track/{}", track.id.unwrap())
+        }
+        _ => format!("/org/musicom/queue/{}", queue_position.unwrap_or(0)),
+    };
+    ObjectPath::try_from(path).unwrap()
+}
+
+/// The `Metadata` property for a song. The artist and title come from `NowPlaying`, as those
+/// include the tags of songs that aren't in the library.
+pub fn build_metadata(
+    item: Option<&QueueItem>,
+    queue_position: Option<usize>,
+    artist: &str,
+    title: &str,
+    length: Duration,
+) -> HashMap<String, Value<'static>> {
+    let mut metadata = HashMap::new();
+    let item = match item {
+        Some(item) => item,
+        None => {
+            let no_track = ObjectPath::try_from("/org/mpris/MediaPlayer2/TrackList/NoTrack");
+            metadata.insert("mpris:trackid".to_string(), no_track.unwrap().into());
+            return metadata;
+        }
+    };
+
+    metadata.insert(
+        "mpris:trackid".to_string(),
+        track_id(item, queue_position).into(),
+    );
+    if length > Duration::zero() {
+        metadata.insert("mpris:length".to_string(), to_micros(length).into());
+    }
+    if !artist.is_empty() {
+        metadata.insert("xesam:artist".to_string(), vec![artist.to_string()].into());
+    }
+    if !title.is_empty() {
+        metadata.insert("xesam:title".to_string(), title.to_string().into());
+    }
+    if let QueueItem::Track(track) = item {
+        if let Some(album) = track.album.as_ref() {
+            metadata.insert("xesam:album".to_string(), album.clone().into());
+        }
+        if let Some(track_num) = track.track_num {
+            metadata.insert("xesam:trackNumber".to_string(), track_num.into());
+        }
+        if track.rating > 0 {
+            let rating = track.rating as f64 / MAX_RATING as f64;
+            metadata.insert("xesam:userRating".to_string(), rating.into());
+        }
+    }
+    if let Some(path) = item.get_path() {
+        if let Ok(url) = Url::from_file_path(path) {
+            metadata.insert("xesam:url".to_string(), url.into_string().into());
+        }
+        let art_url = find_cover_art(path).and_then(|art| Url::from_file_path(art).ok());
+        if let Some(art_url) = art_url {
+            metadata.insert("mpris:artUrl".to_string(), art_url.into_string().into());
+        }
+    }
+    metadata
+}
+
+fn playback_status(state: PlaybackState) -> &'static str {
+    match state {
+        PlaybackState::Playing => "Playing",
+        PlaybackState::Paused => "Paused",
+        PlaybackState::Stopped => "Stopped",
+    }
+}
+
+struct MediaPlayer2;
+
+#[dbus_interface(name = "org.mpris.MediaPlayer2")]
+impl MediaPlayer2 {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[dbus_interface(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[dbus_interface(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[dbus_interface(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[dbus_interface(property)]
+    fn identity(&self) -> &str {
+        "musicom"
+    }
+
+    #[dbus_interface(property)]
+    fn supported_uri_schemes(&self) -> Vec<&str> {
+        vec!["file"]
+    }
+
+    #[dbus_interface(property)]
+    fn supported_mime_types(&self) -> Vec<&str> {
+        vec![
+            "audio/mpeg",
+            "audio/ogg",
+            "audio/flac",
+            "audio/x-flac",
+            "audio/mp4",
+            "audio/x-wav",
+        ]
+    }
+}
+
+/// Everything the PropertiesChanged signals are sent for, to tell what changed
+struct PlayerSnapshot {
+    status: &'static str,
+    metadata: HashMap<String, Value<'static>>,
+    volume: f64,
+    rate: f64,
+    can_go_next: bool,
+    can_go_previous: bool,
+    position: Duration,
+    seek_count: u32,
+}
+
+impl PlayerSnapshot {
+    fn take(player: &PlayerHdl) -> Self {
+        let status = playback_status(player.get_playback_state());
+        let volume = player.get_volume();
+        let rate = player.get_speed().get_rate();
+        let (queue_position, queue_len, can_go_previous) = {
+            let queue = player.queue();
+            let position = queue.get_queue_position();
+            let contents = queue.get_queue_contents();
+            let can_go_previous = position.map_or(false, |position| {
+                contents[..position]
+                    .iter()
+                    .any(|item| item.get_path().is_some())
+            });
+            (position, contents.len(), can_go_previous)
+        };
+        let now_playing = player.now_playing();
+        let (position, length) = now_playing.get_song_progress();
+        let metadata = build_metadata(
+            now_playing.get_item().as_ref(),
+            queue_position,
+            &now_playing.get_artist(),
+            &now_playing.get_song_name(),
+            length,
+        );
+
+        PlayerSnapshot {
+            status,
+            metadata,
+            volume,
+            rate,
+            can_go_next: queue_position.map_or(queue_len > 0, |position| position + 1 < queue_len),
+            can_go_previous,
+            position,
+            seek_count: now_playing.get_seek_count(),
+        }
+    }
+
+    /// The properties that differ from an older snapshot, by their D-Bus names
+    fn changes(&self, old: &Self) -> HashMap<&'static str, Value<'static>> {
+        let mut changes = HashMap::new();
+        if self.status != old.status {
+            changes.insert("PlaybackStatus", self.status.into());
+        }
+        if self.metadata != old.metadata {
+            changes.insert("Metadata", self.metadata.clone().into());
+        }
+        if self.volume != old.volume {
+            changes.insert("Volume", self.volume.into());
+        }
+        if self.rate != old.rate {
+            changes.insert("Rate", self.rate.into());
+        }
+        if self.can_go_next != old.can_go_next {
+            changes.insert("CanGoNext", self.can_go_next.into());
+        }
+        if self.can_go_previous != old.can_go_previous {
+            changes.insert("CanGoPrevious", self.can_go_previous.into());
+        }
+        changes
+    }
+}
+
+struct Player {
+    player: PlayerHdl,
+}
+
+impl Player {
+    /// The Seeked signal is sent by `watch_player` once the player has done the seek
+    fn seek_to(&self, position: Duration) {
+        let (_, length) = self.player.now_playing().get_song_progress();
+        if position > length && length > Duration::zero() {
+            // Seeking past the end goes to the next song, like the spec says
+            self.player.play_next();
+            return;
+        }
+        self.player.seek_to(position.max(Duration::zero()));
+    }
+}
+
+#[dbus_interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) {
+        self.player.play_next();
+    }
+
+    fn previous(&self) {
+        self.player.play_previous();
+    }
+
+    fn pause(&self) {
+        self.player.pause();
+    }
+
+    fn play_pause(&self) {
+        match self.player.get_playback_state() {
+            PlaybackState::Playing => self.player.pause(),
+            _ => self.player.play(),
+        }
+    }
+
+    fn stop(&self) {
+        self.player.stop();
+    }
+
+    fn play(&self) {
+        self.player.play();
+    }
+
+    /// `offset` is in microseconds, like all times in MPRIS
+    fn seek(&self, offset: i64) {
+        let (position, _) = self.player.now_playing().get_song_progress();
+        self.seek_to(position + Duration::microseconds(offset));
+    }
+
+    fn set_position(&self, track_id: ObjectPath, position: i64) {
+        // Requests for a song that isn't playing anymore are ignored
+        let current = PlayerSnapshot::take(&self.player);
+        if current.metadata.get("mpris:trackid") != Some(&Value::from(track_id)) {
+            return;
+        }
+        self.seek_to(Duration::microseconds(position));
+    }
+
+    fn open_uri(&self, uri: &str) -> fdo::Result<()> {
+        let path = Url::parse(uri)
+            .ok()
+            .filter(|url| url.scheme() == "file")
+            .and_then(|url| url.to_file_path().ok())
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Can't open {}", uri)))?;
+
+        let mut queue = self.player.queue_mut();
+        let position = queue.get_queue_contents().len();
+        queue.add_song(&path);
+        queue.set_queue_index(position);
+        queue.play_queue_at_selection();
+        Ok(())
+    }
+
+    #[dbus_interface(property)]
+    fn playback_status(&self) -> &str {
+        playback_status(self.player.get_playback_state())
+    }
+
+    #[dbus_interface(property)]
+    fn rate(&self) -> f64 {
+        self.player.get_speed().get_rate()
+    }
+
+    #[dbus_interface(property)]
+    fn set_rate(&mut self, rate: f64) {
+        // A rate of 0 means pause according to the spec
+        if rate <= 0.0 {
+            self.player.pause();
+        } else {
+            self.player.set_speed(rate);
+        }
+    }
+
+    #[dbus_interface(property)]
+    fn minimum_rate(&self) -> f64 {
+        MIN_RATE
+    }
+
+    #[dbus_interface(property)]
+    fn maximum_rate(&self) -> f64 {
+        MAX_RATE
+    }
+
+    #[dbus_interface(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        PlayerSnapshot::take(&self.player).metadata
+    }
+
+    #[dbus_interface(property)]
+    fn volume(&self) -> f64 {
+        self.player.get_volume()
+    }
+
+    #[dbus_interface(property)]
+    fn set_volume(&mut self, volume: f64) {
+        self.player.set_volume(volume);
+    }
+
+    #[dbus_interface(property)]
+    fn position(&self) -> i64 {
+        let (position, _) = self.player.now_playing().get_song_progress();
+        to_micros(position)
+    }
+
+    #[dbus_interface(property)]
+    fn can_go_next(&self) -> bool {
+        PlayerSnapshot::take(&self.player).can_go_next
+    }
+
+    #[dbus_interface(property)]
+    fn can_go_previous(&self) -> bool {
+        PlayerSnapshot::take(&self.player).can_go_previous
+    }
+
+    #[dbus_interface(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// Send PropertiesChanged whenever the player or the queue report a change, and Seeked whenever
+/// the player seeked. The state to compare against is taken right away, the watching happens in
+/// the background.
+fn watch_player(connection: zbus::Connection) {
+    let player = PlayerHdl::new();
+    let (sender, receiver) = mpsc::channel();
+    let now_playing_sender = Mutex::new(sender.clone());
+    let queue_sender = Mutex::new(sender);
+    player
+        .now_playing_mut()
+        .register_changed_cb(Box::new(move || {
+            now_playing_sender.lock().unwrap().send(()).ok();
+        }));
+    player
+        .queue_mut()
+        .register_queue_change_cb(Box::new(move || {
+            queue_sender.lock().unwrap().send(()).ok();
+        }));
+
+    let mut last = PlayerSnapshot::take(&player);
+    thread::spawn(move || loop {
+        match receiver.recv_timeout(StdDuration::from_millis(POLL_INTERVAL_MS)) {
+            Ok(()) => while receiver.try_recv().is_ok() {},
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let snapshot = PlayerSnapshot::take(&player);
+        if snapshot.seek_count != last.seek_count {
+            connection
+                .emit_signal(
+                    None,
+                    OBJECT_PATH,
+                    PLAYER_INTERFACE,
+                    "Seeked",
+                    &to_micros(snapshot.position),
+                )
+                .unwrap_or_else(|e| log::warn!("Could not send the MPRIS Seeked signal: {}", e));
+        }
+        let changes = snapshot.changes(&last);
+        last = snapshot;
+        if changes.is_empty() {
+            continue;
+        }
+
+        let invalidated: Vec<&str> = Vec::new();
+        connection
+            .emit_signal(
+                None,
+                OBJECT_PATH,
+                "org.freedesktop.DBus.Properties",
+                "PropertiesChanged",
+                &(PLAYER_INTERFACE, changes, invalidated),
+            )
+            .unwrap_or_else(|e| log::warn!("Could not send MPRIS PropertiesChanged: {}", e));
+    });
+}
+
+/// Serve the MPRIS interfaces on a connection, the name is taken right away and the requests
+/// are handled in the background.
+pub fn serve(connection: zbus::Connection) -> zbus::Result<()> {
+    // The object server can't move between threads, so it's set up in the one that runs it
+    let (sender, receiver) = mpsc::channel();
+    let server_connection = connection.clone();
+    thread::spawn(move || {
+        let mut object_server = zbus::ObjectServer::new(&server_connection);
+        let player = Player {
+            player: PlayerHdl::new(),
+        };
+        let registered = object_server
+            .at(OBJECT_PATH, MediaPlayer2)
+            .and_then(|_| object_server.at(OBJECT_PATH, player));
+        let failed = registered.is_err();
+        sender.send(registered).ok();
+        if failed {
+            return;
+        }
+
+        loop {
+            if let Err(e) = object_server.try_handle_next() {
+                log::warn!("Could not handle an MPRIS request: {}", e);
+            }
+        }
+    });
+    receiver.recv().expect("The MPRIS thread quit")?;
+
+    fdo::DBusProxy::new(&connection)?
+        .request_name(BUS_NAME, fdo::RequestNameFlags::DoNotQueue.into())?;
+
+    watch_player(connection);
+    Ok(())
+}
+
+/// Serve MPRIS on the session bus. Not having one isn't an error, e.g. over ssh.
+pub fn start() {
+    let result = zbus::Connection::new_session().and_then(serve);
+    if let Err(e) = result {
+        log::info!("Not providing MPRIS: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, ErrorKind};
+    use std::process::{Child, Command, Stdio};
+
+    use zvariant::OwnedValue;
+
+    use crate::library::Track;
+
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let track = Track {
+            id: Some(12),
+            path: PathBuf::from("/tmp/test1.mp3"),
+            album: Some("Test".to_string()),
+            track_num: Some(3),
+            rating: 4,
+            ..Default::default()
+        };
+        let metadata = build_metadata(
+            Some(&QueueItem::Track(track)),
+            Some(0),
+            "George",
+            "Test 1: The Intro",
+            Duration::seconds(200),
+        );
+
+        assert_eq!(
+            metadata["mpris:trackid"],
+            ObjectPath::try_from("/org/musicom/track/12")
+                .unwrap()
+                .into()
+        );
+        assert_eq!(metadata["mpris:length"], Value::from(200_000_000i64));
+        assert_eq!(metadata["xesam:artist"], vec!["George".to_string()].into());
+        assert_eq!(metadata["xesam:title"], "Test 1: The Intro".into());
+        assert_eq!(metadata["xesam:album"], "Test".into());
+        assert_eq!(metadata["xesam:trackNumber"], 3.into());
+        assert_eq!(metadata["xesam:userRating"], 0.8.into());
+        assert_eq!(metadata["xesam:url"], "file:///tmp/test1.mp3".into());
+
+        let nothing = build_metadata(None, None, "", "", Duration::zero());
+        assert_eq!(nothing.len(), 1);
+    }
+
+    #[test]
+    fn cover_art() {
+        let dir = std::env::temp_dir().join(format!("musicom-mpris-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let song = dir.join("test.mp3");
+        assert_eq!(find_cover_art(&song), None);
+
+        std::fs::write(dir.join("folder.jpg"), b"").unwrap();
+        std::fs::write(dir.join("cover.png"), b"").unwrap();
+        assert_eq!(find_cover_art(&song), Some(dir.join("cover.png")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Kills the private dbus-daemon when the test is done, also when it failed
+    struct BusDaemon(Child);
+
+    impl Drop for BusDaemon {
+        fn drop(&mut self) {
+            self.0.kill().ok();
+            self.0.wait().ok();
+        }
+    }
+
+    /// Whether the connection gets a message `accept` likes. The waiting happens in another
+    /// thread, so a missing signal fails the test instead of hanging it.
+    fn wait_for_message<F>(connection: &zbus::Connection, accept: F) -> bool
+    where
+        F: Fn(&zbus::Message) -> bool + Send + 'static,
+    {
+        let connection = connection.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(message) = connection.receive_message() {
+                if accept(&message) {
+                    sender.send(()).ok();
+                    return;
+                }
+            }
+        });
+        receiver.recv_timeout(StdDuration::from_secs(5)).is_ok()
+    }
+
+    /// Talks to a private dbus-daemon, skipped if there's none installed.
+    #[test]
+    fn private_bus() {
+        let _lock = crate::player::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        let daemon = Command::new("dbus-daemon")
+            .args(&["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut daemon = match daemon {
+            Ok(daemon) => BusDaemon(daemon),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                eprintln!("Skipping the MPRIS test, dbus-daemon isn't installed");
+                return;
+            }
+            Err(e) => panic!("Could not start dbus-daemon: {}", e),
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.0.stdout.as_mut().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        gst::init().unwrap();
+        let server = zbus::Connection::new_for_address(address.trim(), true).unwrap();
+        serve(server).unwrap();
+
+        let client = zbus::Connection::new_for_address(address.trim(), true).unwrap();
+        fdo::DBusProxy::new(&client)
+            .unwrap()
+            .add_match(&format!("type='signal',path='{}'", OBJECT_PATH))
+            .unwrap();
+        let properties = fdo::PropertiesProxy::new_for(&client, BUS_NAME, OBJECT_PATH).unwrap();
+        let identity = properties.get(ROOT_INTERFACE, "Identity").unwrap();
+        assert_eq!(Value::from(identity), Value::from("musicom"));
+        let can_seek = properties.get(PLAYER_INTERFACE, "CanSeek").unwrap();
+        assert_eq!(Value::from(can_seek), Value::from(true));
+        let status = properties.get(PLAYER_INTERFACE, "PlaybackStatus").unwrap();
+        assert_eq!(Value::from(status), Value::from("Stopped"));
+
+        // Methods get through to the player, there's nothing to seek in so nothing happens
+        client
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some(PLAYER_INTERFACE),
+                "Seek",
+                &1_000_000i64,
+            )
+            .unwrap();
+        let status = properties.get(PLAYER_INTERFACE, "PlaybackStatus").unwrap();
+        assert_eq!(Value::from(status), Value::from("Stopped"));
+
+        // Changes are announced, whoever made them
+        let old_volume = PlayerHdl::new().get_volume();
+        let volume = if old_volume == 0.25 { 0.5 } else { 0.25 };
+        properties
+            .set(PLAYER_INTERFACE, "Volume", &Value::from(volume))
+            .unwrap();
+        let volume_changed = wait_for_message(&client, move |message| {
+            let is_properties_changed = message.header().map_or(false, |header| {
+                header.member().ok().flatten() == Some("PropertiesChanged")
+            });
+            is_properties_changed
+                && message
+                    .body::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+                    .map_or(false, |(_, changed, _)| {
+                        changed.get("Volume").cloned().map(Value::from) == Some(Value::from(volume))
+                    })
+        });
+        PlayerHdl::new().set_volume(old_volume);
+        assert!(volume_changed, "No PropertiesChanged for the volume");
+    }
+}
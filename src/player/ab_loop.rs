@@ -0,0 +1,79 @@
+use chrono::Duration;
+
+/// Section of the current song that is repeated over and over
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AbLoop {
+    start: Option<Duration>,
+    end: Option<Duration>,
+}
+
+impl AbLoop {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get_start(&self) -> Option<Duration> {
+        self.start
+    }
+
+    pub fn get_end(&self) -> Option<Duration> {
+        self.end
+    }
+
+    /// Looping only starts once both points are set
+    pub fn is_active(&self) -> bool {
+        self.start.is_some() && self.end.is_some()
+    }
+
+    /// The first call sets the A point, the second one the B point. Points set the wrong way
+    /// around are swapped. Once both are set the next call starts over with a new A point.
+    pub fn set_point(&mut self, position: Duration) {
+        match (self.start, self.end) {
+            (Some(start), None) if position < start => {
+                self.start = Some(position);
+                self.end = Some(start);
+            }
+            (Some(start), None) if position == start => (),
+            (Some(_), None) => self.end = Some(position),
+            _ => {
+                self.start = Some(position);
+                self.end = None;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.start = None;
+        self.end = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_loop_points() {
+        let mut ab_loop = AbLoop::new();
+        assert!(!ab_loop.is_active());
+
+        ab_loop.set_point(Duration::seconds(10));
+        assert!(!ab_loop.is_active());
+        ab_loop.set_point(Duration::seconds(10));
+        assert!(!ab_loop.is_active());
+        ab_loop.set_point(Duration::seconds(20));
+        assert!(ab_loop.is_active());
+        assert_eq!(ab_loop.get_start(), Some(Duration::seconds(10)));
+        assert_eq!(ab_loop.get_end(), Some(Duration::seconds(20)));
+
+        // Starting over, this time B comes before A
+        ab_loop.set_point(Duration::seconds(30));
+        assert!(!ab_loop.is_active());
+        ab_loop.set_point(Duration::seconds(5));
+        assert_eq!(ab_loop.get_start(), Some(Duration::seconds(5)));
+        assert_eq!(ab_loop.get_end(), Some(Duration::seconds(30)));
+
+        ab_loop.clear();
+        assert_eq!(ab_loop, AbLoop::new());
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use gst::prelude::*;
+
+use super::replaygain::{rewrite_gain_tags, ReplayGainSettings};
+use crate::library::EqGains;
+
+/// Headroom given to rgvolume when clipping prevention is off, this is the most it allows.
+const NO_CLIPPING_PREVENTION_HEADROOM_DB: f64 = 60.0;
+
+/// The chain of elements set as a playbin's audio-filter:
+/// audioconvert ! rgvolume ! rglimiter ! equalizer-10bands ! scaletempo ! volume ! audioconvert
+///
+/// scaletempo keeps the pitch the same when the playback speed is changed with a rate seek. The
+/// volume element is the user's volume, the playbin's own volume is used for fading.
+pub struct AudioFilter {
+    bin: gst::Bin,
+    rgvolume: gst::Element,
+    rglimiter: gst::Element,
+    equalizer: gst::Element,
+    volume: gst::Element,
+    replaygain_enabled: Arc<AtomicBool>,
+}
+
+fn make_element(factory_name: &str) -> Option<gst::Element> {
+    gst::ElementFactory::make(factory_name, None)
+        .map_err(|e| log::warn!("Could not create {} element: {}", factory_name, e))
+        .ok()
+}
+
+impl AudioFilter {
+    /// Returns `None` if the elements aren't available, e.g. if gst-plugins-good isn't
+    /// installed. Playback still works without the filter.
+    pub fn new(name: &str) -> Option<Self> {
+        let bin = gst::Bin::new(Some(name));
+
+        let convert_in = make_element("audioconvert")?;
+        let rgvolume = make_element("rgvolume")?;
+        let rglimiter = make_element("rglimiter")?;
+        let equalizer = make_element("equalizer-10bands")?;
+        let scaletempo = make_element("scaletempo")?;
+        let volume = make_element("volume")?;
+        let convert_out = make_element("audioconvert")?;
+        let elements = [
+            &convert_in,
+            &rgvolume,
+            &rglimiter,
+            &equalizer,
+            &scaletempo,
+            &volume,
+            &convert_out,
+        ];
+
+        bin.add_many(&elements).ok()?;
+        gst::Element::link_many(&elements).ok()?;
+
+        let sink_pad =
+            gst::GhostPad::with_target(Some("sink"), &convert_in.get_static_pad("sink")?).ok()?;
+        let src_pad =
+            gst::GhostPad::with_target(Some("src"), &convert_out.get_static_pad("src")?).ok()?;
+        bin.add_pad(&sink_pad).ok()?;
+        bin.add_pad(&src_pad).ok()?;
+
+        let replaygain_enabled = Arc::new(AtomicBool::new(true));
+        let enabled = replaygain_enabled.clone();
+        rgvolume.get_static_pad("sink")?.add_probe(
+            gst::PadProbeType::EVENT_DOWNSTREAM,
+            move |_, info| {
+                let rewritten = match info.data {
+                    Some(gst::PadProbeData::Event(ref event)) => match event.view() {
+                        gst::EventView::Tag(tag) => {
+                            rewrite_gain_tags(tag.get_tag(), enabled.load(Ordering::SeqCst))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+
+                if let Some(tags) = rewritten {
+                    info.data = Some(gst::PadProbeData::Event(gst::event::Tag::new(tags)));
+                }
+                gst::PadProbeReturn::Ok
+            },
+        )?;
+
+        Some(Self {
+            bin,
+            rgvolume,
+            rglimiter,
+            equalizer,
+            volume,
+            replaygain_enabled,
+        })
+    }
+
+    pub fn get_element(&self) -> gst::Element {
+        self.bin.clone().upcast()
+    }
+
+    /// Switching ReplayGain off or on only affects the gain tags of the next song, the other
+    /// settings take effect right away.
+    pub fn set_replaygain(&self, settings: &ReplayGainSettings, album_mode: bool) {
+        let enabled = settings.is_enabled();
+        self.replaygain_enabled.store(enabled, Ordering::SeqCst);
+
+        let preamp = if enabled { settings.get_preamp() } else { 0.0 };
+        let headroom = if settings.get_prevent_clipping() {
+            0.0
+        } else {
+            NO_CLIPPING_PREVENTION_HEADROOM_DB
+        };
+
+        self.rgvolume
+            .set_property("album-mode", &settings.use_album_gain(album_mode))
+            .unwrap();
+        self.rgvolume.set_property("pre-amp", &preamp).unwrap();
+        self.rgvolume.set_property("headroom", &headroom).unwrap();
+        self.rglimiter
+            .set_property("enabled", &settings.get_prevent_clipping())
+            .unwrap();
+    }
+
+    pub fn set_equalizer(&self, gains: &EqGains) {
+        for (band, gain) in gains.iter().enumerate() {
+            self.equalizer
+                .set_property(&format!("band{}", band), gain)
+                .unwrap();
+        }
+    }
+
+    pub fn set_volume(&self, volume: f64) {
+        self.volume.set_property("volume", &volume).unwrap();
+    }
+
+    /// The gain in dB that is currently applied to the song
+    pub fn get_replaygain(&self) -> Option<f64> {
+        if !self.replaygain_enabled.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        self.rgvolume
+            .get_property("result-gain")
+            .ok()?
+            .get_some::<f64>()
+            .ok()
+    }
+}
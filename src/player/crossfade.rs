@@ -0,0 +1,144 @@
+use std::f64::consts::FRAC_PI_2;
+
+use chrono::Duration;
+
+use super::queue::QueueItem;
+
+/// Longest crossfade we allow, anything longer than this just sounds like two songs playing at
+/// once.
+pub const MAX_CROSSFADE_SECS: i64 = 12;
+
+#[derive(Clone, Debug)]
+pub struct CrossfadeSettings {
+    duration: Duration,
+    fade_within_album: bool,
+}
+
+impl CrossfadeSettings {
+    pub fn new() -> Self {
+        Self {
+            duration: Duration::zero(),
+            fade_within_album: false,
+        }
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// A zero duration disables crossfading, songs are then played back to back without gaps.
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration = duration
+            .max(Duration::zero())
+            .min(Duration::seconds(MAX_CROSSFADE_SECS));
+    }
+
+    pub fn get_fade_within_album(&self) -> bool {
+        self.fade_within_album
+    }
+
+    /// Whether consecutive songs from the same album are crossfaded too. Off by default so that
+    /// gapless albums stay gapless.
+    pub fn set_fade_within_album(&mut self, fade_within_album: bool) {
+        self.fade_within_album = fade_within_album;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.duration > Duration::zero()
+    }
+
+    pub fn should_crossfade(&self, current: Option<&QueueItem>, next: &QueueItem) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        if self.fade_within_album {
+            return true;
+        }
+
+        !matches!(
+            (current, next),
+            (Some(QueueItem::Track(cur_track)), QueueItem::Track(next_track))
+                if cur_track.album.is_some() && cur_track.album == next_track.album
+        )
+    }
+}
+
+/// Volumes of the song fading out and the song fading in, `progress` goes from 0 to 1 over the
+/// length of the crossfade. Uses an equal power curve so the fade doesn't dip in the middle.
+pub fn get_fade_volumes(progress: f64) -> (f64, f64) {
+    let progress = progress.max(0.0).min(1.0);
+    ((progress * FRAC_PI_2).cos(), (progress * FRAC_PI_2).sin())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::library::Track;
+
+    fn track(album: Option<&str>) -> QueueItem {
+        QueueItem::new_from_track(Track {
+            id: None,
+            path: PathBuf::from("/tmp/test1.mp3"),
+            title: None,
+            artist: None,
+            album: album.map(|album| album.to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn crossfade_duration_is_clamped() {
+        let mut settings = CrossfadeSettings::new();
+        assert!(!settings.is_enabled());
+
+        settings.set_duration(Duration::seconds(60));
+        assert_eq!(settings.get_duration(), Duration::seconds(MAX_CROSSFADE_SECS));
+
+        settings.set_duration(Duration::seconds(-1));
+        assert_eq!(settings.get_duration(), Duration::zero());
+        assert!(!settings.should_crossfade(None, &track(None)));
+    }
+
+    #[test]
+    fn crossfade_keeps_albums_gapless() {
+        let mut settings = CrossfadeSettings::new();
+        settings.set_duration(Duration::seconds(5));
+
+        let path = QueueItem::new_from_path("/tmp/test2.mp3");
+        assert!(settings.should_crossfade(None, &track(Some("Abbey Road"))));
+        assert!(settings.should_crossfade(Some(&path), &path));
+        assert!(settings.should_crossfade(Some(&track(None)), &track(None)));
+        assert!(settings.should_crossfade(
+            Some(&track(Some("Abbey Road"))),
+            &track(Some("Help!"))
+        ));
+        assert!(!settings.should_crossfade(
+            Some(&track(Some("Abbey Road"))),
+            &track(Some("Abbey Road"))
+        ));
+
+        settings.set_fade_within_album(true);
+        assert!(settings.should_crossfade(
+            Some(&track(Some("Abbey Road"))),
+            &track(Some("Abbey Road"))
+        ));
+    }
+
+    #[test]
+    fn fade_volumes() {
+        assert_eq!(get_fade_volumes(0.0), (1.0, 0.0));
+        assert_eq!(get_fade_volumes(-1.0), (1.0, 0.0));
+
+        let (fade_out, fade_in) = get_fade_volumes(1.0);
+        assert!(fade_out.abs() < 1e-9);
+        assert!((fade_in - 1.0).abs() < 1e-9);
+
+        // Equal power, the total power stays the same throughout the fade
+        let (fade_out, fade_in) = get_fade_volumes(0.3);
+        assert!((fade_out * fade_out + fade_in * fade_in - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::library::{EqGains, EqPreset, NUM_BANDS};
+
+/// Range of the band gains supported by equalizer-10bands
+pub const MIN_BAND_GAIN_DB: f64 = -24.0;
+pub const MAX_BAND_GAIN_DB: f64 = 12.0;
+
+/// Center frequencies of the equalizer-10bands bands in Hz
+pub const BAND_FREQUENCIES: [u32; NUM_BANDS] =
+    [29, 59, 119, 237, 474, 947, 1889, 3770, 7523, 15011];
+
+#[derive(Clone, Debug)]
+pub struct EqualizerSettings {
+    enabled: bool,
+    /// The preset picked by the user, possibly with some bands changed by hand
+    preset: EqPreset,
+    /// Select presets automatically based on the album or genre of the song
+    auto_select: bool,
+    /// The preset chosen automatically for the current song, it takes priority over the user's
+    /// preset until the next song starts.
+    auto_preset: Option<EqPreset>,
+}
+
+impl EqualizerSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            preset: EqPreset::new("Flat", [0.0; NUM_BANDS]),
+            auto_select: false,
+            auto_preset: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn get_auto_select(&self) -> bool {
+        self.auto_select
+    }
+
+    pub fn set_auto_select(&mut self, auto_select: bool) {
+        self.auto_select = auto_select;
+        if !auto_select {
+            self.auto_preset = None;
+        }
+    }
+
+    /// The preset that is actually applied to the audio right now
+    pub fn get_preset(&self) -> &EqPreset {
+        self.auto_preset.as_ref().unwrap_or(&self.preset)
+    }
+
+    pub fn is_auto_selected(&self) -> bool {
+        self.auto_preset.is_some()
+    }
+
+    pub fn set_preset(&mut self, preset: EqPreset) {
+        self.preset = preset;
+        self.auto_preset = None;
+    }
+
+    /// `None` goes back to the user's preset
+    pub fn set_auto_preset(&mut self, preset: Option<EqPreset>) {
+        if self.auto_select {
+            self.auto_preset = preset;
+        }
+    }
+
+    /// Changing a band by hand takes over the automatically selected preset
+    pub fn set_band_gain(&mut self, band: usize, gain: f64) {
+        if let Some(preset) = self.auto_preset.take() {
+            self.preset = preset;
+        }
+        if let Some(band_gain) = self.preset.gains.get_mut(band) {
+            *band_gain = gain.max(MIN_BAND_GAIN_DB).min(MAX_BAND_GAIN_DB);
+        }
+    }
+
+    /// The gains to set on the equalizer element, all zero when the equalizer is disabled
+    pub fn get_effective_gains(&self) -> EqGains {
+        if self.enabled {
+            self.get_preset().gains
+        } else {
+            [0.0; NUM_BANDS]
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn auto_preset_priority() {
+        let mut settings = EqualizerSettings::new();
+        assert_eq!(settings.get_effective_gains(), [0.0; NUM_BANDS]);
+
+        let rock = EqPreset::builtin()
+            .into_iter()
+            .find(|preset| preset.name == "Rock")
+            .unwrap();
+        settings.set_enabled(true);
+        settings.set_auto_preset(Some(rock.clone()));
+        assert!(!settings.is_auto_selected());
+
+        settings.set_auto_select(true);
+        settings.set_auto_preset(Some(rock.clone()));
+        assert_eq!(settings.get_effective_gains(), rock.gains);
+
+        // Adjusting a band turns the automatic preset into the user's preset
+        settings.set_band_gain(0, 100.0);
+        assert!(!settings.is_auto_selected());
+        assert_eq!(settings.get_preset().name, "Rock");
+        assert_eq!(settings.get_effective_gains()[0], MAX_BAND_GAIN_DB);
+        assert_eq!(settings.get_effective_gains()[1], rock.gains[1]);
+
+        settings.set_enabled(false);
+        assert_eq!(settings.get_effective_gains(), [0.0; NUM_BANDS]);
+    }
+}
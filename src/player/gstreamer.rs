@@ -1,4 +1,6 @@
+use std::fmt;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::thread;
 
@@ -7,28 +9,124 @@ use chrono::Duration;
 use gst::glib;
 use gst::prelude::*;
 use gst::ClockTime;
+use gst::MessageView;
 
+use super::ab_loop::AbLoop;
+use super::audio_filter::AudioFilter;
+use super::crossfade::{get_fade_volumes, CrossfadeSettings};
+use super::equalizer::EqualizerSettings;
 use super::now_playing::NowPlaying;
-use super::queue::Queue;
+use super::output::AudioOutput;
+use super::play_tracker::{PlayEnd, PlayTracker};
+use super::queue::{Queue, QueueItem};
+use super::replaygain::{ReplayGainMode, ReplayGainSettings};
+use super::sleep_timer::{SleepSettings, SleepTimer};
+use super::speed::SpeedSettings;
 
 use super::util::create_gst_uri;
+use crate::library::history;
+use crate::library::playback_speed::{self, SpeedKind};
+use crate::library::scrobble::Scrobble;
+use crate::library::settings;
+use crate::library::EqPreset;
 
 lazy_static::lazy_static! {
-    static ref PLAYBIN: gst::Element =
-        gst::ElementFactory::make("playbin", Some("play")).unwrap();
+    // There are two playbins so that one song can fade out while the next one fades in. Only
+    // one of them is active at any time, the other one is either idle or fading out.
+    static ref PLAYBINS: [gst::Element; 2] = [
+        gst::ElementFactory::make("playbin", Some("play")).unwrap(),
+        gst::ElementFactory::make("playbin", Some("play_fade")).unwrap(),
+    ];
+    static ref AUDIO_FILTERS: [Option<AudioFilter>; 2] = [
+        AudioFilter::new("audio_filter"),
+        AudioFilter::new("audio_filter_fade"),
+    ];
     static ref SHARED_STATE: Arc<RwLock<SharedPlayerContents>> =
         GstPlayer::construct_shared_state();
     static ref QUEUE: Arc<RwLock<Queue>> = Arc::new(RwLock::new(Queue::new()));
     static ref NOW_PLAYING: Arc<RwLock<NowPlaying>> = Arc::new(RwLock::new(NowPlaying::new()));
 }
 
+static ACTIVE_PLAYBIN: AtomicUsize = AtomicUsize::new(0);
+
+/// How often the volumes are updated while crossfading
+const FADE_STEP_MS: u32 = 50;
+
+/// Key of the audio output in the settings table
+const OUTPUT_SETTING: &str = "audio_output";
+
+#[derive(Debug)]
+pub enum PlayError {
+    NotASong,
+    FileMissing(PathBuf),
+    InvalidPath(PathBuf),
+    StateChange(gst::StateChangeError),
+}
+
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotASong => write!(f, "queue item is not a song"),
+            Self::FileMissing(path) => write!(f, "{} does not exist", path.display()),
+            Self::InvalidPath(path) => write!(f, "{} can not be played", path.display()),
+            Self::StateChange(e) => write!(f, "could not start playback: {}", e),
+        }
+    }
+}
+
+/// Build the gstreamer URI for a queue item, checking that there is actually something there
+/// to play.
+fn get_item_uri(item: &QueueItem) -> Result<String, PlayError> {
+    let path = item.get_path().ok_or(PlayError::NotASong)?;
+    if !path.exists() {
+        return Err(PlayError::FileMissing(path.to_path_buf()));
+    }
+    create_gst_uri(path).ok_or_else(|| PlayError::InvalidPath(path.to_path_buf()))
+}
+
 struct SharedPlayerContents {
     glib_loop: gst::glib::MainLoop,
+    /// The queue item that the playbin has been told to play, but hasn't started streaming yet.
+    /// It becomes the now playing item once the stream-start message shows up on the bus.
+    pending_item: Option<QueueItem>,
+    crossfade: CrossfadeSettings,
+    /// Set once the song after the current one has been taken from the queue, either in
+    /// about-to-finish or once it's time to start crossfading.
+    next_song_planned: bool,
+    /// The next song and its URI, waiting for the current song to get to the crossfade.
+    crossfade_item: Option<(QueueItem, String)>,
+    /// The playbin that is currently fading out, if any
+    fading_playbin: Option<usize>,
+    /// Bumped whenever playback is interrupted, so a fade in progress knows to give up.
+    fade_generation: usize,
+    replaygain: ReplayGainSettings,
+    equalizer: EqualizerSettings,
+    speed: SpeedSettings,
+    /// Set when the active playbin needs a rate seek, either because the speed changed or
+    /// because a new song started, which always starts out at normal speed.
+    rate_pending: bool,
+    output: AudioOutput,
+    sleep: SleepSettings,
+    /// Set when the sleep timer decided that playback stops once the current song ends
+    stop_requested: bool,
+    /// Set when a requested stop was cancelled after about-to-finish, so the next song has to
+    /// be started once the current one ends.
+    play_next_on_eos: bool,
+    ab_loop: AbLoop,
+    play_tracker: PlayTracker,
+    volume: f64,
+}
+
+/// What the player is doing, as far as anyone outside of it is concerned
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped,
 }
 
 #[derive(Clone)]
 pub struct GstPlayer {
-    playbin: gst::Element,
     shared: Arc<RwLock<SharedPlayerContents>>,
     now_playing: Arc<RwLock<NowPlaying>>,
 }
@@ -43,7 +141,6 @@ impl Drop for SharedPlayerContents {
 impl GstPlayer {
     pub fn new() -> Self {
         Self {
-            playbin: PLAYBIN.clone(),
             shared: SHARED_STATE.clone(),
             now_playing: NOW_PLAYING.clone(),
         }
@@ -58,10 +155,22 @@ impl GstPlayer {
         });
     }
 
-    fn setup_audio_tag_update_cb() {
-        PLAYBIN
+    fn get_active_index() -> usize {
+        ACTIVE_PLAYBIN.load(Ordering::SeqCst)
+    }
+
+    fn get_active_playbin() -> &'static gst::Element {
+        &PLAYBINS[Self::get_active_index()]
+    }
+
+    fn setup_audio_tag_update_cb(playbin: &gst::Element) {
+        playbin
             .connect("audio-tags-changed", false, move |args| {
                 let playbin = args[0].get::<gst::Element>().unwrap().unwrap();
+                // Tags from the song that is fading out are stale
+                if playbin != *Self::get_active_playbin() {
+                    return None;
+                }
 
                 let stream_idx = args[1].get_some::<i32>().unwrap();
 
@@ -74,37 +183,489 @@ impl GstPlayer {
                     .unwrap();
 
                 let mut now_playing_hdl = NOW_PLAYING.write().unwrap();
-                let title = if let Some(title) = tags.get::<gst::tags::Title>() {
-                    title.get().unwrap_or("").to_string()
-                } else {
-                    "".to_string()
-                };
-                let artist = if let Some(artist) = tags.get::<gst::tags::Artist>() {
-                    artist.get().unwrap_or("").to_string()
-                } else {
-                    "".to_string()
-                };
+                let title = tags
+                    .get::<gst::tags::Title>()
+                    .and_then(|title| title.get().map(|title| title.to_string()));
+                let artist = tags
+                    .get::<gst::tags::Artist>()
+                    .and_then(|artist| artist.get().map(|artist| artist.to_string()));
+                let album = tags
+                    .get::<gst::tags::Album>()
+                    .and_then(|album| album.get().map(|album| album.to_string()));
+                let genre = tags
+                    .get::<gst::tags::Genre>()
+                    .and_then(|genre| genre.get().map(|genre| genre.to_string()));
 
                 now_playing_hdl.set_tags(artist, title);
+                let grouping_changed = (album.is_some() && album != now_playing_hdl.get_album())
+                    || (genre.is_some() && genre != now_playing_hdl.get_genre());
+                now_playing_hdl.set_album_and_genre(album, genre);
+                drop(now_playing_hdl);
+
+                if grouping_changed {
+                    Self::auto_select_equalizer();
+                    Self::auto_select_speed();
+                }
                 None
             })
             .unwrap();
     }
 
-    fn setup_next_song_in_queue_cb() {
-        PLAYBIN
+    fn setup_next_song_in_queue_cb(playbin: &gst::Element) {
+        playbin
             .connect("about-to-finish", false, move |args| {
                 let playbin = args[0].get::<gst::Element>().unwrap().unwrap();
-
-                if let Some(ref next_song) = QUEUE.write().unwrap().next_song() {
-                    let uri_str = create_gst_uri(next_song.get_path()?).unwrap();
-                    playbin.set_property("uri", &uri_str).unwrap();
+                if playbin == *Self::get_active_playbin() {
+                    Self::plan_next_song();
                 }
                 None
             })
             .unwrap();
     }
 
+    /// Take the next song from the queue. Depending on the crossfade settings it is either
+    /// queued up on the active playbin to be played gaplessly, or kept around until it is time
+    /// to start fading it in.
+    fn plan_next_song() {
+        let current_item = NOW_PLAYING.read().unwrap().get_item();
+        let mut queue = QUEUE.write().unwrap();
+        let mut shared = SHARED_STATE.write().unwrap();
+        if shared.next_song_planned {
+            return;
+        }
+        shared.next_song_planned = true;
+
+        // Leaving the uri alone makes the playbin post an EOS once the song ends, which then
+        // stops playback.
+        if shared.sleep.song_finishing() {
+            shared.stop_requested = true;
+            return;
+        }
+
+        // If there is no next song we leave the uri alone, the playbin will then post an EOS
+        // message which stops playback in the bus watch. Retrying a song here wouldn't help
+        // since we can't even build a URI for it, so skip straight over broken ones.
+        while let Some(next_song) = queue.next_song() {
+            match get_item_uri(&next_song) {
+                Ok(uri_str) => {
+                    if shared.output.can_crossfade()
+                        && shared
+                            .crossfade
+                            .should_crossfade(current_item.as_ref(), &next_song)
+                    {
+                        shared.crossfade_item = Some((next_song, uri_str));
+                    } else {
+                        shared.pending_item = Some(next_song);
+                        Self::get_active_playbin()
+                            .set_property("uri", &uri_str)
+                            .unwrap();
+                    }
+                    break;
+                }
+                Err(e) => queue.mark_current_failed(&e.to_string()),
+            }
+        }
+    }
+
+    /// Start the crossfade once the active playbin gets close enough to the end of the song.
+    fn check_crossfade_start(playbin: &gst::Element) {
+        let (fade_duration, rate) = {
+            let shared = SHARED_STATE.read().unwrap();
+            // A crossfade planned before switching to a file is played without fading on EOS
+            if !shared.crossfade.is_enabled() || !shared.output.can_crossfade() {
+                return;
+            }
+            (shared.crossfade.get_duration(), shared.speed.get_rate())
+        };
+
+        let (_, cur_state, _) = playbin.get_state(ClockTime::from_mseconds(0));
+        if cur_state != gst::State::Playing {
+            return;
+        }
+
+        let position = playbin
+            .query_position::<gst::ClockTime>()
+            .and_then(|ct| ct.mseconds());
+        let duration = playbin
+            .query_duration::<gst::ClockTime>()
+            .and_then(|ct| ct.mseconds());
+        // Position and duration are in media time, the fade is in real time
+        let remaining = match (position, duration) {
+            (Some(position), Some(duration)) if duration > 0 => {
+                Duration::milliseconds((duration.saturating_sub(position) as f64 / rate) as i64)
+            }
+            _ => return,
+        };
+        if remaining > fade_duration {
+            return;
+        }
+
+        // Songs that aren't crossfaded are left to about-to-finish, so the queue only moves on
+        // once the current song is really about to end.
+        if !SHARED_STATE.read().unwrap().next_song_planned && !Self::next_song_crossfades() {
+            return;
+        }
+        Self::plan_next_song();
+        let crossfade_item = SHARED_STATE.write().unwrap().crossfade_item.take();
+        if let Some((next_song, uri_str)) = crossfade_item {
+            Self::start_crossfade(next_song, &uri_str, remaining);
+        }
+    }
+
+    /// Whether the song after the current one is going to be crossfaded, if it is known yet.
+    fn next_song_crossfades() -> bool {
+        let next_song = match QUEUE.read().unwrap().peek_next_song() {
+            Some(next_song) => next_song,
+            None => return false,
+        };
+        let current_item = NOW_PLAYING.read().unwrap().get_item();
+        SHARED_STATE
+            .read()
+            .unwrap()
+            .crossfade
+            .should_crossfade(current_item.as_ref(), &next_song)
+    }
+
+    fn start_crossfade(next_song: QueueItem, uri_str: &str, fade_duration: Duration) {
+        let old_idx = Self::get_active_index();
+        let new_idx = 1 - old_idx;
+        let old_playbin = &PLAYBINS[old_idx];
+        let new_playbin = &PLAYBINS[new_idx];
+
+        // Make sure anything left over from the last crossfade is gone
+        new_playbin.set_state(gst::State::Null).unwrap();
+        new_playbin.set_property("volume", &0.0f64).unwrap();
+        new_playbin.set_property("uri", &uri_str).unwrap();
+
+        let generation = {
+            let mut shared = SHARED_STATE.write().unwrap();
+            shared.pending_item = Some(next_song);
+            shared.fading_playbin = Some(old_idx);
+            shared.fade_generation += 1;
+            shared.fade_generation
+        };
+        ACTIVE_PLAYBIN.store(new_idx, Ordering::SeqCst);
+
+        log::debug!("Crossfading over {}ms", fade_duration.num_milliseconds());
+        if new_playbin.set_state(gst::State::Playing).is_err() {
+            // The error shows up on the bus as well, which moves on to the next song
+            log::error!("Could not start playing {}", uri_str);
+        }
+
+        let steps = (fade_duration.num_milliseconds() / FADE_STEP_MS as i64).max(1);
+        let mut step = 0;
+        glib::timeout_add(FADE_STEP_MS, move || {
+            if SHARED_STATE.read().unwrap().fade_generation != generation {
+                return glib::Continue(false);
+            }
+
+            // Hold the fade while paused
+            let (_, cur_state, _) = PLAYBINS[new_idx].get_state(ClockTime::from_mseconds(0));
+            if cur_state != gst::State::Playing {
+                return glib::Continue(true);
+            }
+
+            step += 1;
+            let (fade_out, fade_in) = get_fade_volumes(step as f64 / steps as f64);
+            PLAYBINS[old_idx].set_property("volume", &fade_out).unwrap();
+            PLAYBINS[new_idx].set_property("volume", &fade_in).unwrap();
+
+            if step >= steps {
+                Self::finish_crossfade();
+                return glib::Continue(false);
+            }
+            glib::Continue(true)
+        });
+    }
+
+    fn finish_crossfade() {
+        Self::stop_fading_playbin();
+        Self::get_active_playbin()
+            .set_property("volume", &1.0f64)
+            .unwrap();
+    }
+
+    fn stop_fading_playbin() {
+        let fading_playbin = SHARED_STATE.write().unwrap().fading_playbin.take();
+        if let Some(idx) = fading_playbin {
+            PLAYBINS[idx].set_state(gst::State::Null).unwrap();
+            PLAYBINS[idx].set_property("volume", &1.0f64).unwrap();
+        }
+    }
+
+    fn apply_replaygain(playbin_idx: usize, in_album: bool) {
+        if let Some(ref filter) = AUDIO_FILTERS[playbin_idx] {
+            filter.set_replaygain(&SHARED_STATE.read().unwrap().replaygain, in_album);
+        }
+    }
+
+    fn apply_equalizer(playbin_idx: usize) {
+        if let Some(ref filter) = AUDIO_FILTERS[playbin_idx] {
+            filter.set_equalizer(&SHARED_STATE.read().unwrap().equalizer.get_effective_gains());
+        }
+    }
+
+    /// Pick the equalizer preset for the album or genre of the current song, if there is one.
+    fn auto_select_equalizer() {
+        if !SHARED_STATE.read().unwrap().equalizer.get_auto_select() {
+            return;
+        }
+
+        let (album, genre) = {
+            let now_playing = NOW_PLAYING.read().unwrap();
+            (now_playing.get_album(), now_playing.get_genre())
+        };
+        let preset = EqPreset::get_auto(album.as_deref(), genre.as_deref());
+        SHARED_STATE
+            .write()
+            .unwrap()
+            .equalizer
+            .set_auto_preset(preset);
+        Self::apply_equalizer(Self::get_active_index());
+    }
+
+    /// Pick the speed remembered for the current track or its genre, if there is one.
+    fn auto_select_speed() {
+        let (path, genre) = {
+            let now_playing = NOW_PLAYING.read().unwrap();
+            let path = now_playing
+                .get_item()
+                .and_then(|item| item.get_path().map(|path| path.to_path_buf()));
+            (path, now_playing.get_genre())
+        };
+        let rate = path.and_then(|path| {
+            playback_speed::get_remembered_speed(&path, genre.as_deref())
+        });
+
+        let mut shared = SHARED_STATE.write().unwrap();
+        shared.speed.set_remembered_rate(rate);
+        shared.rate_pending = (shared.speed.get_rate() - 1.0).abs() > f64::EPSILON;
+    }
+
+    /// Do the rate seek for the playback speed once the playbin is far enough along to know its
+    /// position.
+    fn apply_pending_rate(playbin: &gst::Element) {
+        if !SHARED_STATE.read().unwrap().rate_pending {
+            return;
+        }
+
+        let position = match playbin.query_position::<gst::ClockTime>() {
+            Some(position) if position.is_some() => position,
+            _ => return,
+        };
+        let seek_result = Self::seek_playbin(playbin, position, true);
+        match seek_result {
+            Ok(()) => SHARED_STATE.write().unwrap().rate_pending = false,
+            Err(e) => log::debug!("Could not change the playback speed yet: {}", e),
+        }
+    }
+
+    /// Add the song that just stopped playing to the play history.
+    fn finish_play_record(end: PlayEnd) {
+        let record = SHARED_STATE.write().unwrap().play_tracker.finish(end);
+        if let Some(mut record) = record {
+            record.save();
+            // A long song skipped after four minutes still counts as a listen
+            Scrobble::record_play(&record);
+        }
+    }
+
+    /// Every seek has to go through here, it keeps the playback speed and the A-B loop. Seeks
+    /// within the loop are segment seeks that stop at the B point and post a segment-done
+    /// message instead of playing on.
+    fn seek_playbin(
+        playbin: &gst::Element,
+        position: gst::ClockTime,
+        flush: bool,
+    ) -> Result<(), glib::BoolError> {
+        let (rate, ab_loop) = {
+            let shared = SHARED_STATE.read().unwrap();
+            (shared.speed.get_rate(), shared.ab_loop.clone())
+        };
+
+        let mut flags = gst::SeekFlags::ACCURATE;
+        if flush {
+            flags |= gst::SeekFlags::FLUSH;
+        }
+        let (stop_type, stop) = match (ab_loop.get_start(), ab_loop.get_end()) {
+            (Some(start), Some(end)) => {
+                let start = ClockTime::from_mseconds(start.num_milliseconds() as u64);
+                let end = ClockTime::from_mseconds(end.num_milliseconds() as u64);
+                if position >= start && position < end {
+                    flags |= gst::SeekFlags::SEGMENT;
+                    (gst::SeekType::Set, end)
+                } else {
+                    (gst::SeekType::None, ClockTime::none())
+                }
+            }
+            _ => (gst::SeekType::None, ClockTime::none()),
+        };
+
+        playbin.seek(rate, flags, gst::SeekType::Set, position, stop_type, stop)
+    }
+
+    /// Fade out and stop once a timed sleep timer runs out.
+    fn check_sleep_timer(playbin: &gst::Element) {
+        let (expired, volume, fading) = {
+            let shared = SHARED_STATE.read().unwrap();
+            (
+                shared.sleep.is_expired(),
+                shared.sleep.get_fade_volume(),
+                shared.fading_playbin.is_some(),
+            )
+        };
+
+        if expired {
+            let player = Self::new();
+            player.shared.write().unwrap().sleep.set_timer(None);
+            player.stop_with(PlayEnd::Stopped);
+            player
+                .now_playing_mut()
+                .set_status_message("Sleep timer: stopped playback".to_string());
+        } else if volume < 1.0 && !fading {
+            // The crossfade sets the volumes itself, the fade out picks up after it
+            playbin.set_property("volume", &volume).unwrap();
+        }
+    }
+
+    fn setup_bus_watch(playbin_idx: usize) {
+        let bus = PLAYBINS[playbin_idx]
+            .get_bus()
+            .expect("playbin without a bus");
+        bus.add_watch(move |_, msg| {
+            Self::handle_bus_message(playbin_idx, msg);
+            glib::Continue(true)
+        })
+        .unwrap();
+    }
+
+    fn handle_bus_message(playbin_idx: usize, msg: &gst::Message) {
+        let is_active = playbin_idx == Self::get_active_index();
+        match msg.view() {
+            MessageView::StreamStart(..) if is_active => {
+                // This is posted once the sinks start receiving data from the new stream, which
+                // for gapless playback is well after about-to-finish picked the next song.
+                let pending_item = {
+                    let mut shared = SHARED_STATE.write().unwrap();
+                    shared.next_song_planned = false;
+                    shared.pending_item.take()
+                };
+                // Without a pending item this is the same song starting over, e.g. after
+                // switching the audio output
+                if let Some(item) = pending_item {
+                    // A new song only starts on its own once the previous one finished
+                    Self::finish_play_record(PlayEnd::Completed);
+                    let track_id = match item {
+                        QueueItem::Track(ref track) => track.id,
+                        _ => item.get_path().and_then(history::get_track_id),
+                    };
+                    {
+                        let mut shared = SHARED_STATE.write().unwrap();
+                        shared.ab_loop.clear();
+                        if let Some(track_id) = track_id {
+                            shared.play_tracker.start(track_id);
+                        }
+                    }
+
+                    NOW_PLAYING.write().unwrap().set_item(Some(item));
+                    let in_album = {
+                        let mut queue = QUEUE.write().unwrap();
+                        queue.song_started();
+                        queue.is_current_song_in_album()
+                    };
+                    Self::apply_replaygain(playbin_idx, in_album);
+                    Self::auto_select_equalizer();
+                }
+                // Even a song that was queued gaplessly starts out at normal speed
+                Self::auto_select_speed();
+            }
+            MessageView::SegmentDone(..) if is_active => {
+                // Reached the B point, go back to A without flushing so the loop is seamless
+                let start = SHARED_STATE.read().unwrap().ab_loop.get_start();
+                if let Some(start) = start {
+                    let start = ClockTime::from_mseconds(start.num_milliseconds() as u64);
+                    if let Err(e) = Self::seek_playbin(&PLAYBINS[playbin_idx], start, false) {
+                        log::warn!("Could not loop back to the A point: {}", e);
+                    }
+                }
+            }
+            MessageView::Eos(..) if !is_active => Self::stop_fading_playbin(),
+            MessageView::Eos(..) => {
+                Self::finish_play_record(PlayEnd::Completed);
+                let (stop_requested, play_next) = {
+                    let mut shared = SHARED_STATE.write().unwrap();
+                    let play_next = shared.play_next_on_eos;
+                    shared.play_next_on_eos = false;
+                    (shared.stop_requested, play_next)
+                };
+                if stop_requested {
+                    log::debug!("Stopping after the current song");
+                    let player = Self::new();
+                    player.stop_with(PlayEnd::Stopped);
+                    player
+                        .now_playing_mut()
+                        .set_status_message("Stopped after the current song".to_string());
+                    return;
+                }
+                if play_next {
+                    let next_song = QUEUE.write().unwrap().next_song();
+                    if let Some(next_song) = next_song {
+                        if let Err(e) = Self::new().play_item(next_song) {
+                            QUEUE.write().unwrap().current_song_failed(&e.to_string());
+                        }
+                        return;
+                    }
+                }
+
+                // If the song ended before the crossfade could start, e.g. because its length
+                // isn't known, just play the next song without fading.
+                let crossfade_item = SHARED_STATE.write().unwrap().crossfade_item.take();
+                if let Some((next_song, _)) = crossfade_item {
+                    if let Err(e) = Self::new().play_item(next_song) {
+                        QUEUE.write().unwrap().current_song_failed(&e.to_string());
+                    }
+                    return;
+                }
+
+                log::debug!("End of stream reached, stopping playback");
+                Self::new().stop_with(PlayEnd::Stopped);
+                QUEUE.write().unwrap().finish();
+            }
+            MessageView::Error(err) => {
+                log::error!(
+                    "Error from {}: {} ({})",
+                    msg.get_src()
+                        .map(|src| src.get_path_string().to_string())
+                        .unwrap_or_default(),
+                    err.get_error(),
+                    err.get_debug().unwrap_or_default()
+                );
+                if !is_active {
+                    Self::stop_fading_playbin();
+                    return;
+                }
+                // Stop before grabbing the queue, stopping waits on the streaming threads which
+                // might be waiting on the queue themselves in about-to-finish.
+                Self::new().stop_with(PlayEnd::Stopped);
+                QUEUE
+                    .write()
+                    .unwrap()
+                    .current_song_failed(&err.get_error().to_string());
+            }
+            MessageView::Warning(warning) => {
+                log::warn!(
+                    "Warning from {}: {} ({})",
+                    msg.get_src()
+                        .map(|src| src.get_path_string().to_string())
+                        .unwrap_or_default(),
+                    warning.get_error(),
+                    warning.get_debug().unwrap_or_default()
+                );
+            }
+            _ => (),
+        }
+    }
+
     fn setup_progress_poller() {
         glib::timeout_add(100, move || {
             let (old_position, old_duration);
@@ -114,12 +675,13 @@ impl GstPlayer {
                 old_duration = progress_pair.1;
             }
 
-            let new_position = PLAYBIN
+            let playbin = Self::get_active_playbin();
+            let new_position = playbin
                 .query_position::<gst::ClockTime>()
                 .map_or(Duration::zero(), |ct| {
                     Duration::seconds(ct.seconds().unwrap_or(0) as i64)
                 });
-            let new_duration = PLAYBIN
+            let new_duration = playbin
                 .query_duration::<gst::ClockTime>()
                 .map_or(Duration::zero(), |ct| {
                     Duration::seconds(ct.seconds().unwrap_or(0) as i64)
@@ -132,6 +694,27 @@ impl GstPlayer {
                     .set_progress(new_position, new_duration);
             }
 
+            let (_, cur_state, _) = playbin.get_state(ClockTime::from_mseconds(0));
+            SHARED_STATE
+                .write()
+                .unwrap()
+                .play_tracker
+                .tick(cur_state == gst::State::Playing, Some(new_duration));
+
+            let replaygain = AUDIO_FILTERS[Self::get_active_index()]
+                .as_ref()
+                .and_then(|filter| filter.get_replaygain());
+            if NOW_PLAYING.read().unwrap().get_replaygain() != replaygain {
+                NOW_PLAYING.write().unwrap().set_replaygain(replaygain);
+            }
+            if NOW_PLAYING.read().unwrap().is_status_message_expired() {
+                NOW_PLAYING.write().unwrap().clear_status_message();
+            }
+
+            Self::apply_pending_rate(playbin);
+            Self::check_crossfade_start(playbin);
+            Self::check_sleep_timer(playbin);
+
             glib::Continue(true)
         });
     }
@@ -139,16 +722,68 @@ impl GstPlayer {
     fn construct_shared_state() -> Arc<RwLock<SharedPlayerContents>> {
         let shared = Arc::new(RwLock::new(SharedPlayerContents {
             glib_loop: glib::MainLoop::new(None, false),
+            pending_item: None,
+            crossfade: CrossfadeSettings::new(),
+            next_song_planned: false,
+            crossfade_item: None,
+            fading_playbin: None,
+            fade_generation: 0,
+            replaygain: ReplayGainSettings::new(),
+            equalizer: EqualizerSettings::new(),
+            speed: SpeedSettings::new(),
+            rate_pending: false,
+            output: AudioOutput::Auto,
+            sleep: SleepSettings::new(),
+            stop_requested: false,
+            play_next_on_eos: false,
+            ab_loop: AbLoop::new(),
+            play_tracker: PlayTracker::new(),
+            volume: 1.0,
         }));
 
         Self::setup_glib_loop_thread(shared.clone());
-        Self::setup_audio_tag_update_cb();
-        Self::setup_next_song_in_queue_cb();
+        for (idx, playbin) in PLAYBINS.iter().enumerate() {
+            if let Some(ref filter) = AUDIO_FILTERS[idx] {
+                filter.set_replaygain(&shared.read().unwrap().replaygain, false);
+                filter.set_equalizer(&shared.read().unwrap().equalizer.get_effective_gains());
+                filter.set_volume(shared.read().unwrap().volume);
+                playbin
+                    .set_property("audio-filter", &filter.get_element())
+                    .unwrap();
+            }
+            Self::setup_audio_tag_update_cb(playbin);
+            Self::setup_next_song_in_queue_cb(playbin);
+            Self::setup_bus_watch(idx);
+        }
         Self::setup_progress_poller();
 
+        let saved_output = settings::get_setting(OUTPUT_SETTING).and_then(|output| {
+            output
+                .parse::<AudioOutput>()
+                .map_err(|e| log::warn!("Ignoring the saved audio output: {}", e))
+                .ok()
+        });
+        if let Some(output) = saved_output {
+            match Self::set_audio_sinks(&output) {
+                Ok(()) => shared.write().unwrap().output = output,
+                Err(e) => log::warn!("Could not use audio output {}: {}", output, e),
+            }
+        }
+
         shared
     }
 
+    /// Put a sink for the output on both playbins, they have to be stopped for this.
+    fn set_audio_sinks(output: &AudioOutput) -> Result<(), String> {
+        let sinks = [output.create_sink()?, output.create_sink()?];
+        for (playbin, sink) in PLAYBINS.iter().zip(sinks.iter()) {
+            playbin
+                .set_property("audio-sink", sink)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
     pub fn queue(&self) -> RwLockReadGuard<Queue> {
         QUEUE.read().unwrap()
     }
@@ -167,34 +802,629 @@ impl GstPlayer {
 
     /// This should only be accessed by the Queue, if you want to play a
     /// file, add it to the queue then play it from there.
-    pub(super) fn play_file<S: Into<PathBuf>>(&self, fname: S) {
+    pub(super) fn play_item(&self, item: QueueItem) -> Result<(), PlayError> {
         self.stop();
 
-        let uri_str = create_gst_uri(&fname.into()).unwrap();
-        self.playbin.set_property("uri", &uri_str).unwrap();
+        let uri_str = get_item_uri(&item)?;
+        self.shared.write().unwrap().pending_item = Some(item);
+        let playbin = Self::get_active_playbin();
+        playbin.set_property("uri", &uri_str).unwrap();
 
-        self.playbin.set_state(gst::State::Playing).unwrap();
+        playbin
+            .set_state(gst::State::Playing)
+            .map_err(PlayError::StateChange)?;
+        Ok(())
     }
 
+    /// Stop playback. The current song counts as skipped if not enough of it was played.
     pub fn stop(&self) {
-        // Shutdown playbin
-        self.playbin
-            .set_state(gst::State::Null)
-            .expect("Unable to set the pipeline to the `Null` state");
+        self.stop_with(PlayEnd::Interrupted);
+    }
+
+    /// Stop playback, `end` says how the play record of the current song ends.
+    pub fn stop_with(&self, end: PlayEnd) {
+        Self::finish_play_record(end);
+        {
+            let mut shared = self.shared.write().unwrap();
+            shared.pending_item = None;
+            shared.next_song_planned = false;
+            shared.crossfade_item = None;
+            shared.fading_playbin = None;
+            shared.fade_generation += 1;
+            shared.stop_requested = false;
+            shared.play_next_on_eos = false;
+            shared.ab_loop.clear();
+        }
+
+        // Shutdown both playbins, one of them might still be fading out
+        for playbin in PLAYBINS.iter() {
+            playbin
+                .set_state(gst::State::Null)
+                .expect("Unable to set the pipeline to the `Null` state");
+            playbin.set_property("volume", &1.0f64).unwrap();
+        }
+
+        self.now_playing.write().unwrap().set_item(None);
     }
 
     pub fn toggle_play_pause(&self) {
-        let (_, cur_state, _) = self.playbin.get_state(ClockTime::from_mseconds(50));
-        match cur_state {
-            gst::State::Playing => {
-                self.playbin.set_state(gst::State::Paused).unwrap();
-            }
-            gst::State::Paused => {
-                self.playbin.set_state(gst::State::Playing).unwrap();
-            }
+        let playbin = Self::get_active_playbin();
+        let (_, cur_state, _) = playbin.get_state(ClockTime::from_mseconds(50));
+        let new_state = match cur_state {
+            gst::State::Playing => gst::State::Paused,
+            gst::State::Paused => gst::State::Playing,
             _ => {
                 log::trace!("Do nothing on toggle on state {:?}", cur_state);
+                return;
+            }
+        };
+
+        playbin.set_state(new_state).unwrap();
+        if let Some(idx) = self.shared.read().unwrap().fading_playbin {
+            PLAYBINS[idx].set_state(new_state).unwrap();
+        }
+    }
+
+    pub fn get_playback_state(&self) -> PlaybackState {
+        let (_, cur_state, pending_state) =
+            Self::get_active_playbin().get_state(ClockTime::from_mseconds(50));
+        // Report where the playbin is going, a song that was just started is already playing
+        let state = match pending_state {
+            gst::State::VoidPending => cur_state,
+            _ => pending_state,
+        };
+        match state {
+            gst::State::Playing => PlaybackState::Playing,
+            gst::State::Paused => PlaybackState::Paused,
+            _ => PlaybackState::Stopped,
+        }
+    }
+
+    /// Resume a paused song, or start playing the queue if nothing is playing.
+    pub fn play(&self) {
+        match self.get_playback_state() {
+            PlaybackState::Playing => (),
+            PlaybackState::Paused => self.toggle_play_pause(),
+            PlaybackState::Stopped => self.queue_mut().play_queue_at_selection(),
+        }
+    }
+
+    pub fn pause(&self) {
+        if self.get_playback_state() == PlaybackState::Playing {
+            self.toggle_play_pause();
+        }
+    }
+
+    pub fn play_next(&self) {
+        self.queue_mut().play_queue();
+    }
+
+    pub fn play_previous(&self) {
+        self.queue_mut().play_previous();
+    }
+
+    /// Show a message in the status line, for things the player didn't do itself
+    pub fn set_status_message(&self, message: String) {
+        self.now_playing_mut().set_status_message(message);
+    }
+
+    pub fn get_volume(&self) -> f64 {
+        self.shared.read().unwrap().volume
+    }
+
+    /// Set the volume from 0.0 to 1.0. This is separate from the playbin volume, which is used
+    /// to fade songs in and out.
+    pub fn set_volume(&self, volume: f64) {
+        let volume = volume.max(0.0).min(1.0);
+        self.shared.write().unwrap().volume = volume;
+        for filter in AUDIO_FILTERS.iter().flatten() {
+            filter.set_volume(volume);
+        }
+        self.now_playing_mut()
+            .set_status_message(format!("Volume: {:.0}%", volume * 100.0));
+    }
+
+    pub fn get_crossfade(&self) -> CrossfadeSettings {
+        self.shared.read().unwrap().crossfade.clone()
+    }
+
+    pub fn set_crossfade_duration(&self, duration: Duration) {
+        let (duration, can_crossfade) = {
+            let mut shared = self.shared.write().unwrap();
+            shared.crossfade.set_duration(duration);
+            (shared.crossfade.get_duration(), shared.output.can_crossfade())
+        };
+
+        let message = if duration > Duration::zero() && !can_crossfade {
+            format!(
+                "Crossfade: {}s, but not while writing to a file",
+                duration.num_seconds()
+            )
+        } else if duration > Duration::zero() {
+            format!("Crossfade: {}s", duration.num_seconds())
+        } else {
+            "Crossfade: off".to_string()
+        };
+        self.now_playing_mut().set_status_message(message);
+    }
+
+    pub fn get_replaygain(&self) -> ReplayGainSettings {
+        self.shared.read().unwrap().replaygain.clone()
+    }
+
+    /// Change the ReplayGain settings, `update` gets to modify the current settings which are
+    /// then applied to both playbins.
+    fn update_replaygain<F>(&self, update: F)
+    where
+        F: FnOnce(&mut ReplayGainSettings),
+    {
+        update(&mut self.shared.write().unwrap().replaygain);
+
+        let in_album = self.queue().is_current_song_in_album();
+        let settings = self.get_replaygain();
+        for filter in AUDIO_FILTERS.iter().flatten() {
+            filter.set_replaygain(&settings, in_album);
+        }
+
+        self.now_playing_mut().set_status_message(format!(
+            "ReplayGain: {}, pre-amp {:+.1}dB, clipping prevention {}",
+            settings.get_mode(),
+            settings.get_preamp(),
+            if settings.get_prevent_clipping() { "on" } else { "off" }
+        ));
+    }
+
+    pub fn set_replaygain_mode(&self, mode: ReplayGainMode) {
+        self.update_replaygain(|settings| settings.set_mode(mode));
+    }
+
+    pub fn set_replaygain_preamp(&self, preamp: f64) {
+        self.update_replaygain(|settings| settings.set_preamp(preamp));
+    }
+
+    pub fn set_replaygain_prevent_clipping(&self, prevent_clipping: bool) {
+        self.update_replaygain(|settings| settings.set_prevent_clipping(prevent_clipping));
+    }
+
+    pub fn get_equalizer(&self) -> EqualizerSettings {
+        self.shared.read().unwrap().equalizer.clone()
+    }
+
+    /// Change the equalizer settings and apply them to both playbins.
+    fn update_equalizer<F>(&self, update: F)
+    where
+        F: FnOnce(&mut EqualizerSettings),
+    {
+        update(&mut self.shared.write().unwrap().equalizer);
+
+        let settings = self.get_equalizer();
+        for filter in AUDIO_FILTERS.iter().flatten() {
+            filter.set_equalizer(&settings.get_effective_gains());
+        }
+
+        let message = if settings.is_enabled() {
+            format!(
+                "Equalizer: {}{}",
+                settings.get_preset().name,
+                if settings.is_auto_selected() { " (auto)" } else { "" }
+            )
+        } else {
+            "Equalizer: off".to_string()
+        };
+        self.now_playing_mut().set_status_message(message);
+    }
+
+    pub fn set_equalizer_enabled(&self, enabled: bool) {
+        self.update_equalizer(|settings| settings.set_enabled(enabled));
+    }
+
+    pub fn set_equalizer_preset(&self, preset: EqPreset) {
+        self.update_equalizer(|settings| settings.set_preset(preset));
+    }
+
+    pub fn set_equalizer_band(&self, band: usize, gain: f64) {
+        self.update_equalizer(|settings| settings.set_band_gain(band, gain));
+    }
+
+    pub fn set_equalizer_auto_select(&self, auto_select: bool) {
+        self.update_equalizer(|settings| settings.set_auto_select(auto_select));
+        if auto_select {
+            Self::auto_select_equalizer();
+        }
+    }
+
+    pub fn get_output(&self) -> AudioOutput {
+        self.shared.read().unwrap().output.clone()
+    }
+
+    /// Switch to another audio output and remember it for the next start. If something is
+    /// playing the playbin is briefly stopped and picks up where it left off.
+    pub fn set_output(&self, output: AudioOutput) -> Result<(), String> {
+        // A song fading out doesn't get to move to the new output
+        self.shared.write().unwrap().fade_generation += 1;
+        Self::finish_crossfade();
+
+        let playbin = Self::get_active_playbin();
+        let (_, cur_state, _) = playbin.get_state(ClockTime::from_mseconds(50));
+        let position = playbin.query_position::<gst::ClockTime>();
+        playbin
+            .set_state(gst::State::Null)
+            .map_err(|e| e.to_string())?;
+
+        let result = Self::set_audio_sinks(&output);
+        if result.is_ok() {
+            self.shared.write().unwrap().output = output.clone();
+            settings::set_setting(OUTPUT_SETTING, &output.to_string());
+        }
+
+        if cur_state == gst::State::Playing || cur_state == gst::State::Paused {
+            // Preroll paused first, seeking only works once the pipeline knows its duration
+            playbin
+                .set_state(gst::State::Paused)
+                .map_err(|e| e.to_string())?;
+            let _ = playbin.get_state(ClockTime::from_seconds(5));
+            if let Some(position) = position.filter(|position| position.is_some()) {
+                let _ = Self::seek_playbin(playbin, position, true);
             }
+            self.shared.write().unwrap().rate_pending = true;
+            playbin.set_state(cur_state).map_err(|e| e.to_string())?;
         }
+
+        let message = match result {
+            Ok(()) => format!("Audio output: {}", output),
+            Err(ref e) => format!("Could not switch to {}: {}", output, e),
+        };
+        self.now_playing_mut().set_status_message(message);
+        result
+    }
+
+    /// Jump to a position in the current song
+    pub fn seek_to(&self, position: Duration) {
+        let position = position.max(Duration::zero());
+        let clock_time = ClockTime::from_mseconds(position.num_milliseconds() as u64);
+        match Self::seek_playbin(Self::get_active_playbin(), clock_time, true) {
+            Ok(()) => self.now_playing_mut().set_seeked(position),
+            Err(e) => log::warn!("Could not seek: {}", e),
+        }
+    }
+
+    pub fn get_ab_loop(&self) -> AbLoop {
+        self.shared.read().unwrap().ab_loop.clone()
+    }
+
+    /// Set the A or B point of the loop at the current position, see `AbLoop::set_point`.
+    pub fn set_ab_loop_point(&self) {
+        // Now playing only knows the position to the second, which isn't good enough for a loop
+        let position = match Self::get_active_playbin()
+            .query_position::<gst::ClockTime>()
+            .and_then(|ct| ct.mseconds())
+        {
+            Some(ms) => Duration::milliseconds(ms as i64),
+            None => return,
+        };
+        let ab_loop = {
+            let mut shared = self.shared.write().unwrap();
+            shared.ab_loop.set_point(position);
+            shared.ab_loop.clone()
+        };
+
+        let message = match (ab_loop.get_start(), ab_loop.get_end()) {
+            (Some(start), Some(end)) => {
+                // Jump back to A right away, this also turns the seek into a segment seek
+                self.seek_to(start);
+                format!(
+                    "A-B loop: {:.1}s to {:.1}s",
+                    start.num_milliseconds() as f64 / 1000.0,
+                    end.num_milliseconds() as f64 / 1000.0
+                )
+            }
+            (Some(start), None) => format!(
+                "A-B loop: A set at {:.1}s",
+                start.num_milliseconds() as f64 / 1000.0
+            ),
+            _ => return,
+        };
+        self.now_playing_mut().set_status_message(message);
+    }
+
+    pub fn clear_ab_loop(&self) {
+        if !self.get_ab_loop().is_active() {
+            self.shared.write().unwrap().ab_loop.clear();
+            return;
+        }
+
+        self.shared.write().unwrap().ab_loop.clear();
+        // A plain seek drops the stop position of the segment so the song plays on to the end
+        let playbin = Self::get_active_playbin();
+        if let Some(position) = playbin.query_position::<gst::ClockTime>() {
+            let _ = Self::seek_playbin(playbin, position, true);
+        }
+        self.now_playing_mut()
+            .set_status_message("A-B loop: off".to_string());
+    }
+
+    pub fn get_sleep(&self) -> SleepSettings {
+        self.shared.read().unwrap().sleep.clone()
+    }
+
+    /// Start a sleep timer, replacing the one that is running. `None` cancels it.
+    pub fn set_sleep_timer(&self, timer: Option<SleepTimer>) {
+        let message = match timer {
+            Some(SleepTimer::Time { .. }) => {
+                let remaining = timer
+                    .as_ref()
+                    .and_then(|timer| timer.get_remaining_time())
+                    .unwrap_or_else(Duration::zero);
+                format!("Sleep timer: {} minutes", (remaining.num_seconds() + 59) / 60)
+            }
+            Some(SleepTimer::Tracks(tracks)) => format!("Sleep timer: {} more songs", tracks),
+            None => "Sleep timer: off".to_string(),
+        };
+
+        let was_fading_out = {
+            let mut shared = self.shared.write().unwrap();
+            let was_fading_out = shared.sleep.get_fade_volume() < 1.0;
+            shared.sleep.set_timer(timer);
+            Self::cancel_stop_request(&mut shared);
+            was_fading_out
+        };
+        if was_fading_out {
+            Self::get_active_playbin()
+                .set_property("volume", &1.0f64)
+                .unwrap();
+        }
+        self.now_playing_mut().set_status_message(message);
+    }
+
+    fn cancel_stop_request(shared: &mut SharedPlayerContents) {
+        if shared.stop_requested {
+            shared.stop_requested = false;
+            shared.play_next_on_eos = true;
+        }
+    }
+
+    pub fn set_stop_after_current(&self, stop_after_current: bool) {
+        {
+            let mut shared = self.shared.write().unwrap();
+            shared.sleep.set_stop_after_current(stop_after_current);
+            if !stop_after_current {
+                Self::cancel_stop_request(&mut shared);
+            }
+        }
+
+        let message = if stop_after_current {
+            "Stop after the current song: on"
+        } else {
+            "Stop after the current song: off"
+        };
+        self.now_playing_mut().set_status_message(message.to_string());
+    }
+
+    pub fn get_speed(&self) -> SpeedSettings {
+        self.shared.read().unwrap().speed.clone()
+    }
+
+    /// Change the playback speed, the pitch stays the same. Progress in `NowPlaying` is still
+    /// reported in media time.
+    pub fn set_speed(&self, rate: f64) {
+        let rate = {
+            let mut shared = self.shared.write().unwrap();
+            shared.speed.set_rate(rate);
+            shared.rate_pending = true;
+            shared.speed.get_rate()
+        };
+        Self::apply_pending_rate(Self::get_active_playbin());
+        self.now_playing_mut()
+            .set_status_message(format!("Speed: {:.2}x", rate));
+    }
+
+    /// Always play the current track, or all songs of its genre, at the current speed.
+    pub fn remember_speed(&self, kind: SpeedKind) {
+        let key = match kind {
+            SpeedKind::Track => self.now_playing().get_item().and_then(|item| {
+                item.get_path()
+                    .and_then(|path| path.to_str())
+                    .map(|path| path.to_string())
+            }),
+            SpeedKind::Genre => self
+                .now_playing()
+                .get_genre()
+                .map(|genre| genre.to_lowercase()),
+        };
+        let key = match key {
+            Some(key) => key,
+            None => return,
+        };
+
+        let rate = self.get_speed().get_rate();
+        if (rate - 1.0).abs() < f64::EPSILON {
+            playback_speed::forget_speed(kind, &key);
+        } else {
+            playback_speed::remember_speed(kind, &key, rate);
+        }
+        self.now_playing_mut()
+            .set_status_message(format!("Speed {:.2}x remembered for {}", rate, key));
+    }
+
+    pub fn set_crossfade_within_album(&self, fade_within_album: bool) {
+        self.shared
+            .write()
+            .unwrap()
+            .crossfade
+            .set_fade_within_album(fade_within_album);
+
+        let message = if fade_within_album {
+            "Crossfade within albums: on"
+        } else {
+            "Crossfade within albums: off"
+        };
+        self.now_playing_mut().set_status_message(message.to_string());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use lazy_static::lazy_static;
+
+    use crate::library::Track;
+
+    lazy_static! {
+        static ref TOP_DIR: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    }
+
+    /// Run a test pipeline to completion, feeding every bus message through the player's bus
+    /// handler. `on_message` is called after each message has been handled.
+    fn run_pipeline<F>(description: &str, mut on_message: F)
+    where
+        F: FnMut(&gst::Message),
+    {
+        let pipeline = gst::parse_launch(description).unwrap();
+        let bus = pipeline.get_bus().unwrap();
+        pipeline.set_state(gst::State::Playing).unwrap();
+
+        loop {
+            let msg = bus
+                .timed_pop(ClockTime::from_seconds(5))
+                .expect("Timed out waiting for the test pipeline");
+            GstPlayer::handle_bus_message(GstPlayer::get_active_index(), &msg);
+            on_message(&msg);
+
+            if let MessageView::Eos(..) | MessageView::Error(..) = msg.view() {
+                break;
+            }
+        }
+
+        pipeline.set_state(gst::State::Null).unwrap();
+    }
+
+    // Everything touches the global player state, so the tests that use it take TEST_LOCK to
+    // avoid racing each other.
+    #[test]
+    fn stream_start_and_end_of_queue() {
+        let _lock = crate::player::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        gst::init().unwrap();
+
+        let player = GstPlayer::new();
+        let first = QueueItem::new_from_path(TOP_DIR.join("resources/test.ogg"));
+        let second = QueueItem::new_from_path(TOP_DIR.join("resources/test.mp3"));
+        player
+            .queue_mut()
+            .replace_queue(vec![first.clone(), second.clone()]);
+
+        // Pretend about-to-finish picked the first song, it should only show up as playing once
+        // the stream actually starts.
+        assert_eq!(player.queue_mut().next_song().unwrap().get_path(), first.get_path());
+        player.shared.write().unwrap().pending_item = Some(first.clone());
+        assert!(player.now_playing().get_item().is_none());
+
+        let mut saw_stream_start = false;
+        run_pipeline("audiotestsrc num-buffers=10 ! fakesink", |msg| {
+            if let MessageView::StreamStart(..) = msg.view() {
+                saw_stream_start = true;
+                let now_playing = player.now_playing().get_item();
+                assert_eq!(now_playing.unwrap().get_path(), first.get_path());
+                assert_eq!(player.now_playing().get_song_name(), "test");
+            }
+        });
+        assert!(saw_stream_start, "No stream-start message seen");
+
+        // The EOS at the end of the queue stops everything.
+        assert!(player.now_playing().get_item().is_none());
+        assert!(player.queue().get_queue_position().is_none());
+        let (_, cur_state, _) =
+            GstPlayer::get_active_playbin().get_state(ClockTime::from_mseconds(50));
+        assert_eq!(cur_state, gst::State::Null);
+
+        // Playing the queue again starts from the top.
+        assert_eq!(player.queue_mut().next_song().unwrap().get_path(), first.get_path());
+        assert_eq!(player.queue_mut().next_song().unwrap().get_path(), second.get_path());
+        assert!(player.queue_mut().next_song().is_none());
+        assert_eq!(player.queue().get_queue_position(), Some(1));
+
+        // Missing files get retried, then skipped for good.
+        let missing = QueueItem::new_from_path(TOP_DIR.join("resources/BLARG_I_DONT_EXIST.mp3"));
+        let mut queue = player.queue_mut();
+        queue.replace_queue(vec![missing.clone(), first.clone(), QueueItem::RepeatQueue]);
+        assert_eq!(queue.next_song().unwrap().get_path(), missing.get_path());
+        let e = get_item_uri(&missing).unwrap_err().to_string();
+        assert!(queue.retry_or_mark_failed(&e));
+        assert!(!queue.is_failed(&missing));
+        assert!(!queue.retry_or_mark_failed(&e));
+        assert!(queue.is_failed(&missing));
+        assert!(player.now_playing().get_status_message().unwrap().contains("BLARG"));
+
+        assert_eq!(queue.next_song().unwrap().get_path(), first.get_path());
+        assert_eq!(queue.next_song().unwrap().get_path(), first.get_path());
+
+        // Once everything has failed there is nothing left to play, even when repeating.
+        queue.mark_current_failed("broken");
+        assert!(queue.next_song().is_none());
+    }
+
+    #[test]
+    fn crossfade_only_pre_rolls_crossfaded_songs() {
+        let _lock = crate::player::TEST_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        gst::init().unwrap();
+
+        let track = |file: &str, album: &str| {
+            QueueItem::new_from_track(Track {
+                path: TOP_DIR.join(file),
+                album: Some(album.to_string()),
+                ..Default::default()
+            })
+        };
+        let first = track("resources/test.ogg", "Abbey Road");
+        let second = track("resources/test.mp3", "Abbey Road");
+        let third = QueueItem::new_from_path(TOP_DIR.join("resources/test.ogg"));
+
+        let player = GstPlayer::new();
+        player.stop();
+        player.set_crossfade_duration(Duration::seconds(5));
+        player
+            .queue_mut()
+            .replace_queue(vec![first.clone(), second.clone(), third.clone()]);
+        let item = player.queue_mut().next_song();
+        player.now_playing_mut().set_item(item);
+
+        // The next song is from the same album, so it is played gaplessly and the queue stays
+        // put until about-to-finish.
+        assert!(!GstPlayer::next_song_crossfades());
+        assert_eq!(player.queue().get_queue_position(), Some(0));
+        GstPlayer::plan_next_song();
+        {
+            let shared = player.shared.read().unwrap();
+            assert_eq!(shared.pending_item.as_ref().unwrap().get_path(), second.get_path());
+            assert!(shared.crossfade_item.is_none());
+        }
+        assert_eq!(player.queue().get_queue_position(), Some(1));
+
+        // A song from somewhere else is kept around to be faded in
+        player.stop();
+        player.now_playing_mut().set_item(Some(second));
+        assert!(GstPlayer::next_song_crossfades());
+        GstPlayer::plan_next_song();
+        {
+            let shared = player.shared.read().unwrap();
+            assert!(shared.pending_item.is_none());
+            let (next_song, _) = shared.crossfade_item.as_ref().unwrap();
+            assert_eq!(next_song.get_path(), third.get_path());
+        }
+
+        // Nothing left to fade to at the end of the queue
+        player.stop();
+        player.now_playing_mut().set_item(Some(third));
+        assert!(!GstPlayer::next_song_crossfades());
+
+        player.set_crossfade_duration(Duration::zero());
+        player.now_playing_mut().set_item(None);
+        player.queue_mut().clear_queue();
     }
 }
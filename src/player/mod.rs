@@ -1,10 +1,33 @@
+mod ab_loop;
+mod audio_filter;
+mod crossfade;
+mod equalizer;
 mod gstreamer;
 mod now_playing;
+mod output;
+mod play_tracker;
+mod playlist_file;
 mod queue;
+mod replaygain;
+mod sleep_timer;
+mod speed;
 mod util;
 
 pub use self::gstreamer::GstPlayer as PlayerHdl;
-pub use self::util::is_audio_file_guess;
+pub use self::gstreamer::PlaybackState;
+pub use self::util::{create_gst_uri, expand_paths, is_audio_file_guess};
 
+pub use self::equalizer::{BAND_FREQUENCIES, MAX_BAND_GAIN_DB, MIN_BAND_GAIN_DB};
+pub use self::output::{list_outputs, AudioOutput};
+pub use self::play_tracker::PlayEnd;
+pub use self::playlist_file::{get_playlist_dir, write_playlist};
+pub use self::sleep_timer::SleepTimer;
+pub use self::speed::{MAX_RATE, MIN_RATE};
 pub use queue::Queue;
 pub use queue::QueueItem;
+
+#[cfg(test)]
+lazy_static::lazy_static! {
+    /// The player is global, tests that change the queue hold this so they don't race.
+    pub static ref TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+}
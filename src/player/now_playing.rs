@@ -1,12 +1,26 @@
+use std::time::{Duration as StdDuration, Instant};
+
 use chrono::Duration;
 
+use super::queue::QueueItem;
 use crate::util::{Notifier, NotifierCb};
 
+/// How long a status message stays on screen
+const STATUS_MESSAGE_SECS: u64 = 5;
+
 pub struct NowPlaying {
     progress: Duration,
     song_len: Duration,
     artist: String,
     song: String,
+    item: Option<QueueItem>,
+    album: Option<String>,
+    genre: Option<String>,
+    /// The message and when it was set
+    status_message: Option<(String, Instant)>,
+    replaygain: Option<f64>,
+    /// Bumped on every seek, so watchers can tell a jump apart from the song playing on
+    seek_count: u32,
     notifier: Notifier,
 }
 
@@ -18,6 +32,12 @@ impl NowPlaying {
             song_len: Duration::zero(),
             artist: Default::default(),
             song: Default::default(),
+            item: None,
+            album: None,
+            genre: None,
+            status_message: None,
+            replaygain: None,
+            seek_count: 0,
             notifier: Default::default(),
         }
     }
@@ -40,13 +60,30 @@ impl NowPlaying {
         self.notifier.notify();
     }
 
+    /// Only tags that are present in the stream override the currently displayed ones, so a file
+    /// without a title tag keeps showing the title from the library or the file name.
     pub(super) fn set_tags (
         &mut self,
-        artist: String,
-        song: String,
+        artist: Option<String>,
+        song: Option<String>,
     ) {
-        self.artist = artist;
-        self.song = song;
+        if let Some(artist) = artist {
+            self.artist = artist;
+        }
+        if let Some(song) = song {
+            self.song = song;
+        }
+        self.notifier.notify();
+    }
+
+    /// Like `set_tags`, but for the tags that decide which equalizer preset gets picked.
+    pub(super) fn set_album_and_genre(&mut self, album: Option<String>, genre: Option<String>) {
+        if album.is_some() {
+            self.album = album;
+        }
+        if genre.is_some() {
+            self.genre = genre;
+        }
         self.notifier.notify();
     }
 
@@ -60,6 +97,68 @@ impl NowPlaying {
         self.notifier.notify();
     }
 
+    /// The position jumped because of a seek, no matter who asked for it.
+    pub(super) fn set_seeked(&mut self, progress: Duration) {
+        self.progress = progress;
+        self.seek_count = self.seek_count.wrapping_add(1);
+        self.notifier.notify();
+    }
+
+    /// Switch to a new queue item, seeding the artist and title from the library if we know
+    /// about the track. Passing `None` means nothing is playing anymore.
+    pub(super) fn set_item(&mut self, item: Option<QueueItem>) {
+        let (artist, song) = match item {
+            Some(QueueItem::Track(ref track)) => (
+                track.artist.clone().unwrap_or_default(),
+                track.title.clone().unwrap_or_default(),
+            ),
+            Some(ref item) => (
+                String::new(),
+                item.get_path()
+                    .and_then(|path| path.file_stem())
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+            None => (String::new(), String::new()),
+        };
+
+        self.progress = Duration::zero();
+        self.song_len = Duration::zero();
+        self.artist = artist;
+        self.song = song;
+        self.album = match item {
+            Some(QueueItem::Track(ref track)) => track.album.clone(),
+            _ => None,
+        };
+        self.genre = None;
+        self.item = item;
+        self.notifier.notify();
+    }
+
+    /// Messages for the user about things that happened in the player, like songs that had to
+    /// be skipped.
+    pub(super) fn set_status_message(&mut self, message: String) {
+        self.status_message = Some((message, Instant::now()));
+        self.notifier.notify();
+    }
+
+    /// Whether the status message has been shown for long enough, the player polls this.
+    pub(super) fn is_status_message_expired(&self) -> bool {
+        matches!(self.status_message, Some((_, set_at))
+            if set_at.elapsed() >= StdDuration::from_secs(STATUS_MESSAGE_SECS))
+    }
+
+    pub fn clear_status_message(&mut self) {
+        self.status_message = None;
+        self.notifier.notify();
+    }
+
+    /// The ReplayGain currently applied in dB, `None` if ReplayGain is disabled.
+    pub(super) fn set_replaygain(&mut self, replaygain: Option<f64>) {
+        self.replaygain = replaygain;
+        self.notifier.notify();
+    }
+
     pub fn get_song_progress(&self) -> (Duration, Duration) {
         (self.progress.clone(), self.song_len.clone())
     }
@@ -71,4 +170,30 @@ impl NowPlaying {
     pub fn get_song_name(&self) -> String {
         self.song.clone()
     }
+
+    pub fn get_item(&self) -> Option<QueueItem> {
+        self.item.clone()
+    }
+
+    pub fn get_album(&self) -> Option<String> {
+        self.album.clone()
+    }
+
+    pub fn get_genre(&self) -> Option<String> {
+        self.genre.clone()
+    }
+
+    pub fn get_status_message(&self) -> Option<String> {
+        self.status_message
+            .as_ref()
+            .map(|(message, _)| message.clone())
+    }
+
+    pub fn get_replaygain(&self) -> Option<f64> {
+        self.replaygain
+    }
+
+    pub fn get_seek_count(&self) -> u32 {
+        self.seek_count
+    }
 }
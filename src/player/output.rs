@@ -0,0 +1,202 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use gst::prelude::*;
+
+/// Where the audio goes. Outputs are stored as strings like `alsa:hw:0`, see `FromStr`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioOutput {
+    /// Let autoaudiosink pick
+    Auto,
+    Pulse(Option<String>),
+    Alsa(Option<String>),
+    PipeWire(Option<String>),
+    /// A device found by the device monitor, by its display name
+    Device(String),
+    /// Write the audio to a WAV file
+    File(PathBuf),
+    /// Throw the audio away, for testing and headless runs
+    Fake,
+}
+
+impl fmt::Display for AudioOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let with_device =
+            |f: &mut fmt::Formatter<'_>, name: &str, device: &Option<String>| match device {
+                Some(device) => write!(f, "{}:{}", name, device),
+                None => write!(f, "{}", name),
+            };
+
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Pulse(device) => with_device(f, "pulse", device),
+            Self::Alsa(device) => with_device(f, "alsa", device),
+            Self::PipeWire(target) => with_device(f, "pipewire", target),
+            Self::Device(name) => write!(f, "device:{}", name),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Fake => write!(f, "fake"),
+        }
+    }
+}
+
+impl FromStr for AudioOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+        let kind = split.next().unwrap_or("");
+        let arg = split.next().map(|arg| arg.to_string());
+
+        match (kind, arg) {
+            ("auto", None) => Ok(Self::Auto),
+            ("pulse", device) => Ok(Self::Pulse(device)),
+            ("alsa", device) => Ok(Self::Alsa(device)),
+            ("pipewire", target) => Ok(Self::PipeWire(target)),
+            ("device", Some(name)) => Ok(Self::Device(name)),
+            ("file", Some(path)) => Ok(Self::File(path.into())),
+            ("fake", None) => Ok(Self::Fake),
+            _ => Err(format!("unknown audio output \"{}\"", s)),
+        }
+    }
+}
+
+fn make_sink(factory_name: &str) -> Result<gst::Element, String> {
+    gst::ElementFactory::make(factory_name, None)
+        .map_err(|_| format!("{} is not available", factory_name))
+}
+
+impl AudioOutput {
+    /// Crossfading plays the two songs on two playbins at once. Each of them has a sink of its
+    /// own, and two filesinks can't write the same file, so files only get one song at a time.
+    pub fn can_crossfade(&self) -> bool {
+        !matches!(self, Self::File(_))
+    }
+
+    /// Build the sink element for the playbin's audio-sink property. Each playbin needs a sink of
+    /// its own, so this is called once per playbin.
+    pub fn create_sink(&self) -> Result<gst::Element, String> {
+        match self {
+            Self::Auto => make_sink("autoaudiosink"),
+            Self::Pulse(device) | Self::Alsa(device) => {
+                let sink = make_sink(if let Self::Pulse(_) = self {
+                    "pulsesink"
+                } else {
+                    "alsasink"
+                })?;
+                if let Some(device) = device {
+                    sink.set_property("device", device)
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(sink)
+            }
+            Self::PipeWire(target) => {
+                let sink = make_sink("pipewiresink")?;
+                if let Some(target) = target {
+                    // Older versions of pipewiresink call this property path
+                    let property = if sink.find_property("target-object").is_some() {
+                        "target-object"
+                    } else {
+                        "path"
+                    };
+                    sink.set_property(property, target)
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(sink)
+            }
+            Self::Device(name) => find_device(name)
+                .ok_or_else(|| format!("audio device \"{}\" not found", name))?
+                .create_element(None)
+                .map_err(|e| e.to_string()),
+            Self::File(path) => {
+                let bin = gst::parse_bin_from_description(
+                    "audioconvert ! wavenc ! filesink name=filesink",
+                    true,
+                )
+                .map_err(|e| e.to_string())?;
+                bin.get_by_name("filesink")
+                    .unwrap()
+                    .set_property("location", &path.to_string_lossy().to_string())
+                    .map_err(|e| e.to_string())?;
+                Ok(bin.upcast())
+            }
+            Self::Fake => {
+                let sink = make_sink("fakesink")?;
+                // Without sync the songs would be over as fast as they can be decoded
+                sink.set_property("sync", &true)
+                    .map_err(|e| e.to_string())?;
+                Ok(sink)
+            }
+        }
+    }
+}
+
+fn get_audio_devices() -> Vec<gst::Device> {
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter(Some("Audio/Sink"), None);
+    if let Err(e) = monitor.start() {
+        log::warn!("Could not start the device monitor: {}", e);
+        return Vec::new();
+    }
+    let devices = monitor.get_devices();
+    monitor.stop();
+    devices
+}
+
+fn find_device(name: &str) -> Option<gst::Device> {
+    get_audio_devices()
+        .into_iter()
+        .find(|device| device.get_display_name() == name)
+}
+
+/// All the audio outputs that can be picked right now, including the devices that are
+/// connected.
+pub fn list_outputs() -> Vec<AudioOutput> {
+    let mut outputs = vec![AudioOutput::Auto];
+    for (factory_name, output) in [
+        ("pulsesink", AudioOutput::Pulse(None)),
+        ("alsasink", AudioOutput::Alsa(None)),
+        ("pipewiresink", AudioOutput::PipeWire(None)),
+    ]
+    .iter()
+    {
+        if gst::ElementFactory::find(factory_name).is_some() {
+            outputs.push(output.clone());
+        }
+    }
+    outputs.extend(
+        get_audio_devices()
+            .iter()
+            .map(|device| AudioOutput::Device(device.get_display_name().to_string())),
+    );
+    outputs.push(AudioOutput::Fake);
+    outputs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn output_strings() {
+        let outputs = [
+            AudioOutput::Auto,
+            AudioOutput::Pulse(None),
+            AudioOutput::Alsa(Some("hw:0,1".to_string())),
+            AudioOutput::PipeWire(Some("alsa_output.usb".to_string())),
+            AudioOutput::Device("Built-in Audio Analog Stereo".to_string()),
+            AudioOutput::File(PathBuf::from("/tmp/out.wav")),
+            AudioOutput::Fake,
+        ];
+        for output in outputs.iter() {
+            assert_eq!(&output.to_string().parse::<AudioOutput>().unwrap(), output);
+        }
+
+        assert!(AudioOutput::Fake.can_crossfade());
+        assert!(!AudioOutput::File(PathBuf::from("/tmp/out.wav")).can_crossfade());
+
+        assert!("auto:hw:0".parse::<AudioOutput>().is_err());
+        assert!("file".parse::<AudioOutput>().is_err());
+        assert!("speakers".parse::<AudioOutput>().is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::Instant;
+
+use chrono::{Duration, Utc};
+
+use crate::config;
+use crate::library::history::PlayRecord;
+
+/// How the song that was being tracked ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayEnd {
+    /// It played until the end
+    Completed,
+    /// Something else was played or playback was stopped, a skip if not enough of it played
+    Interrupted,
+    /// Playback stopped without anyone choosing to, like after an error, when the sleep timer
+    /// ran out or when musicom quit. Never a skip.
+    Stopped,
+}
+
+/// Keeps track of how long the current song has been playing, to record it in the play history
+/// once something else starts.
+#[derive(Debug, Default)]
+pub struct PlayTracker {
+    current: Option<PlayRecord>,
+    /// When the time played was last added up, `None` while paused
+    last_tick: Option<Instant>,
+}
+
+impl PlayTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Start tracking a new song, the previous one should be finished first.
+    pub fn start(&mut self, track_id: i32) {
+        self.current = Some(PlayRecord {
+            id: None,
+            track_id,
+            started_at: Utc::now(),
+            listened: Duration::zero(),
+            duration: None,
+            completed: false,
+            skipped: false,
+        });
+        self.last_tick = Some(Instant::now());
+    }
+
+    /// Called regularly while the player runs to add up the time that was played.
+    pub fn tick(&mut self, playing: bool, duration: Option<Duration>) {
+        let current = match self.current {
+            Some(ref mut current) => current,
+            None => return,
+        };
+
+        if let Some(last_tick) = self.last_tick.take() {
+            current.listened = current.listened
+                + Duration::from_std(last_tick.elapsed()).unwrap_or_else(|_| Duration::zero());
+        }
+        if playing {
+            self.last_tick = Some(Instant::now());
+        }
+        if duration.map_or(false, |duration| duration > Duration::zero()) {
+            current.duration = duration;
+        }
+    }
+
+    /// Stop tracking the current song. Interrupted songs are skipped if not enough of them was
+    /// played.
+    pub fn finish(&mut self, end: PlayEnd) -> Option<PlayRecord> {
+        let mut record = self.current.take()?;
+        if let Some(last_tick) = self.last_tick.take() {
+            record.listened = record.listened
+                + Duration::from_std(last_tick.elapsed()).unwrap_or_else(|_| Duration::zero());
+        }
+
+        record.completed = end == PlayEnd::Completed;
+        record.skipped = end == PlayEnd::Interrupted
+            && is_skip(
+                record.listened,
+                record.duration,
+                config::get().player.skip_threshold_percent,
+            );
+        Some(record)
+    }
+}
+
+fn is_skip(listened: Duration, duration: Option<Duration>, threshold_percent: f64) -> bool {
+    match duration {
+        Some(duration) if duration > Duration::zero() => {
+            let percent =
+                listened.num_milliseconds() as f64 * 100.0 / duration.num_milliseconds() as f64;
+            percent < threshold_percent
+        }
+        // Without a duration all we can go by is whether anything was heard
+        _ => listened < Duration::seconds(10),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn skip_detection() {
+        let minutes = |m| Some(Duration::minutes(m));
+        assert!(is_skip(Duration::seconds(30), minutes(4), 50.0));
+        assert!(!is_skip(Duration::minutes(3), minutes(4), 50.0));
+        assert!(is_skip(Duration::minutes(3), minutes(4), 80.0));
+        assert!(!is_skip(Duration::seconds(30), minutes(4), 0.0));
+        assert!(is_skip(Duration::seconds(1), None, 50.0));
+        assert!(!is_skip(Duration::minutes(1), None, 50.0));
+
+        let mut tracker = PlayTracker::new();
+        assert!(tracker.finish(PlayEnd::Completed).is_none());
+        tracker.start(1);
+        tracker.tick(true, minutes(4));
+
+        let record = tracker.finish(PlayEnd::Interrupted).unwrap();
+        assert_eq!(record.track_id, 1);
+        assert_eq!(record.duration, minutes(4));
+        assert!(!record.completed);
+        assert!(record.skipped);
+
+        // Songs that played until the end are never skipped, even if they were seeked through
+        tracker.start(2);
+        let record = tracker.finish(PlayEnd::Completed).unwrap();
+        assert_eq!(record.track_id, 2);
+        assert!(record.completed);
+        assert!(!record.skipped);
+        assert!(tracker.finish(PlayEnd::Interrupted).is_none());
+
+        // Nobody skipped a song that stopped because of an error
+        tracker.start(3);
+        let record = tracker.finish(PlayEnd::Stopped).unwrap();
+        assert!(!record.completed);
+        assert!(!record.skipped);
+    }
+}
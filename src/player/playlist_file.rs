@@ -0,0 +1,149 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use url::Url;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlaylistFormat {
+    M3u,
+    Pls,
+}
+
+fn playlist_format(path: &Path) -> Option<PlaylistFormat> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "m3u" | "m3u8" => Some(PlaylistFormat::M3u),
+        "pls" => Some(PlaylistFormat::Pls),
+        _ => None,
+    }
+}
+
+pub fn is_playlist_file(path: &Path) -> bool {
+    playlist_format(path).is_some()
+}
+
+/// The entry in a line of the playlist, if the line has one
+fn parse_line(format: PlaylistFormat, line: &str) -> Option<&str> {
+    match format {
+        PlaylistFormat::M3u if line.is_empty() || line.starts_with('#') => None,
+        PlaylistFormat::M3u => Some(line),
+        PlaylistFormat::Pls => {
+            let split = line.find('=')?;
+            if line[..split].to_lowercase().starts_with("file") {
+                Some(&line[split + 1..])
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Relative entries are relative to the directory of the playlist. Streams are skipped, only local
+/// files can be played.
+fn resolve_entry(playlist_dir: &Path, entry: &str) -> Option<PathBuf> {
+    if entry.starts_with("file://") {
+        Url::parse(entry).ok()?.to_file_path().ok()
+    } else if entry.contains("://") {
+        None
+    } else {
+        Some(playlist_dir.join(entry))
+    }
+}
+
+/// The paths of the songs in an M3U or PLS playlist, in order
+pub fn read_playlist(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let format = playlist_format(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a playlist file"))?;
+    // Old M3U files aren't UTF-8, the paths that are still have to work
+    let contents = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+    let playlist_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    Ok(contents
+        .lines()
+        .filter_map(|line| parse_line(format, line.trim()))
+        .filter_map(|entry| resolve_entry(playlist_dir, entry))
+        .collect())
+}
+
+/// Where saved queues are kept
+pub fn get_playlist_dir() -> PathBuf {
+    crate::util::get_project_dirs()
+        .config_dir()
+        .join("playlists")
+}
+
+/// Write the paths to an M3U playlist, which `read_playlist` and other players can read.
+pub fn write_playlist(path: &Path, songs: &[PathBuf]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut contents = "#EXTM3U\n".to_string();
+    for song in songs {
+        contents.push_str(&song.to_string_lossy());
+        contents.push('\n');
+    }
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_playlists() {
+        let dir = std::env::temp_dir().join("musicom-playlist-test");
+        fs::create_dir_all(&dir).unwrap();
+
+        let m3u = dir.join("test.m3u");
+        fs::write(
+            &m3u,
+            "#EXTM3U\n\
+             #EXTINF:200,George - Test 1\n\
+             test1.mp3\n\
+             \n\
+             /music/test2.mp3\n\
+             file:///music/test%203.mp3\n\
+             http://example.com/stream.mp3\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_playlist(&m3u).unwrap(),
+            vec![
+                dir.join("test1.mp3"),
+                PathBuf::from("/music/test2.mp3"),
+                PathBuf::from("/music/test 3.mp3"),
+            ]
+        );
+
+        let pls = dir.join("test.PLS");
+        fs::write(
+            &pls,
+            "[playlist]\n\
+             File1=test1.mp3\n\
+             Title1=Test 1\n\
+             File2=/music/test2.mp3\n\
+             NumberOfEntries=2\n",
+        )
+        .unwrap();
+        assert!(is_playlist_file(&pls));
+        assert_eq!(
+            read_playlist(&pls).unwrap(),
+            vec![dir.join("test1.mp3"), PathBuf::from("/music/test2.mp3")]
+        );
+
+        assert!(!is_playlist_file(&dir.join("test1.mp3")));
+    }
+
+    #[test]
+    fn write_then_read_playlist() {
+        let path = std::env::temp_dir().join("musicom-playlist-test/saved/queue.m3u");
+        let songs = vec![
+            PathBuf::from("/music/test1.mp3"),
+            PathBuf::from("/music/test 2.mp3"),
+        ];
+
+        write_playlist(&path, &songs).unwrap();
+        assert_eq!(read_playlist(&path).unwrap(), songs);
+    }
+}
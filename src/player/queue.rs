@@ -1,8 +1,10 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 
+use crate::config;
 use crate::library::Track;
 use crate::util::{Notifier, NotifierCb};
 use crate::player::PlayerHdl;
@@ -21,8 +23,15 @@ pub enum QueueItem {
 pub struct Queue {
     player: PlayerHdl,
     items: Vec<QueueItem>,
+    /// An id for every item that stays the same while the queue changes around it
+    ids: Vec<u32>,
+    next_id: u32,
     cur_idx: Option<usize>,
+    /// Where the queue carries on once the current song has been removed from it
+    resume_idx: Option<usize>,
     cur_repeat_count: usize,
+    failed: HashSet<PathBuf>,
+    cur_retry_count: usize,
     notifier: Notifier,
 }
 
@@ -53,27 +62,98 @@ impl Queue {
         Queue {
             player: PlayerHdl::new(),
             items: Vec::new(),
+            ids: Vec::new(),
+            next_id: 0,
             cur_idx: None,
+            resume_idx: None,
             cur_repeat_count: 0,
+            failed: HashSet::new(),
+            cur_retry_count: 0,
             notifier: Notifier::new(),
         }
     }
 
     pub fn clear_queue(&mut self) {
+        // Keep counting so that the ids of the old items don't come back
+        let next_id = self.next_id;
         *self = Self::new();
+        self.next_id = next_id;
         self.notifier.notify();
     }
 
+    fn new_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    fn push_item(&mut self, item: QueueItem) {
+        let id = self.new_id();
+        self.items.push(item);
+        self.ids.push(id);
+    }
+
+    /// Shuffle the items from `start` on, their ids go with them.
+    fn shuffle_from(&mut self, start: usize) {
+        let mut entries = self
+            .items
+            .drain(start..)
+            .zip(self.ids.drain(start..))
+            .collect::<Vec<_>>();
+        entries.shuffle(&mut thread_rng());
+        for (item, id) in entries {
+            self.items.push(item);
+            self.ids.push(id);
+        }
+    }
+
+    /// The index of the item `next_song` looks at first
+    fn first_next_idx(&self) -> usize {
+        self.cur_idx
+            .map(|cur_idx| cur_idx + 1)
+            .or(self.resume_idx)
+            .unwrap_or(0)
+    }
+
     fn current_queue_item(&self) -> Option<QueueItem> {
         Some(self.items.get(self.cur_idx?)?.clone())
     }
 
+    /// Returns true if the item is a song that we haven't given up on playing.
+    fn is_playable(&self, item: &QueueItem) -> bool {
+        matches!(item, QueueItem::Path(..) | QueueItem::Track(..)) && !self.is_failed(item)
+    }
+
+    pub fn is_failed(&self, item: &QueueItem) -> bool {
+        item.get_path()
+            .map_or(false, |path| self.failed.contains(path))
+    }
+
+    /// True if the song before or after the current one is from the same album, in which case
+    /// the album is probably being played from start to end.
+    pub fn is_current_song_in_album(&self) -> bool {
+        let cur_idx = match self.cur_idx {
+            Some(cur_idx) => cur_idx,
+            None => return false,
+        };
+        let album = match self.items.get(cur_idx) {
+            Some(QueueItem::Track(track)) if track.album.is_some() => &track.album,
+            _ => return false,
+        };
+
+        let is_same_album = |item: Option<&QueueItem>| {
+            matches!(item, Some(QueueItem::Track(track)) if track.album == *album)
+        };
+        let prev_item = cur_idx.checked_sub(1).and_then(|idx| self.items.get(idx));
+        is_same_album(prev_item) || is_same_album(self.items.get(cur_idx + 1))
+    }
+
     fn peek_next_queue_item(&mut self) -> (Option<QueueItem>, Option<usize>) {
         if self.items.is_empty() {
             return (None, None);
         }
 
-        let next_idx = self.cur_idx.clone().map(|val| val + 1).unwrap_or(0);
+        let next_idx = self.first_next_idx();
 
         let next_item = self.items.get(next_idx).map(|item| item.clone());
         let next_idx = next_item.is_some().then(|| next_idx);
@@ -81,9 +161,19 @@ impl Queue {
     }
 
     pub fn next_song(&mut self) -> Option<QueueItem> {
+        // If every song has failed there is nothing left to play, don't spin on the repeat
+        // items looking for one.
+        if !self.items.iter().any(|item| self.is_playable(item)) {
+            return None;
+        }
+
         for _ in 0..1000 {
             let (next_queue_item_opt, next_idx) = self.peek_next_queue_item();
             match next_queue_item_opt {
+                Some(ref item) if self.is_failed(item) => {
+                    self.cur_idx = next_idx;
+                    continue;
+                }
                 Some(QueueItem::Path(..)) | Some(QueueItem::Track(..)) => {
                     self.cur_idx = next_idx;
                     self.notifier.notify();
@@ -99,7 +189,7 @@ impl Queue {
 
                     let current_track: Option<QueueItem> = self
                         .current_queue_item()
-                        .filter(|qi| matches!(qi, QueueItem::Path(..) | QueueItem::Track(..)));
+                        .filter(|qi| self.is_playable(qi));
 
                     if current_track.is_some() {
                         self.cur_repeat_count = self.cur_repeat_count.saturating_add(1);
@@ -114,7 +204,7 @@ impl Queue {
                 Some(QueueItem::RepeatSongForever) => {
                     let current_track: Option<QueueItem> = self
                         .current_queue_item()
-                        .filter(|qi| matches!(qi, QueueItem::Path(..) | QueueItem::Track(..)));
+                        .filter(|qi| self.is_playable(qi));
 
                     if current_track.is_some() {
                         self.cur_repeat_count = self.cur_repeat_count.saturating_add(1);
@@ -127,14 +217,12 @@ impl Queue {
                     }
                 }
                 Some(QueueItem::ShuffleAll) => {
-                    let mut rng = thread_rng();
-                    self.items[..].shuffle(&mut rng);
+                    self.shuffle_from(0);
                     self.cur_idx = Some(0);
                     continue;
                 }
                 Some(QueueItem::ShuffleAfter) => {
-                    let mut rng = thread_rng();
-                    self.items[self.cur_idx.unwrap()..].shuffle(&mut rng);
+                    self.shuffle_from(self.cur_idx.or(self.resume_idx).unwrap_or(0));
                     self.cur_idx = next_idx;
                     continue;
                 }
@@ -143,8 +231,8 @@ impl Queue {
                     continue;
                 }
                 None => {
-                    self.cur_idx = Some(0);
-                    self.notifier.notify();
+                    // Leave the position on the last song, it is still playing. The player
+                    // calls finish() once the song has actually ended.
                     return None;
                 }
             }
@@ -155,53 +243,251 @@ impl Queue {
         None
     }
 
+    /// The song `next_song` is going to return, without moving through the queue. Returns
+    /// `None` if there is no next song, or if it can't be known yet because a shuffle item
+    /// comes first.
+    pub fn peek_next_song(&self) -> Option<QueueItem> {
+        let current_track = self
+            .current_queue_item()
+            .filter(|item| self.is_playable(item));
+        // Repeat items only repeat the current song if they come right after it
+        let first_idx = self.first_next_idx();
+        let mut next_idx = first_idx;
+        let mut wrapped = false;
+
+        while let Some(item) = self.items.get(next_idx) {
+            match item {
+                item if self.is_failed(item) => (),
+                QueueItem::Path(..) | QueueItem::Track(..) => return Some(item.clone()),
+                QueueItem::RepeatSongTimes(times_to_repeat)
+                    if next_idx == first_idx
+                        && self.cur_repeat_count < *times_to_repeat
+                        && current_track.is_some() =>
+                {
+                    return current_track;
+                }
+                QueueItem::RepeatSongForever
+                    if next_idx == first_idx && current_track.is_some() =>
+                {
+                    return current_track;
+                }
+                QueueItem::RepeatSongTimes(..) | QueueItem::RepeatSongForever => (),
+                QueueItem::ShuffleAll | QueueItem::ShuffleAfter => return None,
+                QueueItem::RepeatQueue if wrapped => return None,
+                // next_song makes the first item the current one, then carries on after it
+                QueueItem::RepeatQueue => {
+                    wrapped = true;
+                    next_idx = 1;
+                    continue;
+                }
+            }
+            next_idx += 1;
+        }
+        None
+    }
+
+    /// Called by the player when the end of the queue has been reached and playback stopped.
+    pub(super) fn finish(&mut self) {
+        self.cur_idx = None;
+        self.resume_idx = None;
+        self.cur_repeat_count = 0;
+        self.notifier.notify();
+    }
+
     pub fn replace_queue(&mut self, new_queue: Vec<QueueItem>) {
+        self.ids = new_queue.iter().map(|_| self.new_id()).collect();
         self.items = new_queue;
         self.cur_idx = None;
+        self.resume_idx = None;
+        self.failed.clear();
+        self.cur_retry_count = 0;
         self.notifier.notify();
     }
 
     pub fn set_queue_index(&mut self, index: usize) {
         if index < self.items.len() {
             self.cur_idx = Some(index);
+            self.resume_idx = None;
         }
         self.notifier.notify();
     }
 
     pub fn play_queue(&mut self) {
-        let next_song = self.next_song();
-        if next_song.is_none() {
-            return;
-        }
-        let next_song = next_song.unwrap();
-        let song_path = next_song.get_path().unwrap();
-        self.player.play_file(song_path);
-        self.notifier.notify();
+        self.play_song(true);
     }
 
     pub fn play_queue_at_selection(&mut self) {
-        if self.cur_idx.is_none() {
-            self.play_queue();
-            return;
+        self.play_song(self.cur_idx.is_none());
+    }
+
+    /// Go back to the song before the current one, skipping over the special items and songs
+    /// that failed.
+    pub fn play_previous(&mut self) {
+        let cur_idx = match self.cur_idx {
+            Some(cur_idx) => cur_idx,
+            None => return,
+        };
+        let previous = self.items[..cur_idx].iter().rposition(|item| match item {
+            QueueItem::Path(..) | QueueItem::Track(..) => !self.is_failed(item),
+            _ => false,
+        });
+
+        if let Some(previous) = previous {
+            self.cur_idx = Some(previous);
+            self.cur_repeat_count = 0;
+            self.play_song(false);
         }
+    }
 
-        let current_song = self.items.get(self.cur_idx.unwrap());
-        if current_song.is_none() {
+    /// Called by the player when the current song failed while it was already handed off to
+    /// gstreamer, e.g. because of a decoding error or a missing codec.
+    pub(super) fn current_song_failed(&mut self, reason: &str) {
+        if self.current_queue_item().is_none() {
             return;
         }
-        let current_song = current_song.unwrap();
-        let song_path = current_song.get_path().unwrap();
-        self.player.play_file(song_path);
+        let retry = self.retry_or_mark_failed(reason);
+        self.play_song(!retry);
+    }
+
+    /// Returns true if the current song should be retried, otherwise it is marked as failed so
+    /// that it is skipped from now on.
+    pub(super) fn retry_or_mark_failed(&mut self, reason: &str) -> bool {
+        let item = match self.current_queue_item() {
+            Some(item) => item,
+            None => return false,
+        };
+        let name = item
+            .get_path()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+
+        // Read every time so a reloaded config applies right away
+        let retry_limit = config::get().player.retry_limit;
+        if self.cur_retry_count < retry_limit {
+            self.cur_retry_count += 1;
+            log::warn!(
+                "Could not play {}, retrying ({}/{}): {}",
+                name,
+                self.cur_retry_count,
+                retry_limit,
+                reason
+            );
+            return true;
+        }
+
+        self.mark_current_failed(reason);
+        false
+    }
+
+    /// Give up on the current song, it is skipped from now on.
+    pub(super) fn mark_current_failed(&mut self, reason: &str) {
+        self.cur_retry_count = 0;
+        let path = match self.current_queue_item() {
+            Some(QueueItem::Path(path)) => path,
+            Some(QueueItem::Track(track)) => track.path,
+            _ => return,
+        };
+
+        log::error!("Could not play {}, skipping it: {}", path.display(), reason);
+        self.player
+            .now_playing_mut()
+            .set_status_message(format!("Skipped {}: {}", path.display(), reason));
+        self.failed.insert(path);
         self.notifier.notify();
     }
 
+    /// Start playing a song, either the next one in the queue or the one at the current
+    /// position. Songs that fail to start are retried or skipped until one plays or the queue
+    /// runs out.
+    fn play_song(&mut self, mut advance: bool) {
+        loop {
+            let song = if advance {
+                self.next_song()
+            } else {
+                self.current_queue_item()
+            };
+            let song = match song {
+                Some(song) => song,
+                None => break,
+            };
+
+            match self.player.play_item(song) {
+                Ok(()) => break,
+                Err(e) => advance = !self.retry_or_mark_failed(&e.to_string()),
+            }
+        }
+        self.notifier.notify();
+    }
+
+    /// Called by the player once a song actually started streaming.
+    pub(super) fn song_started(&mut self) {
+        self.cur_retry_count = 0;
+    }
+
     pub fn add_song(&mut self, path: &Path) {
-        self.items.push(QueueItem::new_from_path(path));
+        self.push_item(QueueItem::new_from_path(path));
         self.notifier.notify();
     }
 
     pub fn add_track(&mut self, track: &Track) {
-        self.items.push(QueueItem::new_from_track(track.clone()));
+        self.push_item(QueueItem::new_from_track(track.clone()));
+        self.notifier.notify();
+    }
+
+    /// Remove an item. If it is the current song it keeps playing, but there is no current
+    /// position anymore and the queue continues with the item that came after it.
+    pub fn remove_item(&mut self, index: usize) -> Option<QueueItem> {
+        if index >= self.items.len() {
+            return None;
+        }
+        let item = self.items.remove(index);
+        self.ids.remove(index);
+        match (self.cur_idx, self.resume_idx) {
+            (Some(cur_idx), _) if index < cur_idx => self.cur_idx = Some(cur_idx - 1),
+            (Some(cur_idx), _) if index == cur_idx => {
+                self.cur_idx = None;
+                self.resume_idx = Some(index);
+                self.cur_repeat_count = 0;
+            }
+            (None, Some(resume_idx)) if index < resume_idx => {
+                self.resume_idx = Some(resume_idx - 1)
+            }
+            _ => (),
+        }
+        self.notifier.notify();
+        Some(item)
+    }
+
+    /// Move an item so that it ends up at `to`, the current song stays the current one.
+    pub fn move_item(&mut self, from: usize, to: usize) {
+        if from >= self.items.len() || to >= self.items.len() {
+            return;
+        }
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+        let id = self.ids.remove(from);
+        self.ids.insert(to, id);
+        // The items before the resume position stay before it
+        self.resume_idx = self.resume_idx.map(|resume_idx| {
+            if from < resume_idx && to >= resume_idx {
+                resume_idx - 1
+            } else if from >= resume_idx && to < resume_idx {
+                resume_idx + 1
+            } else {
+                resume_idx
+            }
+        });
+        self.cur_idx = self.cur_idx.map(|cur_idx| {
+            if cur_idx == from {
+                to
+            } else if from < cur_idx && to >= cur_idx {
+                cur_idx - 1
+            } else if from > cur_idx && to <= cur_idx {
+                cur_idx + 1
+            } else {
+                cur_idx
+            }
+        });
         self.notifier.notify();
     }
 
@@ -209,6 +495,11 @@ impl Queue {
         self.items.clone()
     }
 
+    /// The ids of the items, in the same order as `get_queue_contents`
+    pub fn get_item_ids(&self) -> Vec<u32> {
+        self.ids.clone()
+    }
+
     pub fn get_queue_position(&self) -> Option<usize> {
         let cur_idx = self.cur_idx?;
         assert!(cur_idx < self.items.len());
@@ -219,3 +510,31 @@ impl Queue {
         self.notifier.register(cb);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn remove_current_song() {
+        gst::init().unwrap();
+
+        let first = QueueItem::new_from_path("/tmp/test1.mp3");
+        let second = QueueItem::new_from_path("/tmp/test2.mp3");
+        let mut queue = Queue::new();
+        queue.replace_queue(vec![first.clone(), second.clone(), first.clone()]);
+        let ids = queue.get_item_ids();
+        assert_eq!(queue.next_song().unwrap().get_path(), first.get_path());
+
+        // The current song goes away, the queue goes on with the song after it
+        queue.remove_item(0);
+        assert!(queue.get_queue_position().is_none());
+        assert_eq!(queue.get_item_ids(), ids[1..].to_vec());
+        assert_eq!(
+            queue.peek_next_song().unwrap().get_path(),
+            second.get_path()
+        );
+        assert_eq!(queue.next_song().unwrap().get_path(), second.get_path());
+        assert_eq!(queue.get_queue_position(), Some(0));
+    }
+}
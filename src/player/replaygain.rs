@@ -0,0 +1,233 @@
+use std::fmt;
+
+/// Limits for the pre-amp, anything beyond this is better done with the system volume.
+pub const MAX_PREAMP_DB: f64 = 15.0;
+
+/// ReplayGain targets 89dB SPL, which is roughly -18 LUFS, while EBU R128 gains are relative to
+/// -23 LUFS.
+const R128_TO_REPLAYGAIN_DB: f64 = 5.0;
+
+/// Tags that rgvolume looks at to figure out the gain to apply
+const GAIN_TAGS: [&str; 4] = [
+    "replaygain-track-gain",
+    "replaygain-track-peak",
+    "replaygain-album-gain",
+    "replaygain-album-peak",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+    /// Use the album gain when the songs around the current one in the queue are from the same
+    /// album, otherwise the track gain.
+    Auto,
+}
+
+impl ReplayGainMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Track,
+            Self::Track => Self::Album,
+            Self::Album => Self::Auto,
+            Self::Auto => Self::Off,
+        }
+    }
+}
+
+impl fmt::Display for ReplayGainMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Off => "off",
+            Self::Track => "track",
+            Self::Album => "album",
+            Self::Auto => "auto",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReplayGainSettings {
+    mode: ReplayGainMode,
+    preamp: f64,
+    prevent_clipping: bool,
+}
+
+impl ReplayGainSettings {
+    pub fn new() -> Self {
+        Self {
+            mode: ReplayGainMode::Auto,
+            preamp: 0.0,
+            prevent_clipping: true,
+        }
+    }
+
+    pub fn get_mode(&self) -> ReplayGainMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ReplayGainMode) {
+        self.mode = mode;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.mode != ReplayGainMode::Off
+    }
+
+    /// Extra gain in dB applied on top of the gain from the tags
+    pub fn get_preamp(&self) -> f64 {
+        self.preamp
+    }
+
+    pub fn set_preamp(&mut self, preamp: f64) {
+        self.preamp = preamp.max(-MAX_PREAMP_DB).min(MAX_PREAMP_DB);
+    }
+
+    pub fn get_prevent_clipping(&self) -> bool {
+        self.prevent_clipping
+    }
+
+    /// When enabled the gain is lowered if the peak tags say the song would clip, and a limiter
+    /// catches whatever is left.
+    pub fn set_prevent_clipping(&mut self, prevent_clipping: bool) {
+        self.prevent_clipping = prevent_clipping;
+    }
+
+    pub fn use_album_gain(&self, in_album: bool) -> bool {
+        match self.mode {
+            ReplayGainMode::Album => true,
+            ReplayGainMode::Auto => in_album,
+            ReplayGainMode::Off | ReplayGainMode::Track => false,
+        }
+    }
+}
+
+/// R128 gain tags are Q7.8 fixed point numbers in dB, convert them to a ReplayGain value.
+pub fn parse_r128_gain(value: &str) -> Option<f64> {
+    let fixed_point = value.trim().parse::<i16>().ok()?;
+    Some(fixed_point as f64 / 256.0 + R128_TO_REPLAYGAIN_DB)
+}
+
+/// Prepare a tag list for rgvolume. R128 gains only show up as extended comments, so they're
+/// converted into ReplayGain tags. When ReplayGain is disabled all gain tags are dropped so
+/// rgvolume leaves the stream alone. Returns `None` if the tags can be passed on unchanged.
+pub fn rewrite_gain_tags(tags: &gst::TagListRef, enabled: bool) -> Option<gst::TagList> {
+    if !enabled {
+        if !tags.iter().any(|(name, _)| GAIN_TAGS.contains(&name)) {
+            return None;
+        }
+
+        let mut stripped = gst::TagList::new();
+        {
+            let stripped = stripped.get_mut().unwrap();
+            stripped.set_scope(tags.get_scope());
+            for (name, value) in tags.iter().filter(|(name, _)| !GAIN_TAGS.contains(name)) {
+                let _ = stripped.add_generic(name, &value, gst::TagMergeMode::Append);
+            }
+        }
+        return Some(stripped);
+    }
+
+    let mut track_gain = None;
+    let mut album_gain = None;
+    for idx in 0..tags.get_size::<gst::tags::ExtendedComment>() {
+        let comment = match tags
+            .get_index::<gst::tags::ExtendedComment>(idx)
+            .and_then(|comment| comment.get())
+        {
+            Some(comment) => comment,
+            None => continue,
+        };
+
+        let mut split = comment.splitn(2, '=');
+        let key = split.next().unwrap_or("").to_uppercase();
+        let value = split.next().unwrap_or("");
+        match key.as_str() {
+            "R128_TRACK_GAIN" => track_gain = parse_r128_gain(value),
+            "R128_ALBUM_GAIN" => album_gain = parse_r128_gain(value),
+            _ => (),
+        }
+    }
+
+    // Real ReplayGain tags win over converted ones
+    let track_gain = track_gain.filter(|_| tags.get::<gst::tags::TrackGain>().is_none());
+    let album_gain = album_gain.filter(|_| tags.get::<gst::tags::AlbumGain>().is_none());
+    if track_gain.is_none() && album_gain.is_none() {
+        return None;
+    }
+
+    let mut converted = tags.copy();
+    {
+        let converted = converted.get_mut().unwrap();
+        if let Some(gain) = track_gain {
+            converted.add::<gst::tags::TrackGain>(&gain, gst::TagMergeMode::Replace);
+        }
+        if let Some(gain) = album_gain {
+            converted.add::<gst::tags::AlbumGain>(&gain, gst::TagMergeMode::Replace);
+        }
+    }
+    Some(converted)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn r128_gain_conversion() {
+        assert_eq!(parse_r128_gain("0"), Some(5.0));
+        assert_eq!(parse_r128_gain("-1280"), Some(0.0));
+        assert_eq!(parse_r128_gain(" 256 "), Some(6.0));
+        assert_eq!(parse_r128_gain("-6.5"), None);
+        assert_eq!(parse_r128_gain("100000"), None);
+    }
+
+    #[test]
+    fn album_gain_selection() {
+        let mut settings = ReplayGainSettings::new();
+        assert_eq!(settings.get_mode(), ReplayGainMode::Auto);
+        assert!(settings.use_album_gain(true));
+        assert!(!settings.use_album_gain(false));
+
+        settings.set_mode(ReplayGainMode::Album);
+        assert!(settings.use_album_gain(false));
+
+        settings.set_mode(ReplayGainMode::Track);
+        assert!(!settings.use_album_gain(true));
+
+        settings.set_preamp(40.0);
+        assert_eq!(settings.get_preamp(), MAX_PREAMP_DB);
+    }
+
+    #[test]
+    fn gain_tag_rewriting() {
+        gst::init().unwrap();
+
+        let mut tags = gst::TagList::new();
+        {
+            let tags = tags.get_mut().unwrap();
+            tags.add::<gst::tags::Title>(&"Test 1: The Intro", gst::TagMergeMode::Append);
+            tags.add::<gst::tags::ExtendedComment>(
+                &"R128_TRACK_GAIN=-1280",
+                gst::TagMergeMode::Append,
+            );
+            tags.add::<gst::tags::AlbumGain>(&-3.0, gst::TagMergeMode::Append);
+            tags.add::<gst::tags::ExtendedComment>(
+                &"R128_ALBUM_GAIN=256",
+                gst::TagMergeMode::Append,
+            );
+        }
+
+        let converted = rewrite_gain_tags(&tags, true).unwrap();
+        assert_eq!(converted.get::<gst::tags::TrackGain>().unwrap().get(), Some(0.0));
+        assert_eq!(converted.get::<gst::tags::AlbumGain>().unwrap().get(), Some(-3.0));
+
+        let stripped = rewrite_gain_tags(&converted, false).unwrap();
+        assert!(stripped.get::<gst::tags::TrackGain>().is_none());
+        assert!(stripped.get::<gst::tags::AlbumGain>().is_none());
+        assert!(stripped.get::<gst::tags::Title>().is_some());
+        assert!(rewrite_gain_tags(&stripped, false).is_none());
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::Instant;
+
+use chrono::Duration;
+
+/// How long the volume takes to fade out at the end of a sleep timer
+pub const SLEEP_FADE_OUT_SECS: i64 = 30;
+
+#[derive(Clone, Debug)]
+pub enum SleepTimer {
+    /// Stop playback at a point in time, optionally fading out over the last seconds
+    Time { ends_at: Instant, fade_out: bool },
+    /// Stop once this many more songs have finished, counting the current one
+    Tracks(usize),
+}
+
+impl SleepTimer {
+    pub fn after(duration: Duration, fade_out: bool) -> Self {
+        let ends_at = Instant::now() + duration.to_std().unwrap_or_default();
+        Self::Time { ends_at, fade_out }
+    }
+
+    pub fn get_remaining_time(&self) -> Option<Duration> {
+        match self {
+            Self::Time { ends_at, .. } => Some(
+                Duration::from_std(ends_at.saturating_duration_since(Instant::now()))
+                    .unwrap_or_else(|_| Duration::zero()),
+            ),
+            Self::Tracks(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SleepSettings {
+    timer: Option<SleepTimer>,
+    stop_after_current: bool,
+}
+
+impl SleepSettings {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get_timer(&self) -> Option<&SleepTimer> {
+        self.timer.as_ref()
+    }
+
+    /// `None` cancels the timer
+    pub fn set_timer(&mut self, timer: Option<SleepTimer>) {
+        self.timer = timer;
+    }
+
+    pub fn get_stop_after_current(&self) -> bool {
+        self.stop_after_current
+    }
+
+    pub fn set_stop_after_current(&mut self, stop_after_current: bool) {
+        self.stop_after_current = stop_after_current;
+    }
+
+    /// Called when the current song is about to finish, returns true if playback should stop
+    /// after it instead of moving on to the next song.
+    pub fn song_finishing(&mut self) -> bool {
+        if self.stop_after_current {
+            self.stop_after_current = false;
+            return true;
+        }
+
+        match self.timer {
+            Some(SleepTimer::Tracks(ref mut tracks)) => {
+                *tracks = tracks.saturating_sub(1);
+                if *tracks == 0 {
+                    self.timer = None;
+                    return true;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Volume for the fade out at the end of a timed sleep timer, between 1 and 0
+    pub fn get_fade_volume(&self) -> f64 {
+        match self.timer {
+            Some(ref timer @ SleepTimer::Time { fade_out: true, .. }) => {
+                let remaining = timer.get_remaining_time().unwrap_or_else(Duration::zero);
+                let fade = Duration::seconds(SLEEP_FADE_OUT_SECS);
+                (remaining.num_milliseconds() as f64 / fade.num_milliseconds() as f64).min(1.0)
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// True once a timed sleep timer has run out
+    pub fn is_expired(&self) -> bool {
+        self.timer
+            .as_ref()
+            .and_then(|timer| timer.get_remaining_time())
+            .map_or(false, |remaining| remaining <= Duration::zero())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stop_after_tracks() {
+        let mut settings = SleepSettings::new();
+        assert!(!settings.song_finishing());
+
+        settings.set_stop_after_current(true);
+        assert!(settings.song_finishing());
+        assert!(!settings.get_stop_after_current());
+        assert!(!settings.song_finishing());
+
+        settings.set_timer(Some(SleepTimer::Tracks(2)));
+        assert!(!settings.song_finishing());
+        assert!(settings.song_finishing());
+        assert!(settings.get_timer().is_none());
+    }
+
+    #[test]
+    fn timed_fade_out() {
+        let mut settings = SleepSettings::new();
+        assert_eq!(settings.get_fade_volume(), 1.0);
+
+        settings.set_timer(Some(SleepTimer::after(Duration::minutes(10), true)));
+        assert_eq!(settings.get_fade_volume(), 1.0);
+        assert!(!settings.is_expired());
+
+        settings.set_timer(Some(SleepTimer::after(
+            Duration::seconds(SLEEP_FADE_OUT_SECS / 2),
+            true,
+        )));
+        let volume = settings.get_fade_volume();
+        assert!(
+            volume > 0.4 && volume <= 0.5,
+            "Unexpected volume {}",
+            volume
+        );
+
+        settings.set_timer(Some(SleepTimer::after(Duration::zero(), false)));
+        assert!(settings.is_expired());
+        assert_eq!(settings.get_fade_volume(), 1.0);
+    }
+}
@@ -0,0 +1,71 @@
+/// Range of the playback speed, scaletempo starts to sound rough outside of it.
+pub const MIN_RATE: f64 = 0.5;
+pub const MAX_RATE: f64 = 3.0;
+
+#[derive(Clone, Debug)]
+pub struct SpeedSettings {
+    /// The speed picked by the user
+    rate: f64,
+    /// Speed remembered for the current track or its genre, it wins over the user's speed
+    /// until the next song starts.
+    remembered_rate: Option<f64>,
+}
+
+fn clamp_rate(rate: f64) -> f64 {
+    rate.max(MIN_RATE).min(MAX_RATE)
+}
+
+impl SpeedSettings {
+    pub fn new() -> Self {
+        Self {
+            rate: 1.0,
+            remembered_rate: None,
+        }
+    }
+
+    /// The speed the current song plays at
+    pub fn get_rate(&self) -> f64 {
+        self.remembered_rate.unwrap_or(self.rate)
+    }
+
+    /// Changing the speed by hand takes over from a remembered speed
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = clamp_rate(rate);
+        self.remembered_rate = None;
+    }
+
+    pub fn is_remembered(&self) -> bool {
+        self.remembered_rate.is_some()
+    }
+
+    /// `None` goes back to the user's speed
+    pub fn set_remembered_rate(&mut self, rate: Option<f64>) {
+        self.remembered_rate = rate.map(clamp_rate);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn remembered_rate_priority() {
+        let mut settings = SpeedSettings::new();
+        assert_eq!(settings.get_rate(), 1.0);
+
+        settings.set_rate(10.0);
+        assert_eq!(settings.get_rate(), MAX_RATE);
+
+        settings.set_remembered_rate(Some(0.1));
+        assert!(settings.is_remembered());
+        assert_eq!(settings.get_rate(), MIN_RATE);
+
+        settings.set_remembered_rate(None);
+        assert_eq!(settings.get_rate(), MAX_RATE);
+
+        settings.set_remembered_rate(Some(1.5));
+        settings.set_rate(1.25);
+        assert!(!settings.is_remembered());
+        assert_eq!(settings.get_rate(), 1.25);
+    }
+}
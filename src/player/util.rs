@@ -1,9 +1,14 @@
+use std::collections::HashSet;
+use std::fs;
 use std::path::{Path, PathBuf};
 
 use url::Url;
 
+use super::playlist_file::{is_playlist_file, read_playlist};
+
 pub fn is_audio_file_guess(path: &Path) -> bool {
-    if !path.exists() {
+    // mime_guess calls playlists audio too, but they aren't songs
+    if !path.exists() || is_playlist_file(path) {
         return false;
     }
 
@@ -18,6 +23,71 @@ pub fn is_audio_file_guess(path: &Path) -> bool {
     mime.type_() == "audio"
 }
 
+/// The audio files at the path. Directories are searched recursively, in sorted order so albums
+/// play in the order of their file names.
+pub fn find_audio_files(path: &Path) -> Vec<PathBuf> {
+    find_audio_files_in(path, &mut HashSet::new())
+}
+
+/// `visited` has the directories that were searched already, so a symlink that points back up
+/// doesn't keep this going forever.
+fn find_audio_files_in(path: &Path, visited: &mut HashSet<PathBuf>) -> Vec<PathBuf> {
+    if !path.is_dir() {
+        return if is_audio_file_guess(path) {
+            vec![path.to_path_buf()]
+        } else {
+            Vec::new()
+        };
+    }
+
+    match fs::canonicalize(path) {
+        Ok(real_path) if visited.insert(real_path) => (),
+        Ok(_) => return Vec::new(),
+        Err(e) => {
+            log::warn!("Could not read {}: {}", path.display(), e);
+            return Vec::new();
+        }
+    }
+
+    let mut entries = match path.read_dir() {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            log::warn!("Could not read {}: {}", path.display(), e);
+            return Vec::new();
+        }
+    };
+    entries.sort();
+
+    entries
+        .iter()
+        .flat_map(|entry| find_audio_files_in(entry, visited))
+        .collect()
+}
+
+/// The songs to play for the paths given by the user. Playlists are replaced by their songs, and
+/// directories by the audio files in them.
+pub fn expand_paths(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .flat_map(|path| {
+            if is_playlist_file(path) {
+                read_playlist(path)
+                    .unwrap_or_else(|e| {
+                        log::warn!("Could not read {}: {}", path.display(), e);
+                        Vec::new()
+                    })
+                    .iter()
+                    .flat_map(|entry| find_audio_files(entry))
+                    .collect()
+            } else {
+                find_audio_files(path)
+            }
+        })
+        .collect()
+}
+
 pub fn create_gst_uri(path: &Path) -> Option<String> {
     // The resulting URI must be an aboslute path, so canonicalize before converting to a URI
     let canonical_path: PathBuf = path.canonicalize().ok()?;
@@ -56,6 +126,57 @@ mod test {
         assert_eq!(is_audio_file_guess(&webm_path), false);
     }
 
+    #[test]
+    fn test_find_audio_files() {
+        let resources = TOP_DIR.join("resources");
+        let files = find_audio_files(&resources);
+        assert!(files.contains(&resources.join("test.mp3")));
+        assert!(!files.contains(&resources.join("test.webm")));
+        assert!(files.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_playlist_is_not_audio() {
+        let dir = std::env::temp_dir().join("musicom-find-playlist-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let song = dir.join("song.mp3");
+        std::fs::copy(TOP_DIR.join("resources/test.mp3"), &song).unwrap();
+        let playlist = dir.join("album.m3u");
+        std::fs::write(&playlist, "song.mp3\n").unwrap();
+
+        assert_eq!(is_audio_file_guess(&playlist), false);
+        assert_eq!(find_audio_files(&dir), vec![song]);
+    }
+
+    #[test]
+    fn test_symlink_loop() {
+        let dir = std::env::temp_dir().join(format!("musicom-find-loop-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let song = dir.join("song.mp3");
+        std::fs::copy(TOP_DIR.join("resources/test.mp3"), &song).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("loop")).unwrap();
+
+        assert_eq!(find_audio_files(&dir), vec![song]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_playlist() {
+        let playlist = std::env::temp_dir().join("musicom-expand-test.m3u");
+        let songs = [
+            TOP_DIR.join("resources/test.ogg"),
+            TOP_DIR.join("resources/test.webm"),
+        ];
+        let contents = songs
+            .iter()
+            .map(|song| song.to_str().unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        std::fs::write(&playlist, contents).unwrap();
+
+        assert_eq!(expand_paths(&[playlist]), vec![songs[0].clone()]);
+    }
+
     #[test]
     fn test_nonexistent_discovery() {
         let nonexistent_path = TOP_DIR.join("resources/BLARG_I_DONT_EXIST.mp3");
@@ -0,0 +1,334 @@
+//! Submitting the scrobble log to ListenBrainz, or any other server with the same API.
+//!
+//! The player only adds listens to the local log, a background thread sends them off in batches
+//! and backs off while the server can't be reached. Listens stay in the log after they have been
+//! submitted, so the whole history can still be exported.
+
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::library::db::get_library_db;
+use crate::library::scrobble::Scrobble;
+use crate::library::settings;
+
+pub const DEFAULT_API_URL: &str = "https://api.listenbrainz.org";
+
+const API_URL_SETTING: &str = "scrobble_api_url";
+const TOKEN_SETTING: &str = "scrobble_token";
+
+/// How many listens go into one request
+const BATCH_SIZE: usize = 50;
+/// How often to look for new listens while everything is submitted
+const IDLE_INTERVAL_SECS: u64 = 60;
+/// The wait after a failed submission doubles every time, up to the maximum
+const MIN_BACKOFF_SECS: u64 = 30;
+const MAX_BACKOFF_SECS: u64 = 60 * 60;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScrobblerConfig {
+    /// The root of the API, `/1/submit-listens` is added to it
+    pub api_url: String,
+    pub token: String,
+}
+
+impl ScrobblerConfig {
+    /// `None` until a token is set, there's nothing to submit to without one.
+    pub fn load() -> Option<Self> {
+        let token = settings::get_setting(TOKEN_SETTING).filter(|token| !token.is_empty())?;
+        let api_url =
+            settings::get_setting(API_URL_SETTING).unwrap_or_else(|| DEFAULT_API_URL.to_string());
+        Some(ScrobblerConfig { api_url, token })
+    }
+
+    pub fn save(&self) {
+        settings::set_setting(API_URL_SETTING, &self.api_url);
+        settings::set_setting(TOKEN_SETTING, &self.token);
+    }
+
+    fn submit_url(&self) -> String {
+        format!("{}/1/submit-listens", self.api_url.trim_end_matches('/'))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SubmitError {
+    /// The server refused the listens themselves, sending them again won't help
+    Rejected(String),
+    /// The server couldn't be reached or had a problem, try again later
+    Failed(String),
+    /// The scrobble log couldn't be read or updated, also tried again later
+    Database(String),
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Rejected(reason) => write!(f, "listens were rejected: {}", reason),
+            Self::Failed(reason) => write!(f, "could not submit listens: {}", reason),
+            Self::Database(reason) => write!(f, "could not use the scrobble log: {}", reason),
+        }
+    }
+}
+
+fn listen_json(scrobble: &Scrobble) -> Value {
+    let mut additional_info = json!({
+        "media_player": "musicom",
+        "submission_client": "musicom",
+        "submission_client_version": env!("CARGO_PKG_VERSION"),
+    });
+    if let Some(track_num) = scrobble.track_num {
+        additional_info["tracknumber"] = json!(track_num);
+    }
+    if let Some(duration) = scrobble.duration {
+        additional_info["duration_ms"] = json!(duration.num_milliseconds());
+    }
+
+    let mut track_metadata = json!({
+        "artist_name": scrobble.artist,
+        "track_name": scrobble.title,
+        "additional_info": additional_info,
+    });
+    if let Some(album) = scrobble.album.as_ref() {
+        track_metadata["release_name"] = json!(album);
+    }
+
+    json!({
+        "listened_at": scrobble.listened_at.timestamp(),
+        "track_metadata": track_metadata,
+    })
+}
+
+pub fn build_payload(scrobbles: &[Scrobble]) -> Value {
+    json!({
+        "listen_type": if scrobbles.len() == 1 { "single" } else { "import" },
+        "payload": scrobbles.iter().map(listen_json).collect::<Vec<_>>(),
+    })
+}
+
+pub fn submit_batch(
+    agent: &ureq::Agent,
+    config: &ScrobblerConfig,
+    scrobbles: &[Scrobble],
+) -> Result<(), SubmitError> {
+    let response = agent
+        .post(&config.submit_url())
+        .set("Authorization", &format!("Token {}", config.token))
+        .send_json(build_payload(scrobbles));
+
+    match response {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(400, response)) => Err(SubmitError::Rejected(
+            response.into_string().unwrap_or_default(),
+        )),
+        Err(ureq::Error::Status(code, response)) => Err(SubmitError::Failed(format!(
+            "{} {}",
+            code,
+            response.into_string().unwrap_or_default()
+        ))),
+        Err(e) => Err(SubmitError::Failed(e.to_string())),
+    }
+}
+
+/// Submit everything in the log that hasn't been submitted yet, returns how many listens were
+/// accepted. A batch that gets rejected is retried one listen at a time, and listens that are
+/// rejected on their own are skipped so they don't hold up the rest of the log.
+pub fn submit_pending(config: &ScrobblerConfig) -> Result<usize, SubmitError> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .build();
+    let conn = get_library_db()
+        .ok_or_else(|| SubmitError::Database("the library can't be opened".to_string()))?;
+    let mark_submitted = |scrobbles: &[Scrobble]| {
+        Scrobble::mark_submitted_with_conn(&conn, scrobbles)
+            .map_err(|e| SubmitError::Database(e.to_string()))
+    };
+    let mut submitted = 0;
+
+    loop {
+        let batch = Scrobble::pending_with_conn(&conn, BATCH_SIZE)
+            .map_err(|e| SubmitError::Database(e.to_string()))?;
+        if batch.is_empty() {
+            return Ok(submitted);
+        }
+
+        match submit_batch(&agent, config, &batch) {
+            Ok(()) => submitted += batch.len(),
+            Err(SubmitError::Rejected(_)) if batch.len() > 1 => {
+                for scrobble in batch.iter() {
+                    match submit_batch(&agent, config, std::slice::from_ref(scrobble)) {
+                        Ok(()) => submitted += 1,
+                        Err(SubmitError::Rejected(reason)) => log::warn!(
+                            "Skipping listen of {} - {}: {}",
+                            scrobble.artist,
+                            scrobble.title,
+                            reason
+                        ),
+                        Err(e) => return Err(e),
+                    }
+                    mark_submitted(std::slice::from_ref(scrobble))?;
+                }
+                continue;
+            }
+            Err(SubmitError::Rejected(reason)) => log::warn!(
+                "Skipping listen of {} - {}: {}",
+                batch[0].artist,
+                batch[0].title,
+                reason
+            ),
+            Err(e) => return Err(e),
+        }
+        mark_submitted(&batch)?;
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    let secs = MIN_BACKOFF_SECS.saturating_mul(1 << failures.saturating_sub(1).min(16));
+    Duration::from_secs(secs.min(MAX_BACKOFF_SECS))
+}
+
+/// Keep submitting new listens in the background for as long as musicom runs.
+pub fn spawn_submitter() {
+    thread::spawn(|| {
+        let mut failures = 0;
+        loop {
+            let wait = match ScrobblerConfig::load().map(|config| submit_pending(&config)) {
+                Some(Err(e)) => {
+                    failures += 1;
+                    log::warn!("{}, trying again in {:?}", e, backoff(failures));
+                    backoff(failures)
+                }
+                _ => {
+                    failures = 0;
+                    Duration::from_secs(IDLE_INTERVAL_SECS)
+                }
+            };
+            thread::sleep(wait);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    /// A server that answers the requests with the given status codes, in order, and sends the
+    /// headers and body of each request back.
+    fn mock_server(statuses: Vec<u16>) -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut headers = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    headers.push_str(&line);
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                sender
+                    .send((headers, String::from_utf8(body).unwrap()))
+                    .unwrap();
+
+                let reply = "{\"status\": \"ok\"}";
+                write!(
+                    &stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    reply.len(),
+                    reply
+                )
+                .unwrap();
+            }
+        });
+
+        (url, receiver)
+    }
+
+    fn scrobble(title: &str) -> Scrobble {
+        Scrobble {
+            id: None,
+            artist: "George".to_string(),
+            title: title.to_string(),
+            album: Some("Test".to_string()),
+            track_num: Some(1),
+            duration: Some(chrono::Duration::seconds(200)),
+            listened_at: Utc.timestamp(1_600_000_000, 0),
+            submitted: false,
+        }
+    }
+
+    #[test]
+    fn payload() {
+        let payload = build_payload(&[scrobble("Test 1: The Intro")]);
+        assert_eq!(payload["listen_type"], "single");
+        let listen = &payload["payload"][0];
+        assert_eq!(listen["listened_at"], 1_600_000_000);
+        assert_eq!(listen["track_metadata"]["artist_name"], "George");
+        assert_eq!(listen["track_metadata"]["release_name"], "Test");
+        assert_eq!(
+            listen["track_metadata"]["additional_info"]["duration_ms"],
+            200_000
+        );
+
+        let payload = build_payload(&[scrobble("One"), scrobble("Two")]);
+        assert_eq!(payload["listen_type"], "import");
+        assert_eq!(payload["payload"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn submit_to_mock_server() {
+        let (api_url, requests) = mock_server(vec![200, 503, 400]);
+        let config = ScrobblerConfig {
+            api_url: api_url + "/",
+            token: "secret".to_string(),
+        };
+        let agent = ureq::AgentBuilder::new().build();
+        let batch = [scrobble("Test 1: The Intro")];
+
+        assert_eq!(submit_batch(&agent, &config, &batch), Ok(()));
+        let (headers, body) = requests.recv().unwrap();
+        assert!(headers.starts_with("POST /1/submit-listens "));
+        assert!(headers.contains("Token secret"));
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body, build_payload(&batch));
+
+        match submit_batch(&agent, &config, &batch) {
+            Err(SubmitError::Failed(reason)) => assert!(reason.starts_with("503")),
+            result => panic!("Expected the submission to fail, got {:?}", result),
+        }
+        match submit_batch(&agent, &config, &batch) {
+            Err(SubmitError::Rejected(_)) => (),
+            result => panic!("Expected the listens to be rejected, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        assert_eq!(backoff(1), Duration::from_secs(30));
+        assert_eq!(backoff(2), Duration::from_secs(60));
+        assert_eq!(backoff(3), Duration::from_secs(120));
+        assert_eq!(backoff(100), Duration::from_secs(MAX_BACKOFF_SECS));
+    }
+}
@@ -1,18 +1,38 @@
+mod bookmark_view;
+mod command_line;
+mod equalizer_view;
 mod file_browser;
+mod help_view;
 mod library;
+mod loudness_dialog;
 mod main_view;
+mod output_view;
 mod player_view;
 mod queue_view;
+mod rating_view;
+mod remote_view;
+mod sleep_timer_view;
+mod stats_view;
 
 use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use cursive::view::{Nameable, Resizable, View};
-use cursive::views::{BoxedView, HideableView, LinearLayout, NamedView, Panel};
+use chrono::Duration;
+
+use cursive::event::{Event, EventResult, EventTrigger};
+use cursive::view::{Nameable, Resizable, SizeConstraint, View};
+use cursive::views::{BoxedView, HideableView, LinearLayout, NamedView, Panel, ResizedView};
 use cursive::Cursive;
 
+use crate::config;
+use crate::control::{self, Command, SeekTarget};
+use crate::keymap::{self, Action, KeyPress};
+use crate::library::playback_speed::SpeedKind;
 use crate::player::PlayerHdl;
 use main_view::MainView;
 
+pub use remote_view::run_attached;
+
 // QueueHiderView uses a BoxedView to hide the implementation of the QueueView
 // in the UI, specifically so we don't have to track the full set of type
 // parameters used by the UI to create the QueueView UI. It would be just a
@@ -20,6 +40,13 @@ use main_view::MainView;
 // care about at this level.
 type QueueHiderView = HideableView<BoxedView>;
 
+/// How far the seek actions jump
+pub const SEEK_STEP_SECS: i64 = 10;
+
+/// Set while run_action sends a navigation key to the views, so a key that no view wants isn't
+/// taken for an action again
+static REDISPATCHING: AtomicBool = AtomicBool::new(false);
+
 pub struct UI {
     player: PlayerHdl,
 }
@@ -36,22 +63,47 @@ impl UI {
 
         let linear_layout = self.build_views(&mut siv);
         siv.add_fullscreen_layer(linear_layout);
-        self.build_menus(&mut siv);
+
+        let cb_sink = siv.cb_sink().clone();
+        config::register_reload_cb(Box::new(move || {
+            cb_sink.send(Box::new(Self::apply_config)).ok();
+        }));
 
         cursive::logger::init();
-        let player_clone = self.player.clone();
-        siv.add_global_callback('p', move |_| {
-            player_clone.toggle_play_pause();
+        let player = self.player.clone();
+        siv.set_on_event_inner(EventTrigger::any(), move |event| {
+            if REDISPATCHING.load(Ordering::Relaxed) {
+                return None;
+            }
+            match keymap::press(event) {
+                KeyPress::Action(action) => {
+                    let player = player.clone();
+                    Some(EventResult::with_cb(move |siv| {
+                        Self::run_action(siv, &player, action)
+                    }))
+                }
+                KeyPress::Pending => Some(EventResult::Consumed(None)),
+                KeyPress::Unbound => None,
+            }
         });
         siv.run();
         Ok(())
     }
 
+    /// Settings that only take effect when a view is built are picked up by the next one, the
+    /// others are applied here when the config is reloaded.
+    fn apply_config(siv: &mut Cursive) {
+        let queue_width = config::get().ui.queue_width;
+        siv.call_on_name("queue_panel", |panel: &mut ResizedView<BoxedView>| {
+            panel.set_width(SizeConstraint::AtLeast(queue_width));
+        });
+    }
+
     fn get_queue_sidebar_view(siv: &mut Cursive) -> NamedView<QueueHiderView> {
         let queue_view = self::queue_view::QueueView::new(siv);
-        let panel = Panel::new(queue_view)
-            .title("Queue")
-            .min_width(50)
+        let panel = BoxedView::boxed(Panel::new(queue_view).title("Queue"))
+            .min_width(config::get().ui.queue_width)
+            .with_name("queue_panel")
             .full_height();
         HideableView::new(BoxedView::boxed(panel)).with_name("queue_hider_view")
     }
@@ -67,7 +119,8 @@ impl UI {
         let player_bar = player_view::PlayerView::new(siv);
         let top_level_layout = LinearLayout::vertical()
             .child(browser_layout.full_height())
-            .child(player_bar.fixed_height(1).full_width());
+            .child(player_bar.fixed_height(1).full_width())
+            .child(command_line::CommandLine::new());
         top_level_layout.with_name("top_level_layout")
     }
 
@@ -78,12 +131,98 @@ impl UI {
         }).unwrap();
     }
 
-    fn build_menus(&self, siv: &mut Cursive) {
-        siv.add_global_callback('v', |siv| {
-            main_view::select_new_view_from_user(siv);
-        });
-        siv.add_global_callback('q', |siv| {
-            Self::toggle_queue_sidebar(siv);
-        });
+    /// Actions that no view handled itself, and the ones typed in the command line
+    pub fn run_action(siv: &mut Cursive, player: &PlayerHdl, action: Action) {
+        match action {
+            Action::TogglePlay => player.toggle_play_pause(),
+            Action::PlayNext => player.play_next(),
+            Action::PlayPrevious => player.play_previous(),
+            Action::SeekBackward => {
+                let offset = Duration::seconds(-SEEK_STEP_SECS);
+                control::execute(player, Command::Seek(SeekTarget::Relative(offset))).ok();
+            }
+            Action::SeekForward => {
+                let offset = Duration::seconds(SEEK_STEP_SECS);
+                control::execute(player, Command::Seek(SeekTarget::Relative(offset))).ok();
+            }
+            Action::ShortenCrossfade => {
+                let duration = player.get_crossfade().get_duration();
+                player.set_crossfade_duration(duration - Duration::seconds(1));
+            }
+            Action::LengthenCrossfade => {
+                let duration = player.get_crossfade().get_duration();
+                player.set_crossfade_duration(duration + Duration::seconds(1));
+            }
+            Action::ToggleCrossfadeWithinAlbum => {
+                let fade_within_album = player.get_crossfade().get_fade_within_album();
+                player.set_crossfade_within_album(!fade_within_album);
+            }
+            Action::CycleReplayGainMode => {
+                let mode = player.get_replaygain().get_mode();
+                player.set_replaygain_mode(mode.next());
+            }
+            Action::TogglePreventClipping => {
+                let prevent_clipping = player.get_replaygain().get_prevent_clipping();
+                player.set_replaygain_prevent_clipping(!prevent_clipping);
+            }
+            Action::LowerPreamp => {
+                let preamp = player.get_replaygain().get_preamp();
+                player.set_replaygain_preamp(preamp - 1.0);
+            }
+            Action::RaisePreamp => {
+                let preamp = player.get_replaygain().get_preamp();
+                player.set_replaygain_preamp(preamp + 1.0);
+            }
+            Action::SlowDown => {
+                let rate = player.get_speed().get_rate();
+                player.set_speed(rate - 0.1);
+            }
+            Action::SpeedUp => {
+                let rate = player.get_speed().get_rate();
+                player.set_speed(rate + 0.1);
+            }
+            Action::ResetSpeed => player.set_speed(1.0),
+            Action::RememberTrackSpeed => player.remember_speed(SpeedKind::Track),
+            Action::RememberGenreSpeed => player.remember_speed(SpeedKind::Genre),
+            Action::ToggleStopAfterCurrent => {
+                let stop_after_current = player.get_sleep().get_stop_after_current();
+                player.set_stop_after_current(!stop_after_current);
+            }
+            Action::SetLoopPoint => player.set_ab_loop_point(),
+            Action::ClearLoop => player.clear_ab_loop(),
+            Action::ShowViewChooser => main_view::select_new_view_from_user(siv),
+            Action::ToggleQueue => Self::toggle_queue_sidebar(siv),
+            Action::AddBookmark => bookmark_view::show_add_bookmark(siv),
+            Action::ShowBookmarks => bookmark_view::show_bookmark_list(siv),
+            Action::ShowRatingChooser => rating_view::show_rating_chooser(siv),
+            Action::ShowSleepTimer => sleep_timer_view::show_sleep_timer_chooser(siv),
+            Action::ShowOutputs => output_view::show_output_chooser(siv),
+            Action::ShowEqualizer => {
+                if siv
+                    .find_name::<equalizer_view::EqualizerView>("equalizer_view")
+                    .is_none()
+                {
+                    siv.add_layer(equalizer_view::EqualizerView::new());
+                }
+            }
+            // Only the views that have an action menu handle it
+            Action::OpenActions => (),
+            Action::Help => help_view::show_help(siv, None),
+            Action::CommandLine => command_line::open(siv),
+            Action::MoveUp
+            | Action::MoveDown
+            | Action::PageUp
+            | Action::PageDown
+            | Action::GoToTop
+            | Action::GoToBottom => {
+                if let Some(key) = action.navigation_key() {
+                    REDISPATCHING.store(true, Ordering::Relaxed);
+                    siv.on_event(Event::Key(key));
+                    REDISPATCHING.store(false, Ordering::Relaxed);
+                }
+            }
+            // Only their views handle the others
+            _ => (),
+        }
     }
 }
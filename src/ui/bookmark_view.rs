@@ -0,0 +1,108 @@
+use chrono::Duration;
+
+use cursive::event::{EventResult, Key};
+use cursive::traits::{Nameable, Resizable};
+use cursive::views::{Dialog, EditView, OnEventView, Panel, SelectView};
+use cursive::Cursive;
+
+use crate::library::{Bookmark, Track};
+use crate::player::{PlayerHdl, QueueItem};
+
+fn format_position(position: Duration) -> String {
+    format!(
+        "{:02}:{:02}",
+        position.num_minutes(),
+        position.num_seconds() % 60
+    )
+}
+
+/// Bookmarks are stored per track, so songs that aren't in the library can't have any.
+fn get_current_track_id(player: &PlayerHdl) -> Option<i32> {
+    match player.now_playing().get_item()? {
+        QueueItem::Track(track) => track.id,
+        QueueItem::Path(path) => Track::from_path(path)?.id,
+        _ => None,
+    }
+}
+
+pub fn show_add_bookmark(siv: &mut Cursive) {
+    let player = PlayerHdl::new();
+    let track_id = match get_current_track_id(&player) {
+        Some(track_id) => track_id,
+        None => {
+            siv.add_layer(Dialog::info("Only songs in the library can have bookmarks"));
+            return;
+        }
+    };
+    let (position, _) = player.now_playing().get_song_progress();
+
+    let save = move |siv: &mut Cursive, name: &str| {
+        let name = if name.trim().is_empty() {
+            format_position(position)
+        } else {
+            name.trim().to_string()
+        };
+        Bookmark::new(track_id, &name, position).save();
+        siv.pop_layer();
+    };
+
+    siv.add_layer(
+        Dialog::around(
+            EditView::new()
+                .on_submit(save)
+                .with_name("bookmark_name")
+                .fixed_width(30),
+        )
+        .title(format!("Bookmark at {}", format_position(position)))
+        .button("Save", move |siv| {
+            let name = siv
+                .call_on_name("bookmark_name", |view: &mut EditView| view.get_content())
+                .unwrap();
+            save(siv, &name);
+        })
+        .dismiss_button("Cancel"),
+    );
+}
+
+pub fn show_bookmark_list(siv: &mut Cursive) {
+    let player = PlayerHdl::new();
+    let bookmarks = get_current_track_id(&player)
+        .map(Bookmark::for_track)
+        .unwrap_or_default();
+    if bookmarks.is_empty() {
+        siv.add_layer(Dialog::info(
+            "No bookmarks for this song, press <k> to add one",
+        ));
+        return;
+    }
+
+    let mut select_view = SelectView::new();
+    for bookmark in bookmarks {
+        let label = format!("{}  {}", format_position(bookmark.position), bookmark.name);
+        select_view.add_item(label, bookmark);
+    }
+    select_view.set_on_submit(|siv, bookmark: &Bookmark| {
+        PlayerHdl::new().seek_to(bookmark.position);
+        siv.pop_layer();
+    });
+
+    let wrapped = OnEventView::new(select_view.with_name("bookmark_list"))
+        .on_pre_event(Key::Esc, |siv| {
+            siv.pop_layer();
+        })
+        .on_pre_event_inner('d', |view, _| {
+            let mut select_view = view.get_mut();
+            if let Some(id) = select_view.selected_id() {
+                if let Some((_, bookmark)) = select_view.get_item(id) {
+                    bookmark.delete();
+                }
+                select_view.remove_item(id);
+            }
+            Some(EventResult::Consumed(None))
+        });
+    siv.add_layer(
+        Panel::new(wrapped)
+            .title("Bookmarks, <d> to delete")
+            .min_width(40),
+    );
+}
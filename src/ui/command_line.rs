@@ -0,0 +1,530 @@
+//! The `:` command line at the bottom of the UI. Besides the commands here it takes the name of
+//! every action of the keymap, so everything a key does can also be typed. Seeking, the volume and
+//! the sleep timer run as commands of the control socket, so they work the same in both. The
+//! results and the errors end up in the status line of the player bar, which clears itself.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use chrono::Duration;
+use cursive::event::{Event, EventResult, Key};
+use cursive::traits::{Finder, Nameable, Resizable};
+use cursive::view::{View, ViewWrapper};
+use cursive::views::{EditView, HideableView, LinearLayout, TextView};
+use cursive::{wrap_impl, Cursive};
+
+use crate::control::{self, SeekTarget, VolumeTarget};
+use crate::keymap::Action;
+use crate::library::{self, Track, TrackField, TrackedPath};
+use crate::player::{get_playlist_dir, write_playlist, PlayerHdl};
+use crate::ui::library::LibrarySongView;
+use crate::ui::{main_view, UI};
+use crate::util::expand_home;
+
+/// The commands besides the actions, for completion
+const COMMANDS: &[&str] = &[
+    "add",
+    "scan",
+    "view",
+    "seek",
+    "vol",
+    "save-queue",
+    "search",
+    "sleep",
+];
+
+const SEARCH_FIELDS: &[&str] = &["title:", "artist:", "album:", "track:", "path:"];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Command {
+    Action(Action),
+    /// Track a directory and look for the songs in it
+    Add(PathBuf),
+    Scan {
+        full: bool,
+    },
+    View(String),
+    /// A command the control socket takes too
+    Control(control::Command),
+    SaveQueue(String),
+    Search(Vec<(TrackField, String)>),
+}
+
+/// A sign at the start of the text, `0` if it has none
+fn split_sign(text: &str) -> (i32, &str) {
+    match text.chars().next() {
+        Some('+') => (1, &text[1..]),
+        Some('-') => (-1, &text[1..]),
+        _ => (0, text),
+    }
+}
+
+/// A time like `90`, `1:30` or `1:02:03`
+fn parse_time(text: &str) -> Option<Duration> {
+    let parts = text
+        .split(':')
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if parts.len() > 3 || parts[1..].iter().any(|part| *part >= 60) {
+        return None;
+    }
+    let seconds = parts
+        .iter()
+        .fold(0, |seconds, part| seconds * 60 + i64::from(*part));
+    Some(Duration::seconds(seconds))
+}
+
+/// Words like `artist:George` search a field, the others search every field.
+fn parse_search(query: &str) -> Result<Vec<(TrackField, String)>, String> {
+    query
+        .split_whitespace()
+        .map(|word| match word.find(':') {
+            Some(split) => {
+                let field = TrackField::from_name(&word[..split])
+                    .ok_or_else(|| format!("{} is not a field", &word[..split]))?;
+                Ok((field, word[split + 1..].to_string()))
+            }
+            None => Ok((TrackField::Any, word.to_string())),
+        })
+        .collect()
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let (name, args) = match line.find(char::is_whitespace) {
+        Some(split) => (&line[..split], line[split..].trim()),
+        None => (line, ""),
+    };
+
+    let command = match (name, args) {
+        ("add", "") => return Err("add needs a directory".to_string()),
+        ("add", path) => Command::Add(expand_home(Path::new(path))),
+        ("scan", "") => Command::Scan { full: false },
+        ("scan", "full") => Command::Scan { full: true },
+        ("view", "") => return Err("view needs the name of a view".to_string()),
+        ("view", view) => Command::View(view.to_string()),
+        ("seek", time) => {
+            let (sign, time) = split_sign(time);
+            let position =
+                parse_time(time).ok_or_else(|| format!("{} is not a time like 1:30", args))?;
+            Command::Control(control::Command::Seek(match sign {
+                0 => SeekTarget::Absolute(position),
+                sign => SeekTarget::Relative(position * sign),
+            }))
+        }
+        ("vol", volume) => {
+            let (sign, volume) = split_sign(volume);
+            let percent = volume
+                .parse::<u32>()
+                .ok()
+                .filter(|percent| *percent <= 100)
+                .ok_or_else(|| format!("{} is not a volume from 0 to 100", args))?;
+            let volume = f64::from(percent) / 100.0;
+            Command::Control(control::Command::Volume(match sign {
+                0 => VolumeTarget::Absolute(volume),
+                sign => VolumeTarget::Relative(volume * f64::from(sign)),
+            }))
+        }
+        ("save-queue", "") => return Err("save-queue needs a name".to_string()),
+        ("save-queue", name) if name.contains('/') => {
+            return Err(format!("{} can't be the name of a file", name))
+        }
+        ("save-queue", name) => Command::SaveQueue(name.to_string()),
+        ("search", "") => return Err("search needs something to look for".to_string()),
+        ("search", query) => Command::Search(parse_search(query)?),
+        ("sleep", "off") => Command::Control(control::Command::Sleep(None)),
+        ("sleep", duration) => Command::Control(control::Command::Sleep(Some(
+            control::parse_duration(duration)
+                .ok_or_else(|| format!("{} is not a duration like 30m", duration))?,
+        ))),
+        (name, args) => match Action::from_name(name) {
+            Some(action) if action.view().is_some() => {
+                return Err(format!("{} only works in its view", name))
+            }
+            Some(action) if args.is_empty() => Command::Action(action),
+            Some(_) => return Err(format!("{} doesn't take arguments", name)),
+            None => return Err(format!("unknown command {}", name)),
+        },
+    };
+    Ok(command)
+}
+
+/// The directories that start with the path, with a `/` so Tab goes on into them
+fn complete_path(word: &str) -> Vec<String> {
+    if word == "~" {
+        return vec!["~/".to_string()];
+    }
+    let (dir, prefix) = match word.rfind('/') {
+        Some(split) => (&word[..=split], &word[split + 1..]),
+        None => ("", word),
+    };
+    let list_dir = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_home(Path::new(dir))
+    };
+
+    let mut names = fs::read_dir(list_dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| name.starts_with(prefix))
+                .filter(|name| prefix.starts_with('.') || !name.starts_with('.'))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| format!("{}{}/", dir, name))
+        .collect()
+}
+
+/// The lines Tab can turn the line into, by completing its last word
+fn completions(line: &str, view_names: &[String]) -> Vec<String> {
+    let (head, word) = match line.rfind(' ') {
+        Some(split) => line.split_at(split + 1),
+        None => ("", line),
+    };
+    let candidates: Vec<String> = match head.split_whitespace().next() {
+        None => COMMANDS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(
+                Action::all()
+                    .filter(|action| action.view().is_none())
+                    .map(|action| action.name().to_string()),
+            )
+            .collect(),
+        Some("add") => complete_path(word),
+        Some("view") => view_names.to_vec(),
+        Some("scan") => vec!["full".to_string()],
+        Some("sleep") => vec!["off".to_string()],
+        Some("search") if !word.contains(':') => SEARCH_FIELDS
+            .iter()
+            .map(|field| field.to_string())
+            .collect(),
+        Some(_) => Vec::new(),
+    };
+
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .map(|candidate| format!("{}{}", head, candidate))
+        .collect()
+}
+
+fn scan_in_background(player: PlayerHdl, full: bool) {
+    thread::spawn(move || {
+        let summary = library::scan_library(full);
+        player.set_status_message(format!(
+            "Scan done: added {} songs, updated {}, removed {}",
+            summary.added, summary.updated, summary.removed
+        ));
+    });
+}
+
+/// Run the command, the message for the status line if it has one
+fn run_command(
+    siv: &mut Cursive,
+    player: &PlayerHdl,
+    command: Command,
+) -> Result<Option<String>, String> {
+    match command {
+        Command::Action(action) => UI::run_action(siv, player, action),
+        Command::Add(path) => {
+            let path = fs::canonicalize(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            if !path.is_dir() {
+                return Err(format!("{} is not a directory", path.display()));
+            }
+            if !TrackedPath::iter().any(|tracked_path| tracked_path.path == path) {
+                TrackedPath {
+                    id: None,
+                    path: path.clone(),
+                }
+                .save();
+            }
+            scan_in_background(player.clone(), false);
+            return Ok(Some(format!("Tracking {}, scanning", path.display())));
+        }
+        Command::Scan { full } => {
+            scan_in_background(player.clone(), full);
+            return Ok(Some("Scanning the library".to_string()));
+        }
+        Command::View(name) => main_view::show_view(siv, &name)?,
+        Command::Control(command) => {
+            control::execute(player, command)?;
+        }
+        Command::SaveQueue(name) => {
+            let songs = player
+                .queue()
+                .get_queue_contents()
+                .iter()
+                .filter_map(|item| item.get_path().map(Path::to_path_buf))
+                .collect::<Vec<_>>();
+            let path = get_playlist_dir().join(format!("{}.m3u", name));
+            write_playlist(&path, &songs).map_err(|e| format!("{}: {}", path.display(), e))?;
+            return Ok(Some(format!(
+                "Saved {} songs to {}",
+                songs.len(),
+                path.display()
+            )));
+        }
+        Command::Search(filters) => {
+            let tracks = Track::search(&filters, false);
+            if tracks.is_empty() {
+                return Err("no songs found".to_string());
+            }
+            let mut song_view = LibrarySongView::new();
+            song_view.call_on_name("library_song_view", |view: &mut LibrarySongView| {
+                view.show_songs_from_iter(&tracks);
+            });
+            main_view::replace_view(siv, song_view);
+            return Ok(Some(format!("Found {} songs", tracks.len())));
+        }
+    }
+    Ok(None)
+}
+
+fn run_line(siv: &mut Cursive, line: &str) {
+    if line.is_empty() {
+        return;
+    }
+    let player = PlayerHdl::new();
+    match parse_command(line).and_then(|command| run_command(siv, &player, command)) {
+        Ok(Some(message)) => player.set_status_message(message),
+        Ok(None) => (),
+        Err(e) => player.set_status_message(format!(":{}: {}", line, e)),
+    }
+}
+
+pub struct CommandLine {
+    edit_view: EditView,
+    history: Vec<String>,
+    /// The line of the history that is shown, `history.len()` for the one being typed
+    history_idx: usize,
+    /// What was typed before going through the history
+    typed: String,
+    /// The lines Tab goes through, and the one that is shown
+    completions: Option<(Vec<String>, usize)>,
+}
+
+impl ViewWrapper for CommandLine {
+    wrap_impl!(self.edit_view: EditView);
+
+    fn wrap_on_event(&mut self, e: Event) -> EventResult {
+        match e {
+            Event::Key(Key::Esc) => {
+                self.reset();
+                EventResult::with_cb(close)
+            }
+            Event::Key(Key::Backspace) if self.edit_view.get_content().is_empty() => {
+                self.reset();
+                EventResult::with_cb(close)
+            }
+            Event::Key(Key::Enter) => {
+                let line = self.edit_view.get_content().trim().to_string();
+                if !line.is_empty() && self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                }
+                self.reset();
+                EventResult::with_cb(move |siv| {
+                    close(siv);
+                    run_line(siv, &line);
+                })
+            }
+            Event::Key(Key::Tab) => {
+                self.complete();
+                EventResult::Consumed(None)
+            }
+            Event::Key(Key::Up) => {
+                self.show_history(self.history_idx.saturating_sub(1));
+                EventResult::Consumed(None)
+            }
+            Event::Key(Key::Down) => {
+                self.show_history((self.history_idx + 1).min(self.history.len()));
+                EventResult::Consumed(None)
+            }
+            _ => {
+                self.completions = None;
+                // The keys of the keymap don't work while typing
+                match self.edit_view.on_event(e) {
+                    EventResult::Ignored => EventResult::Consumed(None),
+                    result => result,
+                }
+            }
+        }
+    }
+}
+
+impl CommandLine {
+    pub fn new() -> impl View {
+        let command_line = Self {
+            edit_view: EditView::new(),
+            history: Vec::new(),
+            history_idx: 0,
+            typed: String::new(),
+            completions: None,
+        };
+        let layout = LinearLayout::horizontal()
+            .child(TextView::new(":"))
+            .child(command_line.with_name("command_line").full_width());
+        HideableView::new(layout)
+            .hidden()
+            .with_name("command_line_hider")
+    }
+
+    fn reset(&mut self) {
+        self.edit_view.set_content("");
+        self.history_idx = self.history.len();
+        self.completions = None;
+    }
+
+    fn show_history(&mut self, idx: usize) {
+        if self.history_idx == self.history.len() {
+            self.typed = self.edit_view.get_content().to_string();
+        }
+        self.history_idx = idx;
+        let line = self.history.get(idx).unwrap_or(&self.typed).clone();
+        self.edit_view.set_content(line);
+        self.completions = None;
+    }
+
+    /// Complete the last word, pressing Tab again shows the next completion.
+    fn complete(&mut self) {
+        let (candidates, idx) = match self.completions.take() {
+            Some((candidates, idx)) => {
+                let idx = (idx + 1) % candidates.len();
+                (candidates, idx)
+            }
+            None => (
+                completions(&self.edit_view.get_content(), &main_view::view_names()),
+                0,
+            ),
+        };
+        if let Some(line) = candidates.get(idx) {
+            self.edit_view.set_content(line.clone());
+        }
+        // With one completion the next Tab goes on from it, like into a directory
+        if candidates.len() > 1 {
+            self.completions = Some((candidates, idx));
+        }
+    }
+}
+
+pub fn open(siv: &mut Cursive) {
+    siv.call_on_name(
+        "command_line_hider",
+        |hider: &mut HideableView<LinearLayout>| hider.unhide(),
+    );
+    siv.focus_name("command_line").ok();
+}
+
+/// Hide the command line and give the focus back to the views above it
+fn close(siv: &mut Cursive) {
+    siv.call_on_name(
+        "command_line_hider",
+        |hider: &mut HideableView<LinearLayout>| hider.hide(),
+    );
+    siv.call_on_name("top_level_layout", |layout: &mut LinearLayout| {
+        layout.set_focus_index(0).ok();
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            parse_command("seek 1:30"),
+            Ok(Command::Control(control::Command::Seek(
+                SeekTarget::Absolute(Duration::seconds(90))
+            )))
+        );
+        assert_eq!(
+            parse_command("seek -10"),
+            Ok(Command::Control(control::Command::Seek(
+                SeekTarget::Relative(Duration::seconds(-10))
+            )))
+        );
+        assert_eq!(
+            parse_command("vol 60"),
+            Ok(Command::Control(control::Command::Volume(
+                VolumeTarget::Absolute(0.6)
+            )))
+        );
+        assert_eq!(
+            parse_command("vol -5"),
+            Ok(Command::Control(control::Command::Volume(
+                VolumeTarget::Relative(-0.05)
+            )))
+        );
+        assert_eq!(
+            parse_command("sleep 1h30m"),
+            Ok(Command::Control(control::Command::Sleep(Some(
+                Duration::minutes(90)
+            ))))
+        );
+        assert_eq!(
+            parse_command("sleep off"),
+            Ok(Command::Control(control::Command::Sleep(None)))
+        );
+        assert_eq!(
+            parse_command("search artist:George test"),
+            Ok(Command::Search(vec![
+                (TrackField::Artist, "George".to_string()),
+                (TrackField::Any, "test".to_string())
+            ]))
+        );
+        assert_eq!(
+            parse_command("view  albums"),
+            Ok(Command::View("albums".to_string()))
+        );
+        assert_eq!(
+            parse_command("toggle_play"),
+            Ok(Command::Action(Action::TogglePlay))
+        );
+
+        assert!(parse_command("seek 1:75").is_err());
+        assert!(parse_command("vol 150").is_err());
+        assert!(parse_command("sleep 30x").is_err());
+        assert!(parse_command("search genre:rock")
+            .unwrap_err()
+            .contains("genre"));
+        assert!(parse_command("save-queue ../queue").is_err());
+        assert!(parse_command("toggle_play now").is_err());
+        assert!(parse_command("rate_3").is_err());
+        assert!(parse_command("blarg").unwrap_err().contains("blarg"));
+    }
+
+    #[test]
+    fn complete_lines() {
+        let views = vec!["albums".to_string(), "all_songs".to_string()];
+        assert_eq!(completions("sa", &views), vec!["save-queue"]);
+        assert!(completions("toggle_q", &views).contains(&"toggle_queue".to_string()));
+        assert_eq!(
+            completions("view al", &views),
+            vec!["view albums", "view all_songs"]
+        );
+        assert_eq!(
+            completions("search foo ar", &views),
+            vec!["search foo artist:"]
+        );
+        assert!(completions("seek 1", &views).is_empty());
+
+        let dir = std::env::temp_dir().join("musicom-complete-test");
+        fs::create_dir_all(dir.join("Music")).unwrap();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        fs::write(dir.join("Mixtape.m3u"), "").unwrap();
+        let line = format!("add {}/", dir.display());
+        assert_eq!(
+            completions(&format!("{}M", line), &views),
+            vec![format!("{}Music/", line)]
+        );
+        assert_eq!(completions(&line, &views), vec![format!("{}Music/", line)]);
+    }
+}
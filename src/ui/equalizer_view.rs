@@ -0,0 +1,254 @@
+use cursive::event::{Event, EventResult, Key};
+use cursive::traits::{Nameable, Resizable};
+use cursive::view::{View, ViewWrapper};
+use cursive::views::{Dialog, EditView, OnEventView, Panel, SelectView, TextView};
+use cursive::Cursive;
+
+use crate::keymap::{self, Action};
+use crate::library::{EqAutoKind, EqPreset, NUM_BANDS};
+use crate::player::{PlayerHdl, BAND_FREQUENCIES, MAX_BAND_GAIN_DB, MIN_BAND_GAIN_DB};
+use crate::ui::help_view::{self, ViewHelp};
+
+const HELP: ViewHelp = ViewHelp {
+    title: "Equalizer",
+    actions: &[
+        Action::PreviousBand,
+        Action::NextBand,
+        Action::RaiseBand,
+        Action::LowerBand,
+        Action::ResetBand,
+        Action::ToggleEqualizer,
+        Action::ChooseEqPreset,
+        Action::SaveEqPreset,
+        Action::DeleteEqPreset,
+        Action::ToggleAutoEqPresets,
+        Action::SetAlbumEqPreset,
+        Action::SetGenreEqPreset,
+    ],
+    keys: &[(&["Esc"], "Close the equalizer")],
+    global_keys: true,
+};
+
+/// Width of the bar drawn for each band, one cell per dB
+const BAR_WIDTH: usize = (MAX_BAND_GAIN_DB - MIN_BAND_GAIN_DB) as usize;
+
+pub struct EqualizerView {
+    text_view: TextView,
+    player: PlayerHdl,
+    selected_band: usize,
+}
+
+impl ViewWrapper for EqualizerView {
+    cursive::wrap_impl!(self.text_view: TextView);
+
+    fn wrap_on_event(&mut self, e: Event) -> EventResult {
+        let settings = self.player.get_equalizer();
+        let gain = settings.get_preset().gains[self.selected_band];
+        match keymap::action_for(&e, HELP.actions) {
+            Some(Action::PreviousBand) => {
+                self.selected_band = self.selected_band.saturating_sub(1);
+            }
+            Some(Action::NextBand) => {
+                self.selected_band = (self.selected_band + 1).min(NUM_BANDS - 1);
+            }
+            Some(Action::RaiseBand) => self
+                .player
+                .set_equalizer_band(self.selected_band, gain + 1.0),
+            Some(Action::LowerBand) => self
+                .player
+                .set_equalizer_band(self.selected_band, gain - 1.0),
+            Some(Action::ResetBand) => self.player.set_equalizer_band(self.selected_band, 0.0),
+            Some(Action::ToggleEqualizer) => {
+                self.player.set_equalizer_enabled(!settings.is_enabled())
+            }
+            Some(Action::ToggleAutoEqPresets) => self
+                .player
+                .set_equalizer_auto_select(!settings.get_auto_select()),
+            Some(Action::ChooseEqPreset) => return EventResult::with_cb(Self::show_preset_list),
+            Some(Action::SaveEqPreset) => return EventResult::with_cb(Self::show_save_preset),
+            Some(Action::DeleteEqPreset) => {
+                let preset = settings.get_preset().clone();
+                if EqPreset::is_builtin(&preset.name) {
+                    return EventResult::Consumed(None);
+                }
+                preset.delete();
+                self.player
+                    .set_equalizer_preset(EqPreset::builtin().remove(0));
+            }
+            Some(Action::SetAlbumEqPreset) => {
+                if let Some(album) = self.player.now_playing().get_album() {
+                    return Self::set_auto(EqAutoKind::Album, &album, settings.get_preset());
+                }
+            }
+            Some(Action::SetGenreEqPreset) => {
+                if let Some(genre) = self.player.now_playing().get_genre() {
+                    return Self::set_auto(EqAutoKind::Genre, &genre, settings.get_preset());
+                }
+            }
+            // action_for only returns the actions above
+            Some(_) => (),
+            _ if keymap::action_for(&e, &[Action::Help]).is_some() => {
+                return EventResult::with_cb(|siv| help_view::show_help(siv, Some(&HELP)))
+            }
+            _ => return EventResult::Ignored,
+        }
+
+        self.refresh_view();
+        EventResult::Consumed(None)
+    }
+}
+
+impl EqualizerView {
+    pub fn new() -> impl View {
+        let mut eq_view = Self {
+            text_view: TextView::new(""),
+            player: PlayerHdl::new(),
+            selected_band: 0,
+        };
+        eq_view.refresh_view();
+
+        let panel = Panel::new(eq_view.with_name("equalizer_view"))
+            .title("Equalizer")
+            .fixed_width(BAR_WIDTH + 24);
+        OnEventView::new(panel).on_pre_event(Key::Esc, |siv| {
+            siv.pop_layer();
+        })
+    }
+
+    fn refresh_view(&mut self) {
+        let settings = self.player.get_equalizer();
+        let preset = settings.get_preset();
+
+        let mut lines = vec![
+            format!(
+                "{}  Preset: {}{}",
+                if settings.is_enabled() { "On " } else { "Off" },
+                preset.name,
+                if settings.is_auto_selected() {
+                    " (auto)"
+                } else {
+                    ""
+                }
+            ),
+            format!(
+                "Automatic presets: {}",
+                if settings.get_auto_select() {
+                    "on"
+                } else {
+                    "off"
+                }
+            ),
+            String::new(),
+        ];
+
+        let zero = -MIN_BAND_GAIN_DB as usize;
+        for (band, gain) in preset.gains.iter().enumerate() {
+            let filled = (gain - MIN_BAND_GAIN_DB).round() as usize;
+            let bar = (0..BAR_WIDTH)
+                .map(|cell| match cell {
+                    cell if cell == zero => '|',
+                    cell if (cell < filled && cell >= zero) || (cell >= filled && cell < zero) => {
+                        '='
+                    }
+                    _ => ' ',
+                })
+                .collect::<String>();
+            lines.push(format!(
+                "{} {:>6} {} {:+5.1}dB",
+                if band == self.selected_band { '>' } else { ' ' },
+                format_frequency(BAND_FREQUENCIES[band]),
+                bar,
+                gain
+            ));
+        }
+        lines.push(String::new());
+        lines.push("Press <?> for help".to_string());
+
+        self.text_view.set_content(lines.join("\n"));
+    }
+
+    fn refresh(siv: &mut Cursive) {
+        siv.call_on_name("equalizer_view", |view: &mut EqualizerView| {
+            view.refresh_view();
+        });
+    }
+
+    /// The album and genre presets are looked up by name, so the bands changed by hand have to be
+    /// saved first or they would be lost.
+    fn set_auto(kind: EqAutoKind, key: &str, preset: &EqPreset) -> EventResult {
+        if preset.is_modified() {
+            if EqPreset::is_builtin(&preset.name) {
+                return EventResult::with_cb(|siv| {
+                    siv.add_layer(
+                        Dialog::info(
+                            "The preset has unsaved changes, save it under a new name first",
+                        )
+                        .title("Equalizer"),
+                    );
+                });
+            }
+            preset.clone().save();
+        }
+        EqPreset::set_auto(kind, key, &preset.name);
+        EventResult::Consumed(None)
+    }
+
+    fn show_preset_list(siv: &mut Cursive) {
+        let mut select_view = SelectView::new();
+        for preset in EqPreset::all() {
+            select_view.add_item(preset.name.clone(), preset);
+        }
+        select_view.set_on_submit(|siv, preset: &EqPreset| {
+            PlayerHdl::new().set_equalizer_preset(preset.clone());
+            siv.pop_layer();
+            Self::refresh(siv);
+        });
+
+        let wrapped = OnEventView::new(select_view).on_pre_event(Key::Esc, |siv| {
+            siv.pop_layer();
+        });
+        siv.add_layer(Panel::new(wrapped).title("Presets"));
+    }
+
+    fn show_save_preset(siv: &mut Cursive) {
+        let save = |siv: &mut Cursive, name: &str| {
+            let name = name.trim();
+            // The built-in presets can't be overwritten
+            if name.is_empty() || EqPreset::is_builtin(name) {
+                return;
+            }
+
+            let player = PlayerHdl::new();
+            let mut preset = EqPreset::new(name, player.get_equalizer().get_preset().gains);
+            preset.save();
+            player.set_equalizer_preset(preset);
+            siv.pop_layer();
+            Self::refresh(siv);
+        };
+
+        siv.add_layer(
+            Dialog::around(
+                EditView::new()
+                    .on_submit(save)
+                    .with_name("eq_preset_name")
+                    .fixed_width(30),
+            )
+            .title("Save preset as")
+            .button("Save", move |siv| {
+                let name = siv
+                    .call_on_name("eq_preset_name", |view: &mut EditView| view.get_content())
+                    .unwrap();
+                save(siv, &name);
+            })
+            .dismiss_button("Cancel"),
+        );
+    }
+}
+
+fn format_frequency(frequency: u32) -> String {
+    if frequency >= 1000 {
+        format!("{:.1}k", frequency as f64 / 1000.0)
+    } else {
+        frequency.to_string()
+    }
+}
@@ -7,18 +7,22 @@ use cursive::direction::Direction;
 use cursive::event::{AnyCb, Event, EventResult};
 use cursive::traits::*;
 use cursive::view::{Resizable, Scrollable, Selector};
-use cursive::views::{Dialog, Panel, SelectView};
+use cursive::views::{Panel, SelectView};
 use cursive::{Printer, Rect, Vec2};
 
+use crate::config;
+use crate::keymap::{self, Action};
 use crate::library::TrackedPath;
 use crate::player::is_audio_file_guess;
 use crate::player::{PlayerHdl, QueueItem};
+use crate::ui::help_view::{self, ViewHelp};
 
-const HELP_TEXT: &'static str = "\
-Press <Enter> to start playing a file, or browse between folders
-Press <a> to open the action menu for a file
-Press <p> to pause/play the current song
-Press <?> to open this help menu";
+const HELP: ViewHelp = ViewHelp {
+    title: "File browser",
+    actions: &[Action::OpenActions],
+    keys: &[(&["Enter"], "Play a file, or browse between folders")],
+    global_keys: true,
+};
 
 pub struct FileBrowserView {
     select_view: SelectView,
@@ -45,8 +49,8 @@ impl View for FileBrowserView {
     }
 
     fn on_event(&mut self, e: Event) -> EventResult {
-        match e {
-            Event::Char('a') => {
+        match keymap::action_for(&e, HELP.actions) {
+            Some(Action::OpenActions) => {
                 let path = self.get_current_selection();
                 let current_dir = self.get_current_directory();
                 EventResult::with_cb(move |siv| {
@@ -54,10 +58,9 @@ impl View for FileBrowserView {
                     siv.add_layer(action_popup);
                 })
             }
-            Event::Char('?') => EventResult::with_cb(move |siv| {
-                let help_popup = Self::get_help_view();
-                siv.add_layer(help_popup);
-            }),
+            _ if keymap::action_for(&e, &[Action::Help]).is_some() => {
+                EventResult::with_cb(|siv| help_view::show_help(siv, Some(&HELP)))
+            }
             _ => self.select_view.on_event(e),
         }
     }
@@ -81,14 +84,16 @@ impl View for FileBrowserView {
 
 impl FileBrowserView {
     pub fn new() -> impl View {
-        let user_dirs = directories::UserDirs::new().unwrap();
-        let home = user_dirs.home_dir().to_str().unwrap();
+        let directory = config::get().file_browser.start_dir.unwrap_or_else(|| {
+            let user_dirs = directories::UserDirs::new().unwrap();
+            user_dirs.home_dir().to_path_buf()
+        });
 
         let select_view = SelectView::new().h_align(HAlign::Center);
 
         let mut fbv = FileBrowserView {
             select_view,
-            directory: home.into(),
+            directory,
             player: PlayerHdl::new(),
         };
 
@@ -116,22 +121,18 @@ impl FileBrowserView {
         });
     }
 
-    fn get_help_view() -> impl View {
-        Dialog::info(HELP_TEXT)
-    }
-
     fn refresh_view(&mut self) {
         self.select_view.clear();
+        let show_hidden = config::get().file_browser.show_hidden;
         let mut entries = fs::read_dir(&self.directory)
             .unwrap()
             .map(|res| res.map(|e| e.path()))
             .filter(|res| {
                 if let Ok(path) = res {
-                    // XXX At some point we should make showing hidden files configurable
                     let is_hidden_file =
                         path.file_name().unwrap().to_string_lossy().starts_with(".");
                     let is_audio_file = is_audio_file_guess(path);
-                    !is_hidden_file && (is_audio_file || path.is_dir())
+                    (show_hidden || !is_hidden_file) && (is_audio_file || path.is_dir())
                 } else {
                     false
                 }
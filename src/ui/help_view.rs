@@ -0,0 +1,177 @@
+//! The help lists the keys that do something where it was opened: the keys of the focused view,
+//! then every action of the keymap with the keys it is bound to right now.
+
+use cursive::traits::Scrollable;
+use cursive::views::{Dialog, TextView};
+use cursive::Cursive;
+
+use crate::keymap::{self, Action, ActionGroup, Keymap};
+
+/// The keys a view handles itself. The views take the actions in `actions` from the keymap
+/// with `keymap::action_for`, so the help and the keys can't disagree.
+pub struct ViewHelp {
+    pub title: &'static str,
+    /// The actions of the keymap that this view handles itself
+    pub actions: &'static [Action],
+    /// Keys of the widgets the view is made of, like Enter in a list
+    pub keys: &'static [(&'static [&'static str], &'static str)],
+    /// Whether the global actions of the keymap work in this view too
+    pub global_keys: bool,
+}
+
+/// A title and the keys with what they do
+type HelpSection = (&'static str, Vec<(String, &'static str)>);
+
+fn help_sections(keymap: &Keymap, view: Option<&ViewHelp>) -> Vec<HelpSection> {
+    let mut sections = Vec::new();
+    // The view takes its own keys before the keymap sees them
+    let mut view_keys = Vec::new();
+
+    if let Some(view) = view {
+        let mut entries = Vec::new();
+        for action in view.actions {
+            let keys = keymap.keys_for(*action);
+            if !keys.is_empty() {
+                entries.push((keys.join(", "), action.description()));
+                view_keys.extend(keys);
+            }
+        }
+        for (keys, description) in view.keys {
+            entries.push((keys.join(", "), *description));
+            view_keys.extend(keys.iter().map(|key| key.to_string()));
+        }
+        sections.push((view.title, entries));
+
+        if !view.global_keys {
+            return sections;
+        }
+    }
+    let view_first_keys = view_keys
+        .iter()
+        .map(|sequence| sequence.split(' ').next().unwrap_or_default())
+        .collect::<Vec<_>>();
+
+    for group in ActionGroup::ALL.iter().filter(|g| **g != ActionGroup::View) {
+        let entries = Action::all()
+            .filter(|action| action.group() == *group)
+            .filter(|action| view.map_or(true, |view| !view.actions.contains(action)))
+            .filter_map(|action| {
+                let keys = keymap
+                    .keys_for(action)
+                    .into_iter()
+                    .filter(|sequence| {
+                        let first = sequence.split(' ').next().unwrap_or_default();
+                        !view_first_keys.iter().any(|key| *key == first)
+                    })
+                    .collect::<Vec<_>>();
+                if keys.is_empty() {
+                    None
+                } else {
+                    Some((keys.join(", "), action.description()))
+                }
+            })
+            .collect::<Vec<_>>();
+        if !entries.is_empty() {
+            sections.push((group.title(), entries));
+        }
+    }
+
+    sections
+}
+
+fn help_text(sections: &[HelpSection]) -> String {
+    let width = sections
+        .iter()
+        .flat_map(|(_, entries)| entries.iter().map(|(keys, _)| keys.chars().count()))
+        .max()
+        .unwrap_or_default();
+
+    sections
+        .iter()
+        .map(|(title, entries)| {
+            let lines = entries
+                .iter()
+                .map(|(keys, description)| {
+                    format!("  {:width$}  {}", keys, description, width = width)
+                })
+                .collect::<Vec<_>>();
+            format!("{}\n{}", title, lines.join("\n"))
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Show the keys of the view, or only the global ones if it is `None`.
+pub fn show_help(siv: &mut Cursive, view: Option<&ViewHelp>) {
+    let text = help_text(&help_sections(&keymap::get_keymap(), view));
+    siv.add_layer(
+        Dialog::around(TextView::new(text).scrollable())
+            .title("Keys")
+            .dismiss_button("Ok"),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keymap::{KeyList, KeysConfig};
+
+    const TEST_VIEW: ViewHelp = ViewHelp {
+        title: "Test",
+        actions: &[Action::OpenActions, Action::ToggleEqualizer],
+        keys: &[(&["Enter"], "Play the selection")],
+        global_keys: true,
+    };
+
+    fn find<'a>(sections: &'a [HelpSection], title: &str) -> &'a [(String, &'static str)] {
+        &sections.iter().find(|(t, _)| *t == title).unwrap().1
+    }
+
+    #[test]
+    fn sections_from_keymap() {
+        let mut config = KeysConfig::default();
+        config.bindings.insert(
+            "toggle_play".to_string(),
+            KeyList::Many(vec!["Space".to_string(), "C-p".to_string()]),
+        );
+        let keymap = Keymap::new(&config).unwrap();
+
+        let sections = help_sections(&keymap, None);
+        assert_eq!(sections[0].0, "Playback");
+        assert_eq!(
+            find(&sections, "Playback")[0],
+            ("Space, C-p".to_string(), Action::TogglePlay.description())
+        );
+        assert!(find(&sections, "Windows").contains(&("e".to_string(), "Open the equalizer")));
+        // Nothing is bound to them, and open_actions only works in views
+        assert!(!sections.iter().any(|(title, _)| *title == "Navigation"));
+        assert!(!sections.iter().any(|(title, _)| *title == "Views"));
+
+        let sections = help_sections(&keymap, Some(&TEST_VIEW));
+        assert_eq!(
+            sections[0],
+            (
+                "Test",
+                vec![
+                    ("a".to_string(), Action::OpenActions.description()),
+                    ("e".to_string(), Action::ToggleEqualizer.description()),
+                    ("Enter".to_string(), "Play the selection"),
+                ]
+            )
+        );
+        assert!(!find(&sections, "Windows").contains(&("e".to_string(), "Open the equalizer")));
+
+        let text = help_text(&sections);
+        assert!(text.starts_with("Test\n  a           Open the action menu"));
+        assert!(text.contains("\n\nPlayback\n  Space, C-p  Pause or play"));
+
+        let remote = ViewHelp {
+            actions: &[Action::TogglePlay],
+            global_keys: false,
+            ..TEST_VIEW
+        };
+        let sections = help_sections(&keymap, Some(&remote));
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].1[0].0, "Space, C-p");
+    }
+}
@@ -5,15 +5,43 @@ use cursive::views::{Dialog, OnEventView, Panel, SelectView};
 
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::config;
+use crate::keymap::{self, Action};
+use crate::library::loudness::{self, AnalysisMode};
+use crate::library::rating;
 use crate::library::{Album, Track};
 use crate::player::{PlayerHdl, QueueItem};
-use crate::ui::main_view;
+use crate::ui::help_view::{self, ViewHelp};
+use crate::ui::{loudness_dialog, main_view, rating_view};
 
-const HELP_TEXT: &'static str = "\
-Press <Enter> to start playing a track
-Press <a> to open the action menu for a track
-Press <p> to pause/play the current song
-Press <?> to open this help menu";
+const HELP: ViewHelp = ViewHelp {
+    title: "Songs",
+    actions: &[
+        Action::OpenActions,
+        Action::RateSelection(0),
+        Action::RateSelection(1),
+        Action::RateSelection(2),
+        Action::RateSelection(3),
+        Action::RateSelection(4),
+        Action::RateSelection(5),
+        Action::ToggleSelectionLoved,
+    ],
+    keys: &[(&["Enter"], "Play the selected track")],
+    global_keys: true,
+};
+
+fn get_label(track: &Track) -> String {
+    let track_name = track.title.clone().unwrap_or("No Title".to_string());
+    let short_track_name = track_name
+        .graphemes(true)
+        .take(config::get().ui.title_width)
+        .collect::<String>();
+
+    match rating_view::format_rating(track) {
+        Some(rating) => format!("{}  {}", short_track_name, rating),
+        None => short_track_name,
+    }
+}
 
 pub struct LibrarySongView {
     select_view: SelectView<Track>,
@@ -24,8 +52,8 @@ impl ViewWrapper for LibrarySongView {
     cursive::wrap_impl!(self.select_view: SelectView<Track>);
 
     fn wrap_on_event(&mut self, e: Event) -> EventResult {
-        match e {
-            Event::Char('a') => {
+        match keymap::action_for(&e, HELP.actions) {
+            Some(Action::OpenActions) => {
                 let track = self.select_view.selection();
                 if let Some(track) = track {
                     EventResult::with_cb(move |siv| {
@@ -36,10 +64,15 @@ impl ViewWrapper for LibrarySongView {
                     EventResult::Consumed(None)
                 }
             }
-            Event::Char('?') => EventResult::with_cb(move |siv| {
-                let help_popup = Dialog::info(HELP_TEXT);
-                siv.add_layer(help_popup);
-            }),
+            Some(Action::RateSelection(rating)) => {
+                self.update_selected_rating(|track| track.rating = rating)
+            }
+            Some(Action::ToggleSelectionLoved) => {
+                self.update_selected_rating(|track| track.loved = !track.loved)
+            }
+            _ if keymap::action_for(&e, &[Action::Help]).is_some() => {
+                EventResult::with_cb(|siv| help_view::show_help(siv, Some(&HELP)))
+            }
             _ => self.select_view.on_event(e),
         }
     }
@@ -93,10 +126,28 @@ impl LibrarySongView {
     {
         self.select_view.clear();
         for track in tracks.into_iter() {
-            let track_name = track.title.clone().unwrap_or("No Title".to_string());
+            self.select_view.add_item(get_label(track), track.clone());
+        }
+    }
+
+    fn update_selected_rating<F>(&mut self, update: F) -> EventResult
+    where
+        F: FnOnce(&mut Track),
+    {
+        let idx = match self.select_view.selected_id() {
+            Some(idx) => idx,
+            None => return EventResult::Consumed(None),
+        };
+        let (label, track) = match self.select_view.get_item_mut(idx) {
+            Some(item) => item,
+            None => return EventResult::Consumed(None),
+        };
 
-            let short_track_name = track_name.graphemes(true).take(50).collect::<String>();
-            self.select_view.add_item(short_track_name, track.clone());
+        update(track);
+        *label = get_label(track).into();
+        match rating::save_rating(track) {
+            Ok(()) => EventResult::Consumed(None),
+            Err(e) => EventResult::with_cb(move |siv| siv.add_layer(Dialog::info(e.clone()))),
         }
     }
 
@@ -106,14 +157,39 @@ impl LibrarySongView {
             PlayNow,
             GoToAlbum,
             AddToQueue,
+            AnalyseTrack,
+            AnalyseAlbum,
+            AnalyseAll,
+            ToggleWriteGainTags,
         };
         let mut action_popup = SelectView::new();
         action_popup.add_item("Add to queue", Actions::AddToQueue);
         action_popup.add_item("Go to Album", Actions::GoToAlbum);
         action_popup.add_item("Play Now", Actions::PlayNow);
+        action_popup.add_item("Analyse loudness", Actions::AnalyseTrack);
+        if track.album.is_some() {
+            action_popup.add_item("Analyse album loudness", Actions::AnalyseAlbum);
+        }
+        action_popup.add_item("Analyse loudness of all listed songs", Actions::AnalyseAll);
+        if cfg!(feature = "write-tags") {
+            action_popup.add_item(
+                format!(
+                    "Write gain tags: {}",
+                    if loudness::get_write_tags() {
+                        "on"
+                    } else {
+                        "off"
+                    }
+                ),
+                Actions::ToggleWriteGainTags,
+            );
+        }
 
         action_popup.set_on_submit(move |s, action| {
             let player = PlayerHdl::new();
+            let write_tags = cfg!(feature = "write-tags") && loudness::get_write_tags();
+            // Pop the action menu first, the analysis actions open a dialog of their own
+            s.pop_layer();
             match action {
                 Actions::PlayNow => {
                     s.call_on_name("library_song_view", |v: &mut LibrarySongView| {
@@ -144,8 +220,37 @@ impl LibrarySongView {
                     }
                 }
                 Actions::AddToQueue => player.queue_mut().add_track(&track),
+                Actions::AnalyseTrack => loudness_dialog::start_analysis(
+                    s,
+                    vec![track.clone()],
+                    AnalysisMode::Track,
+                    write_tags,
+                ),
+                Actions::AnalyseAlbum => {
+                    if let Some(album_str) = track.album.as_ref() {
+                        // Leave out other albums with the same title
+                        let album = Album::get_album(album_str);
+                        let tracks = album
+                            .iter_tracks()
+                            .filter(|t| t.path.parent() == track.path.parent())
+                            .cloned()
+                            .collect();
+                        loudness_dialog::start_analysis(s, tracks, AnalysisMode::Album, write_tags);
+                    }
+                }
+                Actions::AnalyseAll => {
+                    let tracks = s
+                        .call_on_name("library_song_view", |v: &mut LibrarySongView| {
+                            v.select_view
+                                .iter()
+                                .map(|(_s, track)| track.clone())
+                                .collect::<Vec<_>>()
+                        })
+                        .unwrap_or_default();
+                    loudness_dialog::start_analysis(s, tracks, AnalysisMode::Album, write_tags);
+                }
+                Actions::ToggleWriteGainTags => loudness::set_write_tags(!write_tags),
             }
-            s.pop_layer();
         });
 
         let wrapped_event = OnEventView::new(action_popup).on_pre_event(Key::Esc, |siv| {
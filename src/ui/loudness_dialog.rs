@@ -0,0 +1,55 @@
+use cursive::traits::Resizable;
+use cursive::utils::Counter;
+use cursive::views::{Dialog, LinearLayout, ProgressBar, TextContent, TextView};
+use cursive::Cursive;
+
+use crate::library::loudness::{spawn_analysis, AnalysisMode};
+use crate::library::Track;
+
+/// Analyse the loudness of the tracks in the background and show the progress in a dialog.
+pub fn start_analysis(siv: &mut Cursive, tracks: Vec<Track>, mode: AnalysisMode, write_tags: bool) {
+    if tracks.is_empty() {
+        return;
+    }
+
+    let counter = Counter::new(0);
+    let current = TextContent::new("");
+    let total = tracks.len();
+
+    let layout = LinearLayout::vertical()
+        .child(TextView::new_with_content(current.clone()))
+        .child(
+            ProgressBar::new()
+                .range(0, total)
+                .with_value(counter.clone())
+                .with_label(|value, (_, max)| format!("{}/{}", value, max)),
+        );
+    siv.add_layer(
+        Dialog::around(layout)
+            .title("Analysing loudness")
+            .button("Hide", |s| {
+                s.pop_layer();
+            })
+            .min_width(60),
+    );
+
+    let cb_sink = siv.cb_sink().clone();
+    spawn_analysis(tracks, mode, write_tags, move |progress| {
+        counter.set(progress.done);
+        match progress.current.as_ref() {
+            Some(track) => current.set_content(
+                track
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| track.path.display().to_string()),
+            ),
+            None => current.set_content(format!(
+                "Done, {} of {} songs could not be analysed",
+                progress.failed, progress.total
+            )),
+        }
+        // Wake up the UI so the new progress gets drawn. If the UI is gone the analysis still
+        // finishes and stores its results.
+        cb_sink.send(Box::new(|_| ())).ok();
+    });
+}
@@ -1,12 +1,17 @@
+use cursive::traits::Finder;
 use cursive::view::{Nameable, View, ViewWrapper};
 use cursive::views::{BoxedView, Panel, SelectView};
 use cursive::{wrap_impl, Cursive};
 
 use lazy_static::lazy_static;
 
+use crate::config::{self, StartView};
+use crate::library::Track;
+
 use super::file_browser::FileBrowserView;
 use super::library::LibrarySongView;
 use super::library::LibraryAlbumView;
+use super::stats_view::StatsView;
 
 type CreateDefaultViewCb = dyn Fn() -> BoxedView + Send + Sync;
 
@@ -20,13 +25,60 @@ lazy_static! {
             "Albums".to_string(),
             Box::new(|| BoxedView::boxed(LibraryAlbumView::new())),
         ),
+        (
+            "Favorites".to_string(),
+            Box::new(|| {
+                let mut song_view = LibrarySongView::new();
+                song_view.call_on_name("library_song_view", |v: &mut LibrarySongView| {
+                    v.show_songs_from_iter(&Track::iter_favorites().collect::<Vec<_>>());
+                });
+                BoxedView::boxed(song_view)
+            }),
+        ),
         (
             "File Browser".to_string(),
             Box::new(|| BoxedView::boxed(FileBrowserView::new())),
         ),
+        (
+            "Stats".to_string(),
+            Box::new(|| BoxedView::boxed(StatsView::new())),
+        ),
     ];
 }
 
+/// The name of the view in the list of views
+fn start_view_name(view: StartView) -> &'static str {
+    match view {
+        StartView::AllSongs => "All Songs",
+        StartView::Albums => "Albums",
+        StartView::Favorites => "Favorites",
+        StartView::FileBrowser => "File Browser",
+        StartView::Stats => "Stats",
+    }
+}
+
+/// The name of a view in commands, like `file_browser`
+fn command_name(name: &str) -> String {
+    name.to_lowercase().replace(' ', "_")
+}
+
+pub fn view_names() -> Vec<String> {
+    VIEW_CB_PAIRS
+        .iter()
+        .map(|(name, _)| command_name(name))
+        .collect()
+}
+
+/// Show the view with the name from `view_names`
+pub fn show_view(siv: &mut Cursive, name: &str) -> Result<(), String> {
+    let (_, view_cb) = VIEW_CB_PAIRS
+        .iter()
+        .find(|(view_name, _)| command_name(view_name) == name)
+        .ok_or_else(|| format!("there is no view {}", name))?;
+    replace_view(siv, view_cb());
+    Ok(())
+}
+
 pub struct MainView {
     boxed_view: BoxedView,
     registered_views: Vec<(String, Box<CreateDefaultViewCb>)>,
@@ -51,7 +103,12 @@ pub fn select_new_view_from_user(siv: &mut Cursive) {
 
 impl MainView {
     pub fn new() -> impl View {
-        let boxed_view = BoxedView::boxed(LibraryAlbumView::new());
+        let start_view = start_view_name(config::get().ui.start_view);
+        let boxed_view = VIEW_CB_PAIRS
+            .iter()
+            .find(|(name, _)| name == start_view)
+            .map(|(_, view_cb)| view_cb())
+            .unwrap();
 
         let mut ret = Self {
             boxed_view,
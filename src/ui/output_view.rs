@@ -0,0 +1,89 @@
+use cursive::event::Key;
+use cursive::traits::{Nameable, Resizable};
+use cursive::view::View;
+use cursive::views::{Dialog, EditView, OnEventView, Panel, SelectView};
+use cursive::Cursive;
+
+use crate::player::{list_outputs, AudioOutput, PlayerHdl};
+
+enum OutputChoice {
+    Output(AudioOutput),
+    /// Ask for the path of the file to write to
+    File,
+}
+
+fn get_output_name(output: &AudioOutput) -> String {
+    match output {
+        AudioOutput::Auto => "Automatic".to_string(),
+        AudioOutput::Pulse(_) => "PulseAudio".to_string(),
+        AudioOutput::Alsa(_) => "ALSA".to_string(),
+        AudioOutput::PipeWire(_) => "PipeWire".to_string(),
+        AudioOutput::Device(name) => name.clone(),
+        AudioOutput::File(path) => format!("File {}", path.display()),
+        AudioOutput::Fake => "No audio".to_string(),
+    }
+}
+
+pub fn show_output_chooser(siv: &mut Cursive) {
+    let current = PlayerHdl::new().get_output();
+
+    let mut select_view = SelectView::new();
+    for output in list_outputs() {
+        let mut name = get_output_name(&output);
+        if output == current {
+            name = format!("{} (current)", name);
+        }
+        select_view.add_item(name, OutputChoice::Output(output));
+    }
+    select_view.add_item("Write to a file...", OutputChoice::File);
+
+    select_view.set_on_submit(|siv, choice| {
+        siv.pop_layer();
+        match choice {
+            OutputChoice::Output(output) => switch_output(siv, output.clone()),
+            OutputChoice::File => show_file_output_dialog(siv),
+        }
+    });
+
+    siv.add_layer(get_cancellable(select_view, "Audio output"));
+}
+
+fn get_cancellable<V: View>(view: V, title: &str) -> impl View {
+    let wrapped = OnEventView::new(view).on_pre_event(Key::Esc, |siv| {
+        siv.pop_layer();
+    });
+    Panel::new(wrapped).title(title)
+}
+
+fn switch_output(siv: &mut Cursive, output: AudioOutput) {
+    if let Err(e) = PlayerHdl::new().set_output(output) {
+        siv.add_layer(Dialog::info(e));
+    }
+}
+
+fn show_file_output_dialog(siv: &mut Cursive) {
+    let submit = |siv: &mut Cursive, path: &str| {
+        if path.trim().is_empty() {
+            return;
+        }
+        siv.pop_layer();
+        switch_output(siv, AudioOutput::File(path.trim().into()));
+    };
+
+    siv.add_layer(
+        Dialog::around(
+            EditView::new()
+                .on_submit(submit)
+                .with_name("output_file_path")
+                .fixed_width(50),
+        )
+        .title("Write audio to WAV file")
+        .button("Ok", move |siv| {
+            let path = siv
+                .call_on_name("output_file_path", |view: &mut EditView| view.get_content())
+                .unwrap();
+            submit(siv, &path);
+        })
+        .dismiss_button("Cancel"),
+    );
+}
@@ -4,14 +4,15 @@ use cursive::align::HAlign;
 use cursive::event::{Event, EventResult};
 use cursive::traits::*;
 use cursive::view::ViewWrapper;
-use cursive::views::{DummyView, LinearLayout, TextContent, TextView};
+use cursive::views::{LinearLayout, TextContent, TextView};
 use cursive::Cursive;
 use cursive::wrap_impl;
 
-use crate::player::PlayerHdl;
+use crate::player::{PlayerHdl, SleepTimer};
 
 pub struct PlayerView {
     player_hdl: PlayerHdl,
+    status: TextContent,
     stream_position: TextContent,
     now_playing: TextContent,
     linear_layout: LinearLayout,
@@ -52,13 +53,18 @@ impl PlayerView {
     }
 
     pub fn new(siv: &Cursive) -> impl View {
+        let status = TextContent::new("");
         let stream_position = TextContent::new("");
         let now_playing = TextContent::new("");
         let player_hdl = PlayerHdl::new();
 
         let mut linear_layout = LinearLayout::horizontal();
-        // DummyView is used to center the now_playing_view
-        linear_layout.add_child(DummyView {}.full_width());
+        // The status view is as wide as the position view, which keeps now_playing centered
+        linear_layout.add_child(
+            TextView::new_with_content(status.clone())
+                .h_align(HAlign::Left)
+                .full_width(),
+        );
         linear_layout.add_child(
             TextView::new_with_content(now_playing.clone())
                 .h_align(HAlign::Center)
@@ -72,6 +78,7 @@ impl PlayerView {
 
         let mut pv = PlayerView {
             player_hdl,
+            status,
             stream_position,
             now_playing,
             linear_layout,
@@ -85,12 +92,55 @@ impl PlayerView {
     }
 
     pub fn refresh_view(&mut self) {
+        let status = &self.status;
         let stream_position = &self.stream_position;
         let now_playing = &self.now_playing;
+        // Grab the player settings before locking now playing, in the player's lock order
+        let rate = self.player_hdl.get_speed().get_rate();
+        let sleep = self.player_hdl.get_sleep();
+        let ab_loop = self.player_hdl.get_ab_loop();
         let now_playing_hdl = self.player_hdl.now_playing();
+        let status_message = now_playing_hdl.get_status_message().unwrap_or_default();
+
+        if status.get_content().source() != status_message {
+            status.set_content(status_message);
+        }
+
         let (position, duration) = now_playing_hdl.get_song_progress();
         let song_name = now_playing_hdl.get_song_name();
         let position_string = format!("{}/{}", format_time(position), format_time(duration));
+        let position_string = match now_playing_hdl.get_replaygain() {
+            Some(gain) => format!("RG {:+.1}dB  {}", gain, position_string),
+            None => position_string,
+        };
+        let position_string = if (rate - 1.0).abs() > f64::EPSILON {
+            format!("{:.2}x  {}", rate, position_string)
+        } else {
+            position_string
+        };
+        let sleep_string = match sleep.get_timer() {
+            Some(SleepTimer::Time { .. }) => sleep
+                .get_timer()
+                .and_then(|timer| timer.get_remaining_time())
+                .map(|remaining| format!("Sleep {}", format_time(remaining))),
+            Some(SleepTimer::Tracks(tracks)) => Some(format!("Sleep in {} songs", tracks)),
+            None if sleep.get_stop_after_current() => Some("Stop after song".to_string()),
+            None => None,
+        };
+        let position_string = match (ab_loop.get_start(), ab_loop.get_end()) {
+            (Some(start), Some(end)) => format!(
+                "A-B {}-{}  {}",
+                format_time(start),
+                format_time(end),
+                position_string
+            ),
+            (Some(start), None) => format!("A {}-  {}", format_time(start), position_string),
+            _ => position_string,
+        };
+        let position_string = match sleep_string {
+            Some(sleep_string) => format!("{}  {}", sleep_string, position_string),
+            None => position_string,
+        };
 
         if stream_position.get_content().source() != position_string {
             stream_position.set_content(position_string);
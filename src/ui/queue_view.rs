@@ -1,30 +1,16 @@
 use cursive::align::HAlign;
-use cursive::event::{Event, EventResult};
 use cursive::view::{Nameable, View, ViewWrapper};
-use cursive::views::Dialog;
 use cursive::views::SelectView;
 
 use crate::player::PlayerHdl;
 
-const HELP_TEXT: &'static str = "\
-Press <p> to pause/play the current song";
-
 pub struct QueueView {
     select_view: SelectView,
     player: PlayerHdl,
 }
 
+// The queue has no keys of its own, the help of the UI lists the global ones
 impl ViewWrapper for QueueView {
-    fn wrap_on_event(&mut self, e: Event) -> EventResult {
-        match e {
-            Event::Char('?') => EventResult::with_cb(move |siv| {
-                let popup = Self::get_help_view();
-                siv.add_layer(popup);
-            }),
-            _ => self.select_view.on_event(e),
-        }
-    }
-
     cursive::wrap_impl!(self.select_view: SelectView);
 }
 
@@ -54,32 +40,33 @@ impl QueueView {
         qv.with_name("queue_view")
     }
 
-    fn get_help_view() -> impl View {
-        Dialog::info(HELP_TEXT)
-    }
-
     fn refresh_view(&mut self) {
         self.select_view.clear();
 
-        let queue = self.player.queue().get_queue_contents();
-        let queue_idx_opt = self.player.queue().get_queue_position();
+        let queue = self.player.queue();
+        let queue_contents = queue.get_queue_contents();
+        let queue_idx_opt = queue.get_queue_position();
 
-        if queue.is_empty() {
+        if queue_contents.is_empty() {
             self.select_view.add_item_str("Empty");
         } else {
             self.select_view.add_all_str(
-                queue
+                queue_contents
                     .iter()
-                    .filter_map(|item| item.get_path()?.file_name().to_owned())
-                    .filter_map(|item| item.to_str())
+                    .filter_map(|item| {
+                        let name = item.get_path()?.file_name()?.to_str()?;
+                        Some(if queue.is_failed(item) {
+                            format!("{} (failed)", name)
+                        } else {
+                            name.to_string()
+                        })
+                    })
                     .enumerate()
                     .map(|(idx, item)| {
-                        if let Some(queue_idx) = queue_idx_opt {
-                            (queue_idx == idx)
-                                .then(|| format!("-- {} --", item))
-                                .unwrap_or(item.to_string())
+                        if queue_idx_opt == Some(idx) {
+                            format!("-- {} --", item)
                         } else {
-                            item.to_string()
+                            item
                         }
                     }),
             );
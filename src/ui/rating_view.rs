@@ -0,0 +1,95 @@
+use cursive::event::Key;
+use cursive::views::{Dialog, OnEventView, Panel, SelectView};
+use cursive::Cursive;
+
+use crate::library::rating::{self, MAX_RATING};
+use crate::library::Track;
+use crate::player::{PlayerHdl, QueueItem};
+
+/// Stars and a heart for the song lists, `None` for tracks that are neither rated nor loved.
+pub fn format_rating(track: &Track) -> Option<String> {
+    if track.rating == 0 && !track.loved {
+        return None;
+    }
+
+    let mut rating = (1..=MAX_RATING)
+        .map(|star| if star <= track.rating { '★' } else { '☆' })
+        .collect::<String>();
+    if track.loved {
+        rating.push_str(" ♥");
+    }
+    Some(rating)
+}
+
+/// Ratings are stored in the library, so songs that aren't in it can't be rated.
+fn get_current_track(player: &PlayerHdl) -> Option<Track> {
+    let track_id = match player.now_playing().get_item()? {
+        QueueItem::Track(track) => track.id,
+        QueueItem::Path(path) => Track::from_path(path)?.id,
+        _ => None,
+    }?;
+    // The queue holds a copy of the track, get the current rating from the library
+    Track::get(track_id)
+}
+
+enum RatingAction {
+    Rate(u8),
+    ToggleLoved,
+    ToggleWriteTags,
+}
+
+/// Rate the song that is playing right now.
+pub fn show_rating_chooser(siv: &mut Cursive) {
+    let track = match get_current_track(&PlayerHdl::new()) {
+        Some(track) => track,
+        None => {
+            siv.add_layer(Dialog::info("Only songs in the library can be rated"));
+            return;
+        }
+    };
+
+    let mut sv = SelectView::new();
+    sv.add_item("No rating", RatingAction::Rate(0));
+    for stars in 1..=MAX_RATING {
+        let label = (0..stars).map(|_| '★').collect::<String>();
+        sv.add_item(label, RatingAction::Rate(stars));
+    }
+    sv.add_item(
+        if track.loved { "Stop loving" } else { "Love" },
+        RatingAction::ToggleLoved,
+    );
+    sv.add_item(
+        format!(
+            "Write ratings to tags: {}",
+            if rating::get_write_tags() {
+                "on"
+            } else {
+                "off"
+            }
+        ),
+        RatingAction::ToggleWriteTags,
+    );
+    let mut sv = sv.selected(track.rating as usize);
+
+    sv.set_on_submit(move |siv, action: &RatingAction| {
+        siv.pop_layer();
+        let mut track = track.clone();
+        match action {
+            RatingAction::Rate(stars) => track.rating = *stars,
+            RatingAction::ToggleLoved => track.loved = !track.loved,
+            RatingAction::ToggleWriteTags => {
+                rating::set_write_tags(!rating::get_write_tags());
+                return;
+            }
+        }
+        if let Err(e) = rating::save_rating(&track) {
+            siv.add_layer(Dialog::info(e));
+        }
+    });
+
+    let title = track.title.clone().unwrap_or("Rating".to_string());
+    let wrapped = OnEventView::new(sv).on_pre_event(Key::Esc, |siv| {
+        siv.pop_layer();
+    });
+    siv.add_layer(Panel::new(wrapped).title(title));
+}
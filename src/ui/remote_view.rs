@@ -0,0 +1,199 @@
+//! The UI of `musicom attach`, a remote control for a musicom daemon. It only shows the status
+//! and the queue, and the playback keys send commands to the control socket. The library views
+//! need the player in the same process, so they aren't available here.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration as StdDuration;
+
+use chrono::Duration;
+use cursive::event::{EventResult, EventTrigger};
+use cursive::traits::*;
+use cursive::view::ViewWrapper;
+use cursive::views::{Dialog, LinearLayout, Panel, SelectView, TextContent, TextView};
+use cursive::wrap_impl;
+use cursive::Cursive;
+use serde_json::Value;
+
+use super::help_view::{self, ViewHelp};
+use super::SEEK_STEP_SECS;
+use crate::control::{self, Command, SeekTarget};
+use crate::keymap::{self, Action, KeyPress};
+
+/// How often the status and the queue are fetched from the daemon
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// Only these actions can be sent to the daemon
+const HELP: ViewHelp = ViewHelp {
+    title: "musicom attach",
+    actions: &[
+        Action::TogglePlay,
+        Action::PlayPrevious,
+        Action::PlayNext,
+        Action::SeekBackward,
+        Action::SeekForward,
+        Action::Help,
+    ],
+    keys: &[
+        (&["Enter"], "Play the selected song"),
+        (&["C-c"], "Detach, the daemon keeps playing"),
+    ],
+    global_keys: false,
+};
+
+pub struct RemoteView {
+    socket_path: PathBuf,
+    status: TextContent,
+    linear_layout: LinearLayout,
+}
+
+impl ViewWrapper for RemoteView {
+    wrap_impl!(self.linear_layout: LinearLayout);
+}
+
+/// Send the command for an action that no view took to the daemon
+fn run_action(siv: &mut Cursive, socket_path: &Path, action: Action) {
+    let command = match action {
+        Action::TogglePlay => Command::Toggle,
+        Action::PlayPrevious => Command::Prev,
+        Action::PlayNext => Command::Next,
+        Action::SeekBackward => {
+            Command::Seek(SeekTarget::Relative(Duration::seconds(-SEEK_STEP_SECS)))
+        }
+        Action::SeekForward => {
+            Command::Seek(SeekTarget::Relative(Duration::seconds(SEEK_STEP_SECS)))
+        }
+        Action::Help => return help_view::show_help(siv, Some(&HELP)),
+        _ => return,
+    };
+    send(siv, socket_path, &command);
+}
+
+fn send(siv: &mut Cursive, socket_path: &Path, command: &Command) {
+    if let Err(e) = control::send_command(socket_path, command) {
+        siv.add_layer(Dialog::info(e));
+    }
+}
+
+impl RemoteView {
+    fn setup_poller(&self, siv: &Cursive) {
+        let cb_sink = siv.cb_sink().clone();
+        let socket_path = self.socket_path.clone();
+
+        thread::spawn(move || loop {
+            let update = control::send_command(&socket_path, &Command::Status).and_then(|status| {
+                control::send_command(&socket_path, &Command::Queue).map(|queue| (status, queue))
+            });
+            let sent = cb_sink.send(Box::new(move |siv| {
+                siv.call_on_name("remote_view", |view: &mut RemoteView| {
+                    view.refresh_view(update)
+                });
+            }));
+            // The UI is gone
+            if sent.is_err() {
+                return;
+            }
+            thread::sleep(StdDuration::from_millis(POLL_INTERVAL_MS));
+        });
+    }
+
+    pub fn new(siv: &Cursive, socket_path: PathBuf) -> impl View {
+        let status = TextContent::new("");
+        let mut queue = SelectView::<usize>::new();
+        let submit_path = socket_path.clone();
+        queue.set_on_submit(move |siv, idx: &usize| {
+            send(siv, &submit_path, &Command::PlayAt(*idx));
+        });
+
+        let linear_layout = LinearLayout::vertical()
+            .child(
+                Panel::new(queue.with_name("remote_queue").scrollable())
+                    .title(format!("Queue of musicom at {}", socket_path.display()))
+                    .full_height(),
+            )
+            .child(TextView::new_with_content(status.clone()).fixed_height(1));
+
+        let rv = RemoteView {
+            socket_path,
+            status,
+            linear_layout,
+        };
+        rv.setup_poller(siv);
+
+        rv.with_name("remote_view")
+    }
+
+    fn refresh_view(&mut self, update: Result<(Value, Value), String>) {
+        let (status, queue) = match update {
+            Ok(update) => update,
+            Err(e) => {
+                self.status
+                    .set_content(format!("Lost the connection: {}", e));
+                return;
+            }
+        };
+        self.status
+            .set_content(control::format_status(&status["status"]));
+
+        let position = queue["queue_position"].as_u64().map(|idx| idx as usize);
+        let songs = queue["queue"]
+            .as_array()
+            .map(|songs| {
+                songs
+                    .iter()
+                    .map(|song| {
+                        let idx = song["index"].as_u64().unwrap_or_default() as usize;
+                        let name = control::format_song(song);
+                        if position == Some(idx) {
+                            (format!("-- {} --", name), idx)
+                        } else {
+                            (name, idx)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        self.linear_layout
+            .call_on_name("remote_queue", |queue: &mut SelectView<usize>| {
+                // Only rebuild the list when it changed, so the selection stays put
+                let unchanged = queue.len() == songs.len()
+                    && queue
+                        .iter()
+                        .zip(songs.iter())
+                        .all(|((label, idx), (name, song_idx))| label == name && idx == song_idx);
+                if unchanged {
+                    return;
+                }
+                let selected = queue.selected_id();
+                queue.clear();
+                queue.add_all(songs);
+                if let Some(selected) = selected.filter(|&selected| selected < queue.len()) {
+                    queue.set_selection(selected);
+                }
+            });
+    }
+}
+
+/// Run the UI for the daemon listening on the socket, until the user detaches.
+pub fn run_attached(socket_path: PathBuf) -> io::Result<()> {
+    let mut siv = cursive::default();
+    let remote_view = RemoteView::new(&siv, socket_path.clone());
+    siv.add_fullscreen_layer(remote_view.full_screen());
+
+    siv.set_on_event_inner(EventTrigger::any(), move |event| {
+        match keymap::press(event) {
+            KeyPress::Action(action) if HELP.actions.contains(&action) => {
+                let socket_path = socket_path.clone();
+                Some(EventResult::with_cb(move |siv| {
+                    run_action(siv, &socket_path, action)
+                }))
+            }
+            KeyPress::Pending => Some(EventResult::Consumed(None)),
+            _ => None,
+        }
+    });
+    siv.run();
+    Ok(())
+}
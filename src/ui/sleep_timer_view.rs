@@ -0,0 +1,55 @@
+use chrono::Duration;
+
+use cursive::event::Key;
+use cursive::views::{OnEventView, Panel, SelectView};
+use cursive::Cursive;
+
+use crate::player::{PlayerHdl, SleepTimer};
+
+enum SleepChoice {
+    Minutes(i64, bool),
+    Tracks(usize),
+    Cancel,
+}
+
+pub fn show_sleep_timer_chooser(siv: &mut Cursive) {
+    let mut select_view = SelectView::new();
+    for &minutes in [15, 30, 45, 60, 90].iter() {
+        select_view.add_item(
+            format!("Stop in {} minutes", minutes),
+            SleepChoice::Minutes(minutes, false),
+        );
+        select_view.add_item(
+            format!("Fade out in {} minutes", minutes),
+            SleepChoice::Minutes(minutes, true),
+        );
+    }
+    for &tracks in [1, 3, 5, 10].iter() {
+        let name = if tracks == 1 {
+            "Stop after this song".to_string()
+        } else {
+            format!("Stop after {} songs", tracks)
+        };
+        select_view.add_item(name, SleepChoice::Tracks(tracks));
+    }
+    if PlayerHdl::new().get_sleep().get_timer().is_some() {
+        select_view.add_item("Cancel the sleep timer", SleepChoice::Cancel);
+    }
+
+    select_view.set_on_submit(|siv, choice| {
+        let timer = match *choice {
+            SleepChoice::Minutes(minutes, fade_out) => {
+                Some(SleepTimer::after(Duration::minutes(minutes), fade_out))
+            }
+            SleepChoice::Tracks(tracks) => Some(SleepTimer::Tracks(tracks)),
+            SleepChoice::Cancel => None,
+        };
+        PlayerHdl::new().set_sleep_timer(timer);
+        siv.pop_layer();
+    });
+
+    let wrapped = OnEventView::new(select_view).on_pre_event(Key::Esc, |siv| {
+        siv.pop_layer();
+    });
+    siv.add_layer(Panel::new(wrapped).title("Sleep timer"));
+}
@@ -0,0 +1,198 @@
+use chrono::Duration;
+
+use cursive::direction::Direction;
+use cursive::event::{Event, EventResult};
+use cursive::traits::{Nameable, Resizable, Scrollable};
+use cursive::view::{View, ViewWrapper};
+use cursive::views::TextView;
+
+use crate::keymap::{self, Action};
+use crate::library::history::{self, StatsGroup, StatsPeriod, TrackFilter, TrackOrder};
+use crate::ui::help_view::{self, ViewHelp};
+
+const HELP: ViewHelp = ViewHelp {
+    title: "Statistics",
+    actions: &[
+        Action::PreviousStatsPeriod,
+        Action::NextStatsPeriod,
+        Action::ShowStatsWeek,
+        Action::ShowStatsMonth,
+        Action::ShowStatsYear,
+        Action::ShowStatsAllTime,
+        Action::RefreshStats,
+    ],
+    keys: &[],
+    global_keys: true,
+};
+
+/// How many entries the top lists show
+const TOP_COUNT: usize = 10;
+/// Width of the bars for the listening time per hour
+const BAR_WIDTH: usize = 40;
+
+pub struct StatsView {
+    text_view: TextView,
+    period: StatsPeriod,
+}
+
+impl ViewWrapper for StatsView {
+    cursive::wrap_impl!(self.text_view: TextView);
+
+    fn wrap_take_focus(&mut self, _: Direction) -> bool {
+        true
+    }
+
+    fn wrap_on_event(&mut self, e: Event) -> EventResult {
+        match keymap::action_for(&e, HELP.actions) {
+            Some(Action::PreviousStatsPeriod) => self.period = self.period.previous(),
+            Some(Action::NextStatsPeriod) => self.period = self.period.next(),
+            Some(Action::ShowStatsWeek) => self.period = StatsPeriod::Week,
+            Some(Action::ShowStatsMonth) => self.period = StatsPeriod::Month,
+            Some(Action::ShowStatsYear) => self.period = StatsPeriod::Year,
+            Some(Action::ShowStatsAllTime) => self.period = StatsPeriod::AllTime,
+            // Refreshing is left, the view is refreshed for every action
+            Some(_) => (),
+            _ if keymap::action_for(&e, &[Action::Help]).is_some() => {
+                return EventResult::with_cb(|siv| help_view::show_help(siv, Some(&HELP)))
+            }
+            _ => return EventResult::Ignored,
+        }
+
+        self.refresh_view();
+        EventResult::Consumed(None)
+    }
+}
+
+impl StatsView {
+    pub fn new() -> impl View {
+        let mut stats_view = Self {
+            text_view: TextView::new(""),
+            period: StatsPeriod::Month,
+        };
+        stats_view.refresh_view();
+
+        stats_view
+            .with_name("stats_view")
+            .full_screen()
+            .scrollable()
+    }
+
+    fn refresh_view(&mut self) {
+        let since = self.period.since();
+
+        let mut lines = vec![
+            format!("< {} >  (press ? for help)", self.period),
+            format!(
+                "Total listening time: {}",
+                format_listened(history::total_listened(since))
+            ),
+        ];
+
+        for (title, group) in [
+            ("Top artists", StatsGroup::Artist),
+            ("Top albums", StatsGroup::Album),
+        ]
+        .iter()
+        {
+            lines.push(String::new());
+            lines.push(title.to_string());
+            let groups = history::top_groups(*group, since, TOP_COUNT);
+            if groups.is_empty() {
+                lines.push("  Nothing played yet".to_string());
+            }
+            for (idx, group) in groups.iter().enumerate() {
+                lines.push(format!(
+                    "{:>3}. {} - {} plays, {}",
+                    idx + 1,
+                    group.name,
+                    group.play_count,
+                    format_listened(group.listened)
+                ));
+            }
+        }
+
+        let top_tracks = TrackFilter {
+            since,
+            min_play_count: Some(1),
+            limit: Some(TOP_COUNT),
+            ..Default::default()
+        };
+        let most_skipped = TrackFilter {
+            since,
+            min_skip_count: Some(1),
+            limit: Some(TOP_COUNT),
+            ..Default::default()
+        };
+        let never_played = TrackFilter {
+            since,
+            never_played: true,
+            limit: Some(TOP_COUNT),
+            ..Default::default()
+        };
+        for (title, order, filter, show_skips) in [
+            ("Top tracks", TrackOrder::PlayCount, top_tracks, false),
+            (
+                "Most skipped tracks",
+                TrackOrder::SkipCount,
+                most_skipped,
+                true,
+            ),
+            ("Never played", TrackOrder::PlayCount, never_played, false),
+        ]
+        .iter()
+        {
+            lines.push(String::new());
+            lines.push(title.to_string());
+            let tracks = history::query_tracks(*order, filter);
+            if tracks.is_empty() {
+                lines.push("  None".to_string());
+            }
+            for (idx, (track, stats)) in tracks.iter().enumerate() {
+                let name = match (&track.artist, &track.title) {
+                    (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+                    (None, Some(title)) => title.clone(),
+                    _ => track.path.to_string_lossy().to_string(),
+                };
+                let count = if *show_skips {
+                    format!(" - {} skips", stats.skip_count)
+                } else if stats.play_count > 0 {
+                    format!(" - {} plays", stats.play_count)
+                } else {
+                    String::new()
+                };
+                lines.push(format!("{:>3}. {}{}", idx + 1, name, count));
+            }
+        }
+
+        lines.push(String::new());
+        lines.push("Listening by hour of day".to_string());
+        let by_hour = history::listening_by_hour(since);
+        let max = by_hour
+            .iter()
+            .map(|listened| listened.num_seconds())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        for (hour, listened) in by_hour.iter().enumerate() {
+            let filled = (listened.num_seconds() * BAR_WIDTH as i64 / max) as usize;
+            lines.push(format!(
+                "  {:02}:00 {:<width$} {}",
+                hour,
+                "=".repeat(filled),
+                format_listened(*listened),
+                width = BAR_WIDTH
+            ));
+        }
+
+        self.text_view.set_content(lines.join("\n"));
+    }
+}
+
+fn format_listened(listened: Duration) -> String {
+    let minutes = listened.num_minutes();
+    if minutes >= 60 {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
@@ -1,3 +1,8 @@
+use std::fs::{self, DirBuilder, Permissions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
 use directories::ProjectDirs;
 
 pub type NotifierCb = Box<dyn Fn() + Send + Sync + 'static>;
@@ -27,3 +32,29 @@ impl Notifier {
 pub fn get_project_dirs() -> ProjectDirs {
     ProjectDirs::from("com.jonesnl", "Nate Jones", "Musicom").unwrap()
 }
+
+/// Replace `~` at the start of a path with the home directory
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), directories::UserDirs::new()) {
+        (Ok(rest), Some(user_dirs)) => user_dirs.home_dir().join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Where the control socket and the instance lock live, they only matter while musicom runs
+pub fn get_runtime_dir() -> PathBuf {
+    get_project_dirs()
+        .runtime_dir()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| std::env::temp_dir().join("musicom"))
+}
+
+/// Create the runtime directory so that only this user can get in. Without `XDG_RUNTIME_DIR` it
+/// is in the temp directory, where everyone could reach the control socket otherwise.
+pub fn create_runtime_dir() -> io::Result<PathBuf> {
+    let dir = get_runtime_dir();
+    DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    // It may have been there already, this fails if it belongs to someone else
+    fs::set_permissions(&dir, Permissions::from_mode(0o700))?;
+    Ok(dir)
+}